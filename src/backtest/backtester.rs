@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, Result};
+use polars::prelude::{DataFrame, NamedFrom, TakeRandom};
+use polars::df;
+use serde_json::{json, Map, Value};
+
+use crate::client::binance_domain::Kline;
+use crate::crypto::mock_exchange::MockExchange;
+use crate::crypto::paper_book::{PaperBook, PaperOrder};
+use crate::strategy::strategy::StrategyFactory;
+
+#[derive(Debug)]
+pub struct BacktestReport {
+    pub pnl: f64,
+    pub max_drawdown: f64,
+    pub round_trips: usize,
+    pub trades: usize,
+    pub fees: f64,
+    /// Columns `time`, `price`, `position` and `equity`, one row per candle.
+    pub equity_curve: DataFrame,
+}
+
+impl BacktestReport {
+    /// The report as JSON, with the equity curve as one object per candle.
+    pub fn to_map(&self) -> Result<Map<String, Value>> {
+        let curve = &self.equity_curve;
+        let times = curve.column("time")?.i64()?;
+        let prices = curve.column("price")?.f64()?;
+        let positions = curve.column("position")?.f64()?;
+        let equity = curve.column("equity")?.f64()?;
+        let points: Vec<Value> = (0..curve.height())
+            .map(|i| {
+                json!({
+                    "time": times.get(i),
+                    "price": prices.get(i),
+                    "position": positions.get(i),
+                    "equity": equity.get(i),
+                })
+            })
+            .collect();
+
+        let mut map = Map::new();
+        map.insert("pnl".to_string(), json!(self.pnl));
+        map.insert("max_drawdown".to_string(), json!(self.max_drawdown));
+        map.insert("round_trips".to_string(), json!(self.round_trips));
+        map.insert("trades".to_string(), json!(self.trades));
+        map.insert("fees".to_string(), json!(self.fees));
+        map.insert("equity_curve".to_string(), Value::Array(points));
+        Ok(map)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Account {
+    cash: f64,
+    position: f64,
+    fees: f64,
+    trades: usize,
+    round_trips: usize,
+}
+
+impl Account {
+    /// Books a fill at its limit price, paying `fee_rate` of the notional in quote.
+    fn apply(&mut self, fill: &PaperOrder, fee_rate: f64) {
+        let notional = fill.price * fill.quantity;
+        let fee = notional * fee_rate;
+        let signed_quantity = match fill.side.as_str() {
+            "BUY" => fill.quantity,
+            _ => -fill.quantity,
+        };
+        // A fill against the current position closes (part of) a round trip.
+        if self.position * signed_quantity < 0.0 {
+            self.round_trips += 1;
+        }
+        self.cash -= signed_quantity * fill.price + fee;
+        self.position += signed_quantity;
+        self.fees += fee;
+        self.trades += 1;
+    }
+
+    fn equity(&self, price: f64) -> f64 {
+        self.cash + self.position * price
+    }
+}
+
+/// Largest peak-to-trough fall of the equity curve, as a fraction of the peak.
+fn max_drawdown(equity: &[f64]) -> f64 {
+    let mut peak = f64::MIN;
+    let mut drawdown: f64 = 0.0;
+    for value in equity {
+        peak = peak.max(*value);
+        if peak > 0.0 {
+            drawdown = drawdown.max((peak - value) / peak);
+        }
+    }
+    drawdown
+}
+
+/// Replays klines through a strategy against a simulated exchange that fills a limit order
+/// when a later candle's high/low crosses its price.
+pub struct Backtester {
+    initial_cash: f64,
+    fee_rate: f64,
+}
+
+impl Backtester {
+    pub fn new(initial_cash: f64, fee_rate: f64) -> Self {
+        Backtester {
+            initial_cash,
+            fee_rate,
+        }
+    }
+
+    pub async fn run(
+        &self,
+        strategy: &str,
+        params: HashMap<String, String>,
+        klines: &[Kline],
+    ) -> Result<BacktestReport> {
+        let symbol = params
+            .get("symbol")
+            .cloned()
+            .ok_or_else(|| anyhow!("symbol param is required"))?;
+        let book = Arc::new(Mutex::new(PaperBook::replay()));
+        let exchange = Arc::new(MockExchange::with_book(book.clone()));
+        let strategy = StrategyFactory::new().create_strategy_with_exchange(strategy, params, exchange);
+
+        let mut account = Account {
+            cash: self.initial_cash,
+            ..Account::default()
+        };
+        let mut times: Vec<i64> = Vec::with_capacity(klines.len());
+        let mut prices: Vec<f64> = Vec::with_capacity(klines.len());
+        let mut positions: Vec<f64> = Vec::with_capacity(klines.len());
+        let mut equity: Vec<f64> = Vec::with_capacity(klines.len());
+
+        for kline in klines {
+            let fills = book.lock().unwrap().push_candle(&symbol, kline);
+            for fill in fills.iter() {
+                account.apply(fill, self.fee_rate);
+            }
+            strategy.excute().await;
+
+            let close: f64 = kline.close.parse()?;
+            times.push(kline.close_time);
+            prices.push(close);
+            positions.push(account.position);
+            equity.push(account.equity(close));
+        }
+
+        let pnl = equity.last().map(|last| last - self.initial_cash).unwrap_or(0.0);
+        let max_drawdown = max_drawdown(&equity);
+        let equity_curve = df!("time" => times,
+                               "price" => prices,
+                               "position" => positions,
+                               "equity" => equity)?;
+
+        Ok(BacktestReport {
+            pnl,
+            max_drawdown,
+            round_trips: account.round_trips,
+            trades: account.trades,
+            fees: account.fees,
+            equity_curve,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::client::binance_domain::Kline;
+    use crate::crypto::paper_book::PaperOrder;
+
+    use super::{max_drawdown, Account, Backtester};
+
+    fn kline(time: i64, high: &str, low: &str, close: &str) -> Kline {
+        Kline {
+            open_time: time,
+            open: close.to_string(),
+            high: high.to_string(),
+            low: low.to_string(),
+            close: close.to_string(),
+            volume: "0".to_string(),
+            close_time: time + 59_999,
+            quote_asset_volume: "0".to_string(),
+            number_of_trades: 0,
+            taker_buy_base_asset_volume: "0".to_string(),
+            taker_buy_quote_asset_volume: "0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_max_drawdown() {
+        assert_eq!(max_drawdown(&[100.0, 120.0, 90.0, 130.0]), 0.25);
+        assert_eq!(max_drawdown(&[]), 0.0);
+    }
+
+    #[test]
+    fn test_account_round_trip() {
+        let mut account = Account {
+            cash: 1000.0,
+            ..Account::default()
+        };
+        let mut buy = PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 100.0, 1.0, "buy");
+        buy.status = "FILLED".to_string();
+        let mut sell = PaperOrder::new("BTCFDUSD", "SELL", "LIMIT", 110.0, 1.0, "sell");
+        sell.status = "FILLED".to_string();
+
+        account.apply(&buy, 0.001);
+        account.apply(&sell, 0.001);
+
+        assert_eq!(account.round_trips, 1);
+        assert_eq!(account.trades, 2);
+        assert_eq!(account.position, 0.0);
+        assert!((account.fees - 0.21).abs() < 1e-9);
+        assert!((account.equity(0.0) - 1009.79).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_run_grid() {
+        let mut params = HashMap::new();
+        params.insert("gap".to_string(), "0.005".to_string());
+        params.insert("quantity".to_string(), "1".to_string());
+        params.insert("symbol".to_string(), "BTCFDUSD".to_string());
+        let klines = vec![
+            kline(0, "100.01", "100.01", "100.01"),
+            kline(60_000, "100.02", "99.01", "99.02"),
+            kline(120_000, "101.01", "99.02", "100.99"),
+            kline(180_000, "101.01", "99.01", "99.51"),
+        ];
+
+        let report = Backtester::new(1000.0, 0.001)
+            .run("Grid", params, &klines)
+            .await
+            .unwrap();
+
+        assert_eq!(report.equity_curve.height(), klines.len());
+        assert!(report.trades >= 2);
+        assert!(report.round_trips >= 1);
+        assert!(report.fees > 0.0);
+        println!("{:?}", report);
+
+        let map = report.to_map().unwrap();
+        let curve = map["equity_curve"].as_array().unwrap();
+        assert_eq!(curve.len(), klines.len());
+        assert_eq!(curve[1]["time"], 119_999);
+        assert_eq!(curve[1]["price"], 99.02);
+        assert_eq!(map["trades"], report.trades);
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs::File;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use polars::prelude::{CsvReader, DataFrame, DataType, ParquetReader, SerReader};
+use serde::Deserialize;
+
+use crate::client::binance_domain::Kline;
+use crate::crypto::crypto_client::CryptoClient;
+use crate::crypto::futures_client::FutureClient;
+
+/// Where a backtest reads its candles from. In a request body it is one key naming the source,
+/// e.g. `{"spot": {"symbol": "BTCFDUSD", "interval": "1m"}}` or `{"parquet": "klines.parquet"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KlineSource {
+    Spot {
+        symbol: String,
+        interval: String,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    },
+    Future {
+        symbol: String,
+        interval: String,
+        limit: Option<u16>,
+        start_time: Option<u64>,
+        end_time: Option<u64>,
+    },
+    /// CSV with a header row and at least `open_time,open,high,low,close` columns, the same
+    /// layout as Binance's kline dumps; `volume` and `close_time` are optional.
+    Csv(PathBuf),
+    /// Parquet file with the same columns as `Csv`.
+    Parquet(PathBuf),
+}
+
+impl KlineSource {
+    pub async fn load(&self) -> Result<Vec<Kline>> {
+        match self {
+            KlineSource::Spot {
+                symbol,
+                interval,
+                limit,
+                start_time,
+                end_time,
+            } => Ok(CryptoClient::new().klines(symbol, interval, *limit, *start_time, *end_time).await?),
+            KlineSource::Future {
+                symbol,
+                interval,
+                limit,
+                start_time,
+                end_time,
+            } => Ok(FutureClient::new().kline(symbol, interval, *limit, *start_time, *end_time).await?),
+            KlineSource::Csv(path) => {
+                let df = CsvReader::from_path(path)?.has_header(true).finish()?;
+                klines_from_frame(&df)
+            }
+            KlineSource::Parquet(path) => {
+                let df = ParquetReader::new(File::open(path)?).finish()?;
+                klines_from_frame(&df)
+            }
+        }
+    }
+}
+
+fn f64_column(df: &DataFrame, name: &str) -> Result<Vec<f64>> {
+    let column = df.column(name)?.cast(&DataType::Float64)?;
+    let values = column.f64()?.into_iter().map(|value| value.unwrap_or(0.0)).collect();
+    Ok(values)
+}
+
+fn i64_column(df: &DataFrame, name: &str) -> Result<Vec<i64>> {
+    let column = df.column(name)?.cast(&DataType::Int64)?;
+    let values = column.i64()?.into_iter().map(|value| value.unwrap_or(0)).collect();
+    Ok(values)
+}
+
+pub fn klines_from_frame(df: &DataFrame) -> Result<Vec<Kline>> {
+    let open_time = i64_column(df, "open_time")?;
+    let open = f64_column(df, "open")?;
+    let high = f64_column(df, "high")?;
+    let low = f64_column(df, "low")?;
+    let close = f64_column(df, "close")?;
+    let volume = f64_column(df, "volume").unwrap_or(vec![0.0; df.height()]);
+    let close_time = i64_column(df, "close_time").unwrap_or(open_time.clone());
+    if open_time.is_empty() {
+        return Err(anyhow!("no klines found"));
+    }
+
+    Ok((0..df.height())
+        .map(|i| Kline {
+            open_time: open_time[i],
+            open: open[i].to_string(),
+            high: high[i].to_string(),
+            low: low[i].to_string(),
+            close: close[i].to_string(),
+            volume: volume[i].to_string(),
+            close_time: close_time[i],
+            quote_asset_volume: "0".to_string(),
+            number_of_trades: 0,
+            taker_buy_base_asset_volume: "0".to_string(),
+            taker_buy_quote_asset_volume: "0".to_string(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use polars::df;
+    use polars::prelude::{NamedFrom, ParquetWriter};
+
+    use super::KlineSource;
+
+    #[tokio::test]
+    async fn test_load_csv() {
+        let path = env::temp_dir().join("quanpass_klines_test.csv");
+        fs::write(
+            &path,
+            "open_time,open,high,low,close,volume,close_time\n0,100,105,95,101,10,59999\n60000,101,103,99,102.5,8,119999\n",
+        )
+        .unwrap();
+
+        let klines = KlineSource::Csv(path.clone()).load().await.unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(klines.len(), 2);
+        assert_eq!(klines[1].open_time, 60000);
+        assert_eq!(klines[1].close, "102.5");
+        assert_eq!(klines[0].low, "95");
+    }
+
+    #[tokio::test]
+    async fn test_load_parquet() {
+        let path = env::temp_dir().join("quanpass_klines_test.parquet");
+        let mut frame = df!("open_time" => [0i64, 60000],
+                            "open" => [100.0, 101.0],
+                            "high" => [105.0, 103.0],
+                            "low" => [95.0, 99.0],
+                            "close" => [101.0, 102.5])
+        .unwrap();
+        ParquetWriter::new(fs::File::create(&path).unwrap())
+            .finish(&mut frame)
+            .unwrap();
+
+        let klines = KlineSource::Parquet(path.clone()).load().await.unwrap();
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(klines.len(), 2);
+        assert_eq!(klines[1].open_time, 60000);
+        assert_eq!(klines[1].close, "102.5");
+        assert_eq!(klines[1].close_time, 60000);
+        assert_eq!(klines[0].volume, "0");
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let source: KlineSource = serde_json::from_str(r#"{"spot": {"symbol": "BTCFDUSD", "interval": "1m"}}"#).unwrap();
+        assert!(matches!(source, KlineSource::Spot { limit: None, .. }));
+        let source: KlineSource = serde_json::from_str(r#"{"parquet": "klines.parquet"}"#).unwrap();
+        assert!(matches!(source, KlineSource::Parquet(_)));
+    }
+}
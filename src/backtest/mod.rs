@@ -0,0 +1,71 @@
+use std::collections::HashMap;
+
+use salvo::{handler, http::StatusCode, prelude::Json, Request, Response};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::robot::param_strings;
+use crate::strategy::strategy::StrategyFactory;
+
+use self::backtester::Backtester;
+use self::kline_source::KlineSource;
+
+pub mod backtester;
+pub mod kline_source;
+
+/// Body of `POST /backtest`.
+#[derive(Debug, Deserialize)]
+pub struct RunBacktest {
+    label: String,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+    source: KlineSource,
+    #[serde(default = "default_initial_cash")]
+    initial_cash: f64,
+    #[serde(default = "default_fee_rate")]
+    fee_rate: f64,
+}
+
+fn default_initial_cash() -> f64 {
+    10_000.0
+}
+
+fn default_fee_rate() -> f64 {
+    0.001
+}
+
+fn reply(res: &mut Response, status: StatusCode, message: String) {
+    res.status_code(status);
+    res.render(message);
+}
+
+/// Replays the candles of `source` through a strategy and answers with the report.
+#[handler]
+pub async fn run_backtest(req: &mut Request, res: &mut Response) {
+    let body: RunBacktest = match req.parse_json().await {
+        Ok(body) => body,
+        Err(err) => return reply(res, StatusCode::BAD_REQUEST, format!("invalid body: {}", err)),
+    };
+    let params = match param_strings(body.params) {
+        Ok(params) => params,
+        Err(err) => return reply(res, StatusCode::UNPROCESSABLE_ENTITY, err),
+    };
+    let params = match StrategyFactory::new().validate(&body.label, &params) {
+        Ok(params) => params,
+        Err(err) => return reply(res, StatusCode::UNPROCESSABLE_ENTITY, err.to_string()),
+    };
+    let klines = match body.source.load().await {
+        Ok(klines) => klines,
+        Err(err) => {
+            return reply(res, StatusCode::UNPROCESSABLE_ENTITY, format!("failed to load klines: {}", err))
+        }
+    };
+    let report = Backtester::new(body.initial_cash, body.fee_rate)
+        .run(&body.label, params, &klines)
+        .await
+        .and_then(|report| report.to_map());
+    match report {
+        Ok(report) => res.render(Json(report)),
+        Err(err) => reply(res, StatusCode::INTERNAL_SERVER_ERROR, format!("backtest failed: {}", err)),
+    }
+}
@@ -1,68 +1,99 @@
-use std::{
-    collections::BTreeMap,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{collections::BTreeMap, time::Duration};
 
-use anyhow::bail;
 use hex::encode as hex_encode;
 use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use rand::{thread_rng, Rng};
 use reqwest::{
-    blocking::Response,
-    header::{HeaderMap, HeaderName, HeaderValue},
-    StatusCode,
+    header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    Method, RequestBuilder, Response, StatusCode,
 };
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use sha2::Sha256;
 
+use crate::client::{rate_limiter, server_clock};
+use crate::config::app_config::{AccountConfig, AppConfig, DEFAULT_ACCOUNT};
+use crate::util::binance_error::{BinanceError, Result};
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 struct ServerTime {
     server_time: i64,
 }
 
+/// First retry delay, doubled on each further attempt.
+const RETRY_BASE: Duration = Duration::from_millis(200);
+const RETRY_MAX: Duration = Duration::from_secs(5);
+
+/// Connection pool shared by every client, whatever its host or account.
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    let config = &AppConfig::get().http;
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_millis(config.connect_timeout_ms))
+        .pool_max_idle_per_host(config.max_idle_per_host)
+        .build()
+        .unwrap()
+});
+
 #[derive(Clone)]
 pub struct BinanceClient {
+    account: String,
     api_key: String,
     secret_key: String,
     host: String,
-    inner_client: reqwest::blocking::Client,
+    inner_client: reqwest::Client,
+    /// Applied to each request on its own, so a slow call never holds up other robots.
+    timeout: Duration,
+    max_retries: u32,
+}
+
+/// Futures hosts serve `/fapi` endpoints, spot hosts `/api`.
+pub fn is_futures_host(host: &str) -> bool {
+    host.contains("fapi") || host.contains("binancefuture")
+}
+
+/// Delay before retry number `attempt` (from 1): exponential, with a random half of it taken
+/// off so clients failing together do not retry together.
+pub fn backoff(attempt: u32) -> Duration {
+    let delay = RETRY_BASE
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(RETRY_MAX);
+    delay.mul_f64(thread_rng().gen_range(0.5..=1.0))
 }
 
 impl BinanceClient {
     pub fn new() -> Self {
-        BinanceClient {
-            api_key: String::from(
-                "uzmfZmBb2jlmCNi5O9hp27o0CJxa5v42Lec3kVvFkXSPOUl9r8qa3CEFBhAkQThP",
-            ),
-            secret_key: String::from(
-                "LJjVjovJ1oaGowqPE0dRQBFIH9NIxI14Fsq4RTi3NWjFWHQf3yZaEMnkqywW8FIB",
-            ),
-            host: String::from("https://api.binance.com"),
-            inner_client: reqwest::blocking::Client::builder()
-                .pool_idle_timeout(None)
-                .build()
-                .unwrap(),
-        }
+        BinanceClient::new_with_account(DEFAULT_ACCOUNT, "https://api.binance.com")
     }
 
     pub fn new_with_host(host: &str) -> Self {
+        BinanceClient::new_with_account(DEFAULT_ACCOUNT, host)
+    }
+
+    pub fn new_with_account(account: &str, host: &str) -> Self {
+        let credentials = match AppConfig::get().account(account) {
+            Some(credentials) => credentials.clone(),
+            None => {
+                println!("No credentials configured for account {}", account);
+                AccountConfig::default()
+            }
+        };
         BinanceClient {
-            api_key: String::from(
-                "uzmfZmBb2jlmCNi5O9hp27o0CJxa5v42Lec3kVvFkXSPOUl9r8qa3CEFBhAkQThP",
-            ),
-            secret_key: String::from(
-                "LJjVjovJ1oaGowqPE0dRQBFIH9NIxI14Fsq4RTi3NWjFWHQf3yZaEMnkqywW8FIB",
-            ),
-            inner_client: reqwest::blocking::Client::builder()
-                .pool_idle_timeout(None)
-                .build()
-                .unwrap(),
+            account: account.to_string(),
+            api_key: credentials.api_key,
+            secret_key: credentials.secret_key,
+            inner_client: HTTP_CLIENT.clone(),
             host: host.to_string(),
-            // ... initialize other fields ...
+            timeout: Duration::from_millis(AppConfig::get().http.timeout_ms),
+            max_retries: AppConfig::get().http.max_retries,
         }
     }
 
-    fn build_headers(&self, content_type: bool) -> Result<HeaderMap, anyhow::Error> {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn build_headers(&self, content_type: bool) -> Result<HeaderMap> {
         let mut custom_headers = HeaderMap::new();
 
         custom_headers.insert("user-agent", HeaderValue::from_static("binance-rs"));
@@ -74,7 +105,10 @@ impl BinanceClient {
         }
         custom_headers.insert(
             HeaderName::from_static("x-mbx-apikey"),
-            HeaderValue::from_str(self.api_key.as_str())?,
+            HeaderValue::from_str(self.api_key.as_str()).map_err(|err| BinanceError::Auth {
+                code: 0,
+                msg: err.to_string(),
+            })?,
         );
 
         Ok(custom_headers)
@@ -90,122 +124,140 @@ impl BinanceClient {
         request
     }
 
-    fn get_timestamp(&self, start: SystemTime) -> u64 {
-        let since_epoch = start.duration_since(UNIX_EPOCH).unwrap();
-        since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_nanos()) / 1_000_000
+    /// Current server time of the host, in milliseconds.
+    pub async fn server_time(&self) -> Result<i64> {
+        let endpoint = if is_futures_host(&self.host) { "/fapi/v1/time" } else { "/api/v3/time" };
+        let server_time: ServerTime = self.get(endpoint, None).await?;
+        Ok(server_time.server_time)
     }
 
-    pub fn build_signed_request(&self, mut parameters: BTreeMap<String, String>) -> String {
-        let timestamp = self.get_timestamp(SystemTime::now());
-        parameters.insert("timestamp".into(), timestamp.to_string());
-        return self.build_request(parameters);
+    /// Appends `recvWindow` and a `timestamp` on the host's clock (see `server_clock`) to the
+    /// query, then signs it.
+    fn sign_request(&self, endpoint: &str, request: Option<String>) -> String {
+        let mut request = request.unwrap_or_default();
+        if !request.is_empty() {
+            request.push('&');
+        }
+        request.push_str(&format!(
+            "recvWindow={}&timestamp={}",
+            server_clock::recv_window(&self.host),
+            server_clock::timestamp(&self.host)
+        ));
+        let mut signed_key = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
+        signed_key.update(request.as_bytes());
+        let signature = hex_encode(signed_key.finalize().into_bytes());
+        format!("{}{}?{}&signature={}", self.host, endpoint, request, signature)
     }
 
-    pub fn build_future_signed_request(
+    /// Signs the request anew on each attempt, so retries carry a fresh timestamp. A timestamp
+    /// Binance rejects re-samples the host's clock before the request is sent once more.
+    async fn send_signed<T: DeserializeOwned>(
         &self,
-        parameters: BTreeMap<String, String>,
-        recv_window: u64,
-    ) -> String {
-        {
-            let mut parameters = parameters;
-            if recv_window > 0 {
-                parameters.insert("recvWindow".into(), recv_window.to_string());
+        method: Method,
+        endpoint: &str,
+        request: Option<String>,
+    ) -> Result<T> {
+        server_clock::ensure_sampled(self).await;
+        let build = || {
+            let url = self.sign_request(endpoint, request.clone());
+            Ok(self
+                .inner_client
+                .request(method.clone(), url.as_str())
+                .headers(self.build_headers(true)?))
+        };
+        match self.send(build).await {
+            Err(err) if err.is_timestamp_rejected() => {
+                println!("{} rejected the request timestamp: {}", self.host, err);
+                if server_clock::sample(self).await.is_err() {
+                    return Err(err);
+                }
+                self.send(build).await
             }
-            let server_time: ServerTime = self.get("/fapi/v1/time", None).unwrap();
-            let timestamp = server_time.server_time as u64;
-
-            parameters.insert("timestamp".into(), timestamp.to_string());
-            self.build_request(parameters)
+            result => result,
         }
     }
 
-    fn sign_request(&self, endpoint: &str, request: Option<String>) -> String {
-        if let Some(request) = request {
-            let mut signed_key =
-                Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-            signed_key.update(request.as_bytes());
-            let signature = hex_encode(signed_key.finalize().into_bytes());
-            let request_body: String = format!("{}&signature={}", request, signature);
-            format!("{}{}?{}", self.host, String::from(endpoint), request_body)
-        } else {
-            let signed_key = Hmac::<Sha256>::new_from_slice(self.secret_key.as_bytes()).unwrap();
-            let signature = hex_encode(signed_key.finalize().into_bytes());
-            let request_body: String = format!("&signature={}", signature);
-            format!("{}{}?{}", self.host, String::from(endpoint), request_body)
+    /// Sends the request `build` makes once it fits under the host's and account's rate limits
+    /// (see `rate_limiter`), then records the usage Binance reports back. Reads are retried with
+    /// backoff after any transient failure; other requests only when the connection never
+    /// opened, since they may have been executed otherwise.
+    async fn send<T, F>(&self, build: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn() -> Result<RequestBuilder>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            let request = build()?.timeout(self.timeout).build()?;
+            let url = request.url();
+            let weight = rate_limiter::request_weight(request.method(), url.path(), url.query().unwrap_or(""));
+            let order = rate_limiter::is_order(request.method(), url.path());
+            let idempotent = request.method() == Method::GET;
+            rate_limiter::acquire(&self.host, &self.account, weight, order).await?;
+
+            let (result, sent) = match self.inner_client.execute(request).await {
+                Ok(response) => {
+                    rate_limiter::record(&self.host, &self.account, response.headers());
+                    (self.handler(response).await, true)
+                }
+                Err(err) => (Err(BinanceError::from(&err)), !err.is_connect()),
+            };
+            match result {
+                Err(err) if attempt <= self.max_retries && (!sent || idempotent && err.is_transient()) => {
+                    println!("Retrying {} after {}", self.host, err);
+                    tokio::time::sleep(backoff(attempt)).await;
+                }
+                result => return result,
+            }
         }
     }
 
-    fn handler<T: DeserializeOwned>(&self, response: Response) -> Result<T, anyhow::Error> {
-        match response.status() {
-            StatusCode::OK => Ok(response.json::<T>()?),
-            StatusCode::INTERNAL_SERVER_ERROR => {
-                bail!("Internal Server Error");
-            }
-            StatusCode::SERVICE_UNAVAILABLE => {
-                bail!("Service Unavailable");
-            }
-            StatusCode::UNAUTHORIZED => {
-                bail!("Unauthorized");
-            }
-            s => {
-                bail!(format!(
-                    "Code:{:?}, Received mesaage: {:?}",
-                    s,
-                    response.text()
-                ));
-            }
+    async fn handler<T: DeserializeOwned>(&self, response: Response) -> Result<T> {
+        let status = response.status();
+        if status == StatusCode::OK {
+            return Ok(response.json::<T>().await?);
         }
+        let retry_after = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        if let (StatusCode::TOO_MANY_REQUESTS | StatusCode::IM_A_TEAPOT, Some(retry_after)) = (status, retry_after) {
+            rate_limiter::ban(&self.host, retry_after);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(BinanceError::from_response(status.as_u16(), retry_after, &body))
     }
 
-    pub fn get_signed<T: DeserializeOwned>(
+    pub async fn get_signed<T: DeserializeOwned>(
         &self,
         endpoint: &str,
         request: Option<String>,
-    ) -> Result<T, anyhow::Error> {
-        let url = self.sign_request(endpoint, request);
-        let client = &self.inner_client;
-        let response = client
-            .get(url.as_str())
-            .headers(self.build_headers(true)?)
-            .send()?;
-
-        self.handler(response)
+    ) -> Result<T> {
+        self.send_signed(Method::GET, endpoint, request).await
     }
 
-    pub fn post_signed<T: DeserializeOwned>(
+    pub async fn post_signed<T: DeserializeOwned>(
         &self,
         endpoint: &str,
         request: Option<String>,
-    ) -> Result<T, anyhow::Error> {
-        let url = self.sign_request(endpoint, request);
-        let client = &self.inner_client;
-        let response = client
-            .post(url.as_str())
-            .headers(self.build_headers(true)?)
-            .send()?;
-
-        self.handler(response)
+    ) -> Result<T> {
+        self.send_signed(Method::POST, endpoint, request).await
     }
 
-    pub fn delete_signed<T: DeserializeOwned>(
+    pub async fn delete_signed<T: DeserializeOwned>(
         &self,
         endpoint: &str,
         request: Option<String>,
-    ) -> Result<T, anyhow::Error> {
-        let url = self.sign_request(endpoint, request);
-        let client = &self.inner_client;
-        let response = client
-            .delete(url.as_str())
-            .headers(self.build_headers(true)?)
-            .send()?;
-
-        self.handler(response)
+    ) -> Result<T> {
+        self.send_signed(Method::DELETE, endpoint, request).await
     }
-    pub fn get<T: DeserializeOwned>(
+    pub async fn get<T: DeserializeOwned>(
         &self,
         endpoint: &str,
         request: Option<String>,
-    ) -> Result<T, anyhow::Error> {
+    ) -> Result<T> {
         let mut url: String = format!("{}{}", self.host, String::from(endpoint));
         if let Some(request) = request {
             if !request.is_empty() {
@@ -213,21 +265,13 @@ impl BinanceClient {
             }
         }
 
-        let client = &self.inner_client;
-        let response = client.get(url.as_str()).send()?;
-
-        self.handler(response)
+        self.send(|| Ok(self.inner_client.get(url.as_str()))).await
     }
 
-    pub fn post<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T, anyhow::Error> {
+    pub async fn post<T: DeserializeOwned>(&self, endpoint: &str) -> Result<T> {
         let url: String = format!("{}{}", self.host, String::from(endpoint));
 
-        let client = &self.inner_client;
-        let response = client
-            .post(url.as_str())
-            .headers(self.build_headers(false)?)
-            .send()?;
-
-        self.handler(response)
+        self.send(|| Ok(self.inner_client.post(url.as_str()).headers(self.build_headers(false)?)))
+            .await
     }
 }
@@ -62,15 +62,246 @@ pub struct Transaction {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct FutureTransaction {
-    order_id: u64,
-    client_order_id: String,
-    symbol: String,
-    status: String,
-    r#type: String,
-    side: String
+    pub order_id: u64,
+    pub client_order_id: String,
+    pub symbol: String,
+    pub status: String,
+    pub r#type: String,
+    pub side: String,
 
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CanceledOrder {
+    pub symbol: String,
+    pub orig_client_order_id: String,
+    pub order_id: u64,
+    pub order_list_id: i64,
+    pub client_order_id: String,
+    #[serde(default)]
+    pub transact_time: u64,
+    #[serde(with = "string_or_float")]
+    pub price: f64,
+    #[serde(with = "string_or_float")]
+    pub orig_qty: f64,
+    #[serde(with = "string_or_float")]
+    pub executed_qty: f64,
+    #[serde(with = "string_or_float")]
+    pub cummulative_quote_qty: f64,
+    pub status: String,
+    pub time_in_force: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub side: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FutureCanceledOrder {
+    pub order_id: i64,
+    pub client_order_id: String,
+    pub symbol: String,
+    pub status: String,
+    #[serde(with = "string_or_float")]
+    pub price: f64,
+    #[serde(with = "string_or_float")]
+    pub orig_qty: f64,
+    #[serde(with = "string_or_float")]
+    pub executed_qty: f64,
+    #[serde(with = "string_or_float", default = "default_stop_price")]
+    pub stop_price: f64,
+    pub time_in_force: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub side: String,
+    pub position_side: String,
+    #[serde(default)]
+    pub reduce_only: bool,
+    pub update_time: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CancelAllResponse {
+    pub code: i64,
+    pub msg: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetBalance {
+    pub asset: String,
+    #[serde(with = "string_or_float")]
+    pub free: f64,
+    #[serde(with = "string_or_float")]
+    pub locked: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountInformation {
+    pub can_trade: bool,
+    pub balances: Vec<AssetBalance>,
+}
+
+/// A fill from `GET /api/v3/myTrades`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Trade {
+    pub symbol: String,
+    pub id: u64,
+    pub order_id: u64,
+    #[serde(with = "string_or_float")]
+    pub price: f64,
+    #[serde(with = "string_or_float")]
+    pub qty: f64,
+    #[serde(with = "string_or_float")]
+    pub quote_qty: f64,
+    #[serde(with = "string_or_float")]
+    pub commission: f64,
+    pub commission_asset: String,
+    pub time: u64,
+    pub is_buyer: bool,
+    pub is_maker: bool,
+}
+
+/// A fill from `GET /fapi/v1/userTrades`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FutureTrade {
+    pub symbol: String,
+    pub id: u64,
+    pub order_id: u64,
+    pub side: String,
+    #[serde(with = "string_or_float")]
+    pub price: f64,
+    #[serde(with = "string_or_float")]
+    pub qty: f64,
+    #[serde(with = "string_or_float")]
+    pub quote_qty: f64,
+    #[serde(with = "string_or_float")]
+    pub commission: f64,
+    pub commission_asset: String,
+    #[serde(with = "string_or_float")]
+    pub realized_pnl: f64,
+    pub maker: bool,
+    pub time: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FutureBalance {
+    pub asset: String,
+    #[serde(with = "string_or_float")]
+    pub balance: f64,
+    #[serde(with = "string_or_float")]
+    pub available_balance: f64,
+}
+
+/// A position from `GET /fapi/v2/positionRisk`. One-way mode reports a single `BOTH` entry per
+/// symbol, hedge mode a `LONG` and a `SHORT` one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionRisk {
+    pub symbol: String,
+    #[serde(with = "string_or_float")]
+    pub position_amt: f64,
+    #[serde(with = "string_or_float")]
+    pub entry_price: f64,
+    #[serde(with = "string_or_float")]
+    pub mark_price: f64,
+    #[serde(with = "string_or_float")]
+    pub un_realized_profit: f64,
+    /// `0` when the position cannot be liquidated, e.g. when it is flat.
+    #[serde(with = "string_or_float")]
+    pub liquidation_price: f64,
+    #[serde(with = "string_or_float")]
+    pub leverage: f64,
+    pub margin_type: String,
+    pub position_side: String,
+    #[serde(with = "string_or_float", default)]
+    pub notional: f64,
+    #[serde(default)]
+    pub update_time: u64,
+}
+
+/// Answer of `GET /fapi/v1/positionSide/dual`: `true` in hedge mode, `false` in one-way mode.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionMode {
+    pub dual_side_position: bool,
+}
+
+/// Totals from `GET /fapi/v2/account`, in USDⓈ.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FutureAccount {
+    #[serde(with = "string_or_float")]
+    pub total_wallet_balance: f64,
+    #[serde(with = "string_or_float")]
+    pub total_unrealized_profit: f64,
+    #[serde(with = "string_or_float")]
+    pub total_margin_balance: f64,
+    #[serde(with = "string_or_float")]
+    pub total_initial_margin: f64,
+    #[serde(with = "string_or_float")]
+    pub total_maint_margin: f64,
+    #[serde(with = "string_or_float")]
+    pub available_balance: f64,
+}
+
+impl FutureAccount {
+    /// Maintenance margin over margin balance; the account is liquidated when it reaches 1.
+    pub fn margin_ratio(&self) -> f64 {
+        match self.total_margin_balance > 0.0 {
+            true => self.total_maint_margin / self.total_margin_balance,
+            false => 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "filterType")]
+pub enum SymbolFilter {
+    #[serde(rename = "PRICE_FILTER", rename_all = "camelCase")]
+    PriceFilter {
+        #[serde(with = "string_or_float")]
+        tick_size: f64,
+    },
+    #[serde(rename = "LOT_SIZE", rename_all = "camelCase")]
+    LotSize {
+        #[serde(with = "string_or_float")]
+        min_qty: f64,
+        #[serde(with = "string_or_float")]
+        step_size: f64,
+    },
+    /// Spot calls the field `minNotional`, futures call it `notional`.
+    #[serde(rename = "MIN_NOTIONAL", rename_all = "camelCase")]
+    MinNotional {
+        #[serde(with = "string_or_float", alias = "notional")]
+        min_notional: f64,
+    },
+    #[serde(rename = "NOTIONAL", rename_all = "camelCase")]
+    Notional {
+        #[serde(with = "string_or_float")]
+        min_notional: f64,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub filters: Vec<SymbolFilter>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExchangeInformation {
+    pub symbols: Vec<SymbolInfo>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Builder, Default)]
 pub struct CurrentPrice {
     pub symbol: String,
@@ -105,6 +336,90 @@ pub struct Kline {
     pub taker_buy_quote_asset_volume: String,
 }
 
+/// Event of the `<symbol>@aggTrade` market stream.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AggTradeEvent {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "p", with = "string_or_float")]
+    pub price: f64,
+    #[serde(rename = "q", with = "string_or_float")]
+    pub quantity: f64,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+}
+
+/// Event of the `<symbol>@bookTicker` market stream: the best bid and ask.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BookTickerEvent {
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "b", with = "string_or_float")]
+    pub bid_price: f64,
+    #[serde(rename = "a", with = "string_or_float")]
+    pub ask_price: f64,
+}
+
+/// Event of the `<symbol>@kline_<interval>` market stream, pushed while the candle is open and
+/// once more when it closes.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KlineEvent {
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "k")]
+    pub kline: StreamKline,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StreamKline {
+    #[serde(rename = "t")]
+    pub open_time: i64,
+    #[serde(rename = "T")]
+    pub close_time: i64,
+    #[serde(rename = "i")]
+    pub interval: String,
+    #[serde(rename = "o")]
+    pub open: String,
+    #[serde(rename = "h")]
+    pub high: String,
+    #[serde(rename = "l")]
+    pub low: String,
+    #[serde(rename = "c")]
+    pub close: String,
+    #[serde(rename = "v")]
+    pub volume: String,
+    #[serde(rename = "n")]
+    pub number_of_trades: i64,
+    #[serde(rename = "x")]
+    pub closed: bool,
+    #[serde(rename = "q")]
+    pub quote_asset_volume: String,
+    #[serde(rename = "V")]
+    pub taker_buy_base_asset_volume: String,
+    #[serde(rename = "Q")]
+    pub taker_buy_quote_asset_volume: String,
+}
+
+impl From<StreamKline> for Kline {
+    fn from(kline: StreamKline) -> Self {
+        Kline {
+            open_time: kline.open_time,
+            open: kline.open,
+            high: kline.high,
+            low: kline.low,
+            close: kline.close,
+            volume: kline.volume,
+            close_time: kline.close_time,
+            quote_asset_volume: kline.quote_asset_volume,
+            number_of_trades: kline.number_of_trades,
+            taker_buy_base_asset_volume: kline.taker_buy_base_asset_volume,
+            taker_buy_quote_asset_volume: kline.taker_buy_quote_asset_volume,
+        }
+    }
+}
+
 fn get_value(row: &[Value], index: usize) -> Result<Value, ErrorKind> {
     Ok(row
         .get(index)
@@ -1,141 +1,978 @@
 use lazy_static::lazy_static;
-use rusqlite::{Connection, Result};
-use std::sync::Mutex;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Result};
+use serde::Serialize;
+use std::{collections::HashMap, sync::Mutex};
 
-struct DBClient {
+use crate::config::app_config::DEFAULT_ACCOUNT;
+use crate::crypto::exchange::{ExchangeOrder, ExchangeTrade};
+use crate::ledger::pnl::Pnl;
+
+const DB_PATH: &str = "quanpass.db";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotRecord {
+    pub id: String,
+    pub name: String,
+    pub strategy: String,
+    pub params: HashMap<String, String>,
+    pub schedule: String,
+    pub status: String,
+    pub mode: String,
+}
+
+/// One scheduled run of a robot. Times are milliseconds since the epoch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunRecord {
+    pub id: i64,
+    pub robot_id: String,
+    pub started_at: i64,
+    pub finished_at: i64,
+    /// `ok`, `error` when anything in `errors` went wrong, or `panic`.
+    pub outcome: String,
+    pub orders: Vec<ExchangeOrder>,
+    pub errors: Vec<String>,
+}
+
+/// An order in the ledger. `market` is `spot` or `futures`; `robot_id` is set when the client
+/// order id carries the tag of a robot.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OrderRecord {
+    pub mode: String,
+    pub account: String,
+    pub market: String,
+    pub robot_id: Option<String>,
+    #[serde(flatten)]
+    pub order: ExchangeOrder,
+}
+
+/// A fill in the ledger, attributed to the robot of its order.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TradeRecord {
+    pub mode: String,
+    pub account: String,
+    pub market: String,
+    pub robot_id: Option<String>,
+    #[serde(flatten)]
+    pub trade: ExchangeTrade,
+}
+
+/// A robot's PnL at `time` (milliseconds).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PnlRecord {
+    pub time: i64,
+    #[serde(flatten)]
+    pub pnl: Pnl,
+}
+
+/// Filters of `list_orders`; `from`/`to` bound the order creation time in milliseconds.
+#[derive(Debug, Clone, Default)]
+pub struct OrderFilter {
+    pub robot_id: Option<String>,
+    pub symbol: Option<String>,
+    pub side: Option<String>,
+    pub status: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl OrderFilter {
+    fn where_clause(&self) -> (String, Vec<Value>) {
+        let mut clauses = vec!["1 = 1".to_string()];
+        let mut values = Vec::new();
+        let columns = [
+            ("robot_id", &self.robot_id),
+            ("symbol", &self.symbol),
+            ("side", &self.side),
+            ("status", &self.status),
+        ];
+        for (column, value) in columns {
+            if let Some(value) = value {
+                values.push(Value::Text(value.clone()));
+                clauses.push(format!("{} = ?{}", column, values.len()));
+            }
+        }
+        if let Some(from) = self.from {
+            values.push(Value::Integer(from as i64));
+            clauses.push(format!("time >= ?{}", values.len()));
+        }
+        if let Some(to) = self.to {
+            values.push(Value::Integer(to as i64));
+            clauses.push(format!("time <= ?{}", values.len()));
+        }
+        (clauses.join(" AND "), values)
+    }
+}
+
+/// Order states Binance may still change.
+const OPEN_ORDER_STATUSES: &str = "'NEW', 'PARTIALLY_FILLED', 'PENDING_NEW'";
+
+pub struct DBClient {
     pub conn: Connection,
 }
 
 impl DBClient {
     fn new() -> Self {
-        let conn = Connection::open_in_memory().unwrap();
-        DBClient { conn }
+        // Tests share one throwaway database instead of the working directory's.
+        let conn = match cfg!(test) {
+            true => Connection::open_in_memory().unwrap(),
+            false => Connection::open(DB_PATH).unwrap(),
+        };
+        let client = DBClient { conn };
+        client.create_robot_table().unwrap();
+        client.create_run_table().unwrap();
+        client.create_ledger_tables().unwrap();
+        client.create_pnl_table().unwrap();
+        client.create_order_sequence_table().unwrap();
+        client.create_paper_book_table().unwrap();
+        client.create_grid_ladder_table().unwrap();
+        client
     }
 
-    fn get_instance() -> &'static Mutex<DBClient> {
+    pub fn get_instance() -> &'static Mutex<DBClient> {
         lazy_static! {
             static ref INSTANCE: Mutex<DBClient> = Mutex::new(DBClient::new());
         }
         &INSTANCE
     }
 
-    // Example method to create a table
-    pub fn create_order_table(&self) -> Result<()> {
-        self.conn
-            .execute(
-                "CREATE TABLE IF NOT EXISTS orders (
+    /// Orders and fills synced from Binance, keyed by venue and account so live and testnet ids,
+    /// or two accounts trading one symbol, never clash.
+    pub fn create_ledger_tables(&self) -> Result<()> {
+        let legacy_orders = self.set_aside_ledger("ledger_orders")?;
+        let legacy_trades = self.set_aside_ledger("ledger_trades")?;
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ledger_orders (
+                mode TEXT NOT NULL,
+                account TEXT NOT NULL,
+                market TEXT NOT NULL,
                 symbol TEXT NOT NULL,
-                order_id INTEGER PRIMARY KEY,
-                order_list_id INTEGER,
+                order_id INTEGER NOT NULL,
                 client_order_id TEXT NOT NULL,
+                robot_id TEXT,
+                side TEXT NOT NULL,
+                order_type TEXT NOT NULL,
                 price REAL NOT NULL,
-                orig_qty TEXT NOT NULL,
-                executed_qty TEXT NOT NULL,
-                cummulative_quote_qty TEXT NOT NULL,
+                orig_qty REAL NOT NULL,
+                executed_qty REAL NOT NULL,
                 status TEXT NOT NULL,
-                time_in_force TEXT NOT NULL,
-                type_name TEXT NOT NULL,
-                side TEXT NOT NULL,
-                stop_price REAL NOT NULL,
-                iceberg_qty TEXT NOT NULL,
                 time INTEGER NOT NULL,
                 update_time INTEGER NOT NULL,
-                is_working INTEGER NOT NULL,
-                orig_quote_order_qty TEXT NOT NULL
+                position_side TEXT NOT NULL DEFAULT 'BOTH',
+                stop_price REAL NOT NULL DEFAULT 0,
+                PRIMARY KEY (mode, account, market, symbol, order_id)
             );",
-                [],
-            )
-            .unwrap();
+            [],
+        )?;
+        // Ledgers created before hedge mode and stop order support lack the columns.
+        let _ = self.conn.execute(
+            "ALTER TABLE ledger_orders ADD COLUMN position_side TEXT NOT NULL DEFAULT 'BOTH'",
+            [],
+        );
+        let _ = self.conn.execute(
+            "ALTER TABLE ledger_orders ADD COLUMN stop_price REAL NOT NULL DEFAULT 0",
+            [],
+        );
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS ledger_trades (
+                mode TEXT NOT NULL,
+                account TEXT NOT NULL,
+                market TEXT NOT NULL,
+                symbol TEXT NOT NULL,
+                trade_id INTEGER NOT NULL,
+                order_id INTEGER NOT NULL,
+                robot_id TEXT,
+                side TEXT NOT NULL,
+                price REAL NOT NULL,
+                quantity REAL NOT NULL,
+                quote_qty REAL NOT NULL,
+                commission REAL NOT NULL,
+                commission_asset TEXT NOT NULL,
+                realized_pnl REAL NOT NULL,
+                maker INTEGER NOT NULL,
+                time INTEGER NOT NULL,
+                PRIMARY KEY (mode, account, market, symbol, trade_id)
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS ledger_orders_robot ON ledger_orders (robot_id, time)",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS ledger_trades_robot ON ledger_trades (robot_id, time)",
+            [],
+        )?;
+        if let Some(columns) = legacy_orders {
+            self.copy_back_ledger("ledger_orders", &columns)?;
+        }
+        if let Some(columns) = legacy_trades {
+            self.copy_back_ledger("ledger_trades", &columns)?;
+        }
+        Ok(())
+    }
+
+    /// Ledgers created before accounts were told apart key their rows without one, and a primary
+    /// key cannot be altered: such a table is renamed, and `copy_back_ledger` fills the new one
+    /// from it. Returns the columns to copy.
+    fn set_aside_ledger(&self, table: &str) -> Result<Option<Vec<String>>> {
+        let columns: Vec<String> = self
+            .conn
+            .prepare(&format!("PRAGMA table_info({})", table))?
+            .query_map([], |row| row.get(1))?
+            .collect::<Result<Vec<String>>>()?;
+        if columns.is_empty() || columns.iter().any(|column| column == "account") {
+            return Ok(None);
+        }
+        self.conn
+            .execute(&format!("ALTER TABLE {} RENAME TO {}_before_account", table, table), [])?;
+        self.conn
+            .execute(&format!("DROP INDEX IF EXISTS {}_robot", table), [])?;
+        Ok(Some(columns))
+    }
+
+    /// Copies the rows set aside by `set_aside_ledger`, each taking the account of its robot, or
+    /// the default one.
+    fn copy_back_ledger(&self, table: &str, columns: &[String]) -> Result<()> {
+        let old = format!("{}_before_account", table);
+        let robots: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'robots'",
+            [],
+            |row| row.get(0),
+        )?;
+        let account = match robots > 0 {
+            true => format!(
+                "COALESCE((SELECT json_extract(robots.params, '$.account') FROM robots WHERE robots.id = {}.robot_id), '{}')",
+                old, DEFAULT_ACCOUNT
+            ),
+            false => format!("'{}'", DEFAULT_ACCOUNT),
+        };
+        let columns = columns.join(", ");
+        self.conn.execute(
+            &format!(
+                "INSERT OR IGNORE INTO {} (account, {}) SELECT {}, {} FROM {}",
+                table, columns, account, columns, old
+            ),
+            [],
+        )?;
+        self.conn.execute(&format!("DROP TABLE {}", old), [])?;
+        Ok(())
+    }
+
+    pub fn create_robot_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS robots (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                strategy TEXT NOT NULL,
+                params TEXT NOT NULL,
+                schedule TEXT NOT NULL,
+                status TEXT NOT NULL,
+                mode TEXT NOT NULL DEFAULT 'live'
+            );",
+            [],
+        )?;
+        // Databases created before trading modes existed lack the column.
+        let _ = self.conn.execute(
+            "ALTER TABLE robots ADD COLUMN mode TEXT NOT NULL DEFAULT 'live'",
+            [],
+        );
+        Ok(())
+    }
+
+    pub fn save_robot(&self, robot: &RobotRecord) -> Result<()> {
+        let params = serde_json::to_string(&robot.params).unwrap_or_default();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO robots (id, name, strategy, params, schedule, status, mode) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![robot.id, robot.name, robot.strategy, params, robot.schedule, robot.status, robot.mode],
+        )?;
+        Ok(())
+    }
+
+    pub fn update_robot_status(&self, id: &str, status: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE robots SET status = ?1 WHERE id = ?2",
+            params![status, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_robot(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM robots WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn list_robots(&self) -> Result<Vec<RobotRecord>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, strategy, params, schedule, status, mode FROM robots")?;
+        let robots = stmt
+            .query_map([], |row| {
+                let params: String = row.get(3)?;
+                Ok(RobotRecord {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    strategy: row.get(2)?,
+                    params: serde_json::from_str(&params).unwrap_or_default(),
+                    schedule: row.get(4)?,
+                    status: row.get(5)?,
+                    mode: row.get(6)?,
+                })
+            })?
+            .collect::<Result<Vec<RobotRecord>>>()?;
+        Ok(robots)
+    }
+
+    pub fn create_run_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS robot_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                robot_id TEXT NOT NULL,
+                started_at INTEGER NOT NULL,
+                finished_at INTEGER NOT NULL,
+                outcome TEXT NOT NULL,
+                orders TEXT NOT NULL,
+                errors TEXT NOT NULL
+            );",
+            [],
+        )?;
+        self.conn.execute(
+            "CREATE INDEX IF NOT EXISTS robot_runs_robot ON robot_runs (robot_id, id)",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Stores a run and returns its id; `run.id` is ignored.
+    pub fn save_run(&self, run: &RunRecord) -> Result<i64> {
+        let orders = serde_json::to_string(&run.orders).unwrap_or_default();
+        let errors = serde_json::to_string(&run.errors).unwrap_or_default();
+        self.conn.execute(
+            "INSERT INTO robot_runs (robot_id, started_at, finished_at, outcome, orders, errors) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![run.robot_id, run.started_at, run.finished_at, run.outcome, orders, errors],
+        )?;
+        Ok(self.conn.last_insert_rowid())
+    }
+
+    /// Runs of a robot, newest first.
+    pub fn list_runs(&self, robot_id: &str, limit: u32, offset: u32) -> Result<Vec<RunRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, robot_id, started_at, finished_at, outcome, orders, errors FROM robot_runs
+            WHERE robot_id = ?1 ORDER BY id DESC LIMIT ?2 OFFSET ?3",
+        )?;
+        let runs = stmt
+            .query_map(params![robot_id, limit, offset], |row| {
+                let orders: String = row.get(5)?;
+                let errors: String = row.get(6)?;
+                Ok(RunRecord {
+                    id: row.get(0)?,
+                    robot_id: row.get(1)?,
+                    started_at: row.get(2)?,
+                    finished_at: row.get(3)?,
+                    outcome: row.get(4)?,
+                    orders: serde_json::from_str(&orders).unwrap_or_default(),
+                    errors: serde_json::from_str(&errors).unwrap_or_default(),
+                })
+            })?
+            .collect::<Result<Vec<RunRecord>>>()?;
+        Ok(runs)
+    }
+
+    pub fn count_runs(&self, robot_id: &str) -> Result<u32> {
+        self.conn.query_row(
+            "SELECT COUNT(*) FROM robot_runs WHERE robot_id = ?1",
+            params![robot_id],
+            |row| row.get(0),
+        )
+    }
+
+    pub fn delete_runs(&self, robot_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM robot_runs WHERE robot_id = ?1", params![robot_id])?;
+        Ok(())
+    }
+
+    pub fn create_pnl_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS pnl_snapshots (
+                robot_id TEXT NOT NULL,
+                time INTEGER NOT NULL,
+                pnl TEXT NOT NULL,
+                PRIMARY KEY (robot_id, time)
+            );",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_pnl(&self, robot_id: &str, record: &PnlRecord) -> Result<()> {
+        let pnl = serde_json::to_string(&record.pnl).unwrap_or_default();
+        self.conn.execute(
+            "INSERT OR REPLACE INTO pnl_snapshots (robot_id, time, pnl) VALUES (?1, ?2, ?3)",
+            params![robot_id, record.time, pnl],
+        )?;
+        Ok(())
+    }
+
+    /// Snapshots of a robot between `from` and `to`, oldest first, at most `limit` of the newest.
+    pub fn list_pnl(&self, robot_id: &str, from: i64, to: i64, limit: u32) -> Result<Vec<PnlRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT time, pnl FROM (
+                SELECT time, pnl FROM pnl_snapshots WHERE robot_id = ?1 AND time >= ?2 AND time <= ?3
+                ORDER BY time DESC LIMIT ?4
+            ) ORDER BY time",
+        )?;
+        let records = stmt
+            .query_map(params![robot_id, from, to, limit], |row| {
+                let pnl: String = row.get(1)?;
+                Ok(PnlRecord {
+                    time: row.get(0)?,
+                    pnl: serde_json::from_str(&pnl).unwrap_or_default(),
+                })
+            })?
+            .collect::<Result<Vec<PnlRecord>>>()?;
+        Ok(records)
+    }
+
+    pub fn delete_pnl(&self, robot_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM pnl_snapshots WHERE robot_id = ?1", params![robot_id])?;
+        Ok(())
+    }
+
+    /// Last number handed out for each client order id tag.
+    pub fn create_order_sequence_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS order_sequences (
+                tag TEXT PRIMARY KEY,
+                next INTEGER NOT NULL
+            );",
+            [],
+        )?;
+        Ok(())
+    }
+
+    /// Next number of `tag`'s sequence, stored before it is returned: one more than the last,
+    /// and never below `floor`.
+    pub fn next_order_sequence(&self, tag: &str, floor: i64) -> Result<i64> {
+        self.conn.query_row(
+            "INSERT INTO order_sequences (tag, next) VALUES (?1, ?2)
+             ON CONFLICT(tag) DO UPDATE SET next = MAX(next + 1, excluded.next)
+             RETURNING next",
+            params![tag, floor],
+            |row| row.get(0),
+        )
+    }
+
+    /// Paper orders and positions of a paper robot, as the JSON of its `PaperBook`.
+    pub fn create_paper_book_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS paper_books (
+                robot_id TEXT PRIMARY KEY,
+                book TEXT NOT NULL
+            );",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_paper_book(&self, robot_id: &str, book: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO paper_books (robot_id, book) VALUES (?1, ?2)
+             ON CONFLICT(robot_id) DO UPDATE SET book = excluded.book",
+            params![robot_id, book],
+        )?;
+        Ok(())
+    }
+
+    pub fn load_paper_book(&self, robot_id: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT book FROM paper_books WHERE robot_id = ?1", params![robot_id], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    pub fn delete_paper_book(&self, robot_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM paper_books WHERE robot_id = ?1", params![robot_id])?;
+        Ok(())
+    }
+
+    /// Ladder of a range grid, as JSON, keyed by the order tag of its robot.
+    pub fn create_grid_ladder_table(&self) -> Result<()> {
+        self.conn.execute(
+            "CREATE TABLE IF NOT EXISTS grid_ladders (
+                tag TEXT PRIMARY KEY,
+                ladder TEXT NOT NULL
+            );",
+            [],
+        )?;
+        Ok(())
+    }
+
+    pub fn save_grid_ladder(&self, tag: &str, ladder: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO grid_ladders (tag, ladder) VALUES (?1, ?2)
+             ON CONFLICT(tag) DO UPDATE SET ladder = excluded.ladder",
+            params![tag, ladder],
+        )?;
         Ok(())
     }
+
+    pub fn load_grid_ladder(&self, tag: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT ladder FROM grid_ladders WHERE tag = ?1", params![tag], |row| {
+                row.get(0)
+            })
+            .optional()
+    }
+
+    pub fn delete_grid_ladder(&self, tag: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM grid_ladders WHERE tag = ?1", params![tag])?;
+        Ok(())
+    }
+
+    /// Inserts an order or refreshes its state. A robot tag, once known, is kept.
+    pub fn upsert_order(&self, record: &OrderRecord) -> Result<()> {
+        let order = &record.order;
+        self.conn.execute(
+            "INSERT INTO ledger_orders (mode, market, symbol, order_id, client_order_id, robot_id, side, order_type, price, orig_qty, executed_qty, status, time, update_time, position_side, stop_price, account)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)
+            ON CONFLICT (mode, account, market, symbol, order_id) DO UPDATE SET
+                robot_id = COALESCE(ledger_orders.robot_id, excluded.robot_id),
+                executed_qty = excluded.executed_qty,
+                status = excluded.status,
+                update_time = excluded.update_time",
+            params![
+                record.mode,
+                record.market,
+                order.symbol,
+                order.order_id as i64,
+                order.client_order_id,
+                record.robot_id,
+                order.side,
+                order.order_type,
+                order.price,
+                order.orig_qty,
+                order.executed_qty,
+                order.status,
+                order.time as i64,
+                order.update_time as i64,
+                order.position_side,
+                order.stop_price,
+                record.account,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Inserts a fill, taking the robot from its order when the trade has none.
+    pub fn upsert_trade(&self, record: &TradeRecord) -> Result<()> {
+        let trade = &record.trade;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO ledger_trades (mode, market, symbol, trade_id, order_id, robot_id, side, price, quantity, quote_qty, commission, commission_asset, realized_pnl, maker, time, account)
+            VALUES (?1, ?2, ?3, ?4, ?5,
+                COALESCE(?6, (SELECT robot_id FROM ledger_orders WHERE mode = ?1 AND account = ?16 AND market = ?2 AND symbol = ?3 AND order_id = ?5)),
+                ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16)",
+            params![
+                record.mode,
+                record.market,
+                trade.symbol,
+                trade.trade_id as i64,
+                trade.order_id as i64,
+                record.robot_id,
+                trade.side,
+                trade.price,
+                trade.quantity,
+                trade.quote_qty,
+                trade.commission,
+                trade.commission_asset,
+                trade.realized_pnl,
+                trade.maker,
+                trade.time as i64,
+                record.account,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Order id the next order sync of a symbol starts from: one past the newest order stored,
+    /// `None` when nothing is stored yet. Orders that may still change are followed by
+    /// `open_order_ids` instead.
+    pub fn order_cursor(&self, mode: &str, account: &str, market: &str, symbol: &str) -> Result<Option<u64>> {
+        let last: Option<i64> = self.conn.query_row(
+            "SELECT MAX(order_id) FROM ledger_orders WHERE mode = ?1 AND account = ?2 AND market = ?3 AND symbol = ?4",
+            params![mode, account, market, symbol],
+            |row| row.get(0),
+        )?;
+        Ok(last.map(|id| id as u64 + 1))
+    }
+
+    /// Order ids and client order ids of the stored orders of a symbol that may still change.
+    pub fn open_order_ids(&self, mode: &str, account: &str, market: &str, symbol: &str) -> Result<Vec<(u64, String)>> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT order_id, client_order_id FROM ledger_orders WHERE mode = ?1 AND account = ?2 AND market = ?3 AND symbol = ?4 AND status IN ({}) ORDER BY order_id",
+            OPEN_ORDER_STATUSES
+        ))?;
+        let rows = stmt.query_map(params![mode, account, market, symbol], |row| {
+            Ok((row.get::<_, i64>(0)? as u64, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    /// Trade id the next trade sync of a symbol starts from, `None` when nothing is stored yet.
+    pub fn trade_cursor(&self, mode: &str, account: &str, market: &str, symbol: &str) -> Result<Option<u64>> {
+        let last: Option<i64> = self.conn.query_row(
+            "SELECT MAX(trade_id) FROM ledger_trades WHERE mode = ?1 AND account = ?2 AND market = ?3 AND symbol = ?4",
+            params![mode, account, market, symbol],
+            |row| row.get(0),
+        )?;
+        Ok(last.map(|id| id as u64 + 1))
+    }
+
+    /// Orders matching `filter`, newest first.
+    pub fn list_orders(&self, filter: &OrderFilter, limit: u32, offset: u32) -> Result<Vec<OrderRecord>> {
+        let (clause, values) = filter.where_clause();
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT mode, market, robot_id, order_id, client_order_id, symbol, side, order_type, price, orig_qty, executed_qty, status, time, update_time, position_side, stop_price, account
+            FROM ledger_orders WHERE {} ORDER BY time DESC, order_id DESC LIMIT {} OFFSET {}",
+            clause, limit, offset
+        ))?;
+        let orders = stmt
+            .query_map(params_from_iter(values), |row| {
+                Ok(OrderRecord {
+                    mode: row.get(0)?,
+                    account: row.get(16)?,
+                    market: row.get(1)?,
+                    robot_id: row.get(2)?,
+                    order: ExchangeOrder {
+                        order_id: row.get::<_, i64>(3)? as u64,
+                        client_order_id: row.get(4)?,
+                        symbol: row.get(5)?,
+                        side: row.get(6)?,
+                        order_type: row.get(7)?,
+                        price: row.get(8)?,
+                        stop_price: row.get(15)?,
+                        orig_qty: row.get(9)?,
+                        executed_qty: row.get(10)?,
+                        status: row.get(11)?,
+                        position_side: row.get(14)?,
+                        time: row.get::<_, i64>(12)? as u64,
+                        update_time: row.get::<_, i64>(13)? as u64,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<OrderRecord>>>()?;
+        Ok(orders)
+    }
+
+    /// Fills attributed to a robot, oldest first.
+    pub fn list_trades(&self, robot_id: &str) -> Result<Vec<TradeRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT mode, market, robot_id, trade_id, order_id, symbol, side, price, quantity, quote_qty, commission, commission_asset, realized_pnl, maker, time, account
+            FROM ledger_trades WHERE robot_id = ?1 ORDER BY time, trade_id",
+        )?;
+        let trades = stmt
+            .query_map(params![robot_id], |row| {
+                Ok(TradeRecord {
+                    mode: row.get(0)?,
+                    account: row.get(15)?,
+                    market: row.get(1)?,
+                    robot_id: row.get(2)?,
+                    trade: ExchangeTrade {
+                        trade_id: row.get::<_, i64>(3)? as u64,
+                        order_id: row.get::<_, i64>(4)? as u64,
+                        symbol: row.get(5)?,
+                        side: row.get(6)?,
+                        price: row.get(7)?,
+                        quantity: row.get(8)?,
+                        quote_qty: row.get(9)?,
+                        commission: row.get(10)?,
+                        commission_asset: row.get(11)?,
+                        realized_pnl: row.get(12)?,
+                        maker: row.get(13)?,
+                        time: row.get::<_, i64>(14)? as u64,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<TradeRecord>>>()?;
+        Ok(trades)
+    }
+
+    /// Assets the fees of a symbol were paid in.
+    pub fn fee_assets(&self, mode: &str, market: &str, symbol: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT DISTINCT commission_asset FROM ledger_trades
+            WHERE mode = ?1 AND market = ?2 AND symbol = ?3 AND commission_asset != ''",
+        )?;
+        let assets = stmt
+            .query_map(params![mode, market, symbol], |row| row.get(0))?
+            .collect::<Result<Vec<String>>>()?;
+        Ok(assets)
+    }
+
+    pub fn count_orders(&self, filter: &OrderFilter) -> Result<u32> {
+        let (clause, values) = filter.where_clause();
+        self.conn.query_row(
+            &format!("SELECT COUNT(*) FROM ledger_orders WHERE {}", clause),
+            params_from_iter(values),
+            |row| row.get(0),
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::client::binance_domain::Order;
+    use std::collections::HashMap;
+
+    use rusqlite::Connection;
+
+    use crate::crypto::exchange::{ExchangeOrder, ExchangeTrade};
 
-    use super::DBClient;
+    use crate::ledger::pnl::Pnl;
+
+    use super::{DBClient, OrderFilter, OrderRecord, PnlRecord, RobotRecord, RunRecord, TradeRecord};
 
     #[test]
-    fn test_limit_sell_and_limit_buy() {
-        let db_client_guard = DBClient::get_instance()
-            .lock()
-            .unwrap()
-            .create_order_table()
-            .unwrap();
+    fn test_order_sequence() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_order_sequence_table().unwrap();
+
+        assert_eq!(db_client.next_order_sequence("robot-1", 100).unwrap(), 100);
+        assert_eq!(db_client.next_order_sequence("robot-1", 100).unwrap(), 101);
+        assert_eq!(db_client.next_order_sequence("robot-1", 50).unwrap(), 102);
+        assert_eq!(db_client.next_order_sequence("robot-1", 500).unwrap(), 500);
+        assert_eq!(db_client.next_order_sequence("robot-2", 7).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_robot_persistence() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_robot_table().unwrap();
+
+        let mut params = HashMap::new();
+        params.insert("symbol".to_string(), "BTCFDUSD".to_string());
+        let mut robot = RobotRecord {
+            id: "robot-1".to_string(),
+            name: "grid".to_string(),
+            strategy: "Grid".to_string(),
+            params,
+            schedule: "0 1/2 * * * *".to_string(),
+            status: "running".to_string(),
+            mode: "paper".to_string(),
+        };
+        db_client.save_robot(&robot).unwrap();
+        assert_eq!(db_client.list_robots().unwrap(), vec![robot.clone()]);
+
+        db_client.update_robot_status("robot-1", "suspended").unwrap();
+        robot.status = "suspended".to_string();
+        assert_eq!(db_client.list_robots().unwrap(), vec![robot]);
+
+        db_client.delete_robot("robot-1").unwrap();
+        assert!(db_client.list_robots().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_run_history() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_run_table().unwrap();
+
+        for started_at in 1..=3 {
+            let run = RunRecord {
+                id: 0,
+                robot_id: "robot-1".to_string(),
+                started_at,
+                finished_at: started_at + 1,
+                outcome: "error".to_string(),
+                orders: Vec::new(),
+                errors: vec![format!("failure {}", started_at)],
+            };
+            db_client.save_run(&run).unwrap();
+        }
+        assert_eq!(db_client.count_runs("robot-1").unwrap(), 3);
+        assert_eq!(db_client.count_runs("robot-2").unwrap(), 0);
+
+        let page = db_client.list_runs("robot-1", 2, 1).unwrap();
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0].started_at, 2);
+        assert_eq!(page[0].errors, vec!["failure 2".to_string()]);
+        assert_eq!(page[1].started_at, 1);
+
+        db_client.delete_runs("robot-1").unwrap();
+        assert_eq!(db_client.count_runs("robot-1").unwrap(), 0);
+    }
 
-        /* db_client_guard.create_order_table().unwrap();
-            let order = Order {
-                symbol: "BTCUSDT".to_string(),
-                order_id: 1u64,
-                order_list_id: 0,
-                client_order_id: "testClientOrderId".to_string(),
-                price: 50000.0,
-                orig_qty: "1.0".to_string(),
-                executed_qty: "0.0".to_string(),
-                cummulative_quote_qty: "0.0".to_string(),
-                status: "NEW".to_string(),
-                time_in_force: "GTC".to_string(),
-                type_name: "LIMIT".to_string(),
+    fn order_record(order_id: u64, robot_id: Option<&str>, status: &str, time: u64) -> OrderRecord {
+        OrderRecord {
+            mode: "live".to_string(),
+            account: "default".to_string(),
+            market: "spot".to_string(),
+            robot_id: robot_id.map(|id| id.to_string()),
+            order: ExchangeOrder {
+                order_id,
+                client_order_id: format!("tag-{}", order_id),
+                symbol: "BTCFDUSD".to_string(),
                 side: "BUY".to_string(),
+                order_type: "LIMIT".to_string(),
+                price: 100.0,
                 stop_price: 0.0,
-                iceberg_qty: "0.0".to_string(),
-                time: 1622549728473,
-                update_time: 1622549728473,
-                is_working: true,
-                orig_quote_order_qty: "0.0".to_string(),
+                orig_qty: 1.0,
+                executed_qty: 0.0,
+                status: status.to_string(),
+                position_side: "BOTH".to_string(),
+                time,
+                update_time: time,
+            },
+        }
+    }
+
+    #[test]
+    fn test_ledger() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_ledger_tables().unwrap();
+        assert_eq!(db_client.order_cursor("live", "default", "spot", "BTCFDUSD").unwrap(), None);
+
+        db_client.upsert_order(&order_record(1, Some("robot-1"), "FILLED", 10)).unwrap();
+        db_client.upsert_order(&order_record(2, Some("robot-1"), "NEW", 20)).unwrap();
+        db_client.upsert_order(&order_record(3, None, "CANCELED", 30)).unwrap();
+        assert_eq!(db_client.order_cursor("live", "default", "spot", "BTCFDUSD").unwrap(), Some(4));
+        assert_eq!(
+            db_client.open_order_ids("live", "default", "spot", "BTCFDUSD").unwrap(),
+            vec![(2, "tag-2".to_string())]
+        );
+
+        // A refresh updates the state and keeps the robot tag.
+        let mut filled = order_record(2, None, "FILLED", 20);
+        filled.order.executed_qty = 1.0;
+        filled.order.update_time = 25;
+        db_client.upsert_order(&filled).unwrap();
+        assert_eq!(db_client.order_cursor("live", "default", "spot", "BTCFDUSD").unwrap(), Some(4));
+        assert!(db_client.open_order_ids("live", "default", "spot", "BTCFDUSD").unwrap().is_empty());
+
+        let filter = OrderFilter {
+            robot_id: Some("robot-1".to_string()),
+            ..OrderFilter::default()
+        };
+        let orders = db_client.list_orders(&filter, 10, 0).unwrap();
+        assert_eq!(db_client.count_orders(&filter).unwrap(), 2);
+        assert_eq!(orders[0].order.order_id, 2);
+        assert_eq!(orders[0].order.status, "FILLED");
+        assert_eq!(orders[0].robot_id.as_deref(), Some("robot-1"));
+
+        let filter = OrderFilter {
+            status: Some("FILLED".to_string()),
+            from: Some(15),
+            ..OrderFilter::default()
+        };
+        assert_eq!(db_client.count_orders(&filter).unwrap(), 1);
+
+        let trade = TradeRecord {
+            mode: "live".to_string(),
+            account: "default".to_string(),
+            market: "spot".to_string(),
+            robot_id: None,
+            trade: ExchangeTrade {
+                trade_id: 7,
+                order_id: 2,
+                symbol: "BTCFDUSD".to_string(),
+                side: "BUY".to_string(),
+                price: 100.0,
+                quantity: 1.0,
+                quote_qty: 100.0,
+                commission: 0.0,
+                commission_asset: "BNB".to_string(),
+                realized_pnl: 0.0,
+                maker: true,
+                time: 25,
+            },
+        };
+        db_client.upsert_trade(&trade).unwrap();
+        db_client.upsert_trade(&trade).unwrap();
+        assert_eq!(db_client.trade_cursor("live", "default", "spot", "BTCFDUSD").unwrap(), Some(8));
+        let robot_id: Option<String> = db_client
+            .conn
+            .query_row("SELECT robot_id FROM ledger_trades WHERE trade_id = 7", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(robot_id.as_deref(), Some("robot-1"));
+        assert_eq!(db_client.list_trades("robot-1").unwrap(), vec![TradeRecord {
+            robot_id: Some("robot-1".to_string()),
+            ..trade
+        }]);
+        assert_eq!(db_client.fee_assets("live", "spot", "BTCFDUSD").unwrap(), vec!["BNB".to_string()]);
+    }
+
+    #[test]
+    fn test_ledger_account_migration() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_robot_table().unwrap();
+        db_client
+            .conn
+            .execute_batch(
+                "CREATE TABLE ledger_orders (mode TEXT NOT NULL, market TEXT NOT NULL, symbol TEXT NOT NULL,
+                    order_id INTEGER NOT NULL, client_order_id TEXT NOT NULL, robot_id TEXT, side TEXT NOT NULL,
+                    order_type TEXT NOT NULL, price REAL NOT NULL, orig_qty REAL NOT NULL,
+                    executed_qty REAL NOT NULL, status TEXT NOT NULL, time INTEGER NOT NULL,
+                    update_time INTEGER NOT NULL, PRIMARY KEY (mode, market, symbol, order_id));
+                INSERT INTO ledger_orders VALUES ('live', 'spot', 'BTCFDUSD', 1, 'tag-1', 'robot-1', 'BUY', 'LIMIT', 100, 1, 0, 'NEW', 10, 10);
+                INSERT INTO ledger_orders VALUES ('live', 'spot', 'BTCFDUSD', 2, 'web_2', NULL, 'BUY', 'LIMIT', 100, 1, 0, 'NEW', 20, 20);",
+            )
+            .unwrap();
+        let mut params = HashMap::new();
+        params.insert("account".to_string(), "sub".to_string());
+        db_client
+            .save_robot(&RobotRecord {
+                id: "robot-1".to_string(),
+                name: "grid".to_string(),
+                strategy: "Grid".to_string(),
+                params,
+                schedule: "0 * * * * *".to_string(),
+                status: "Running".to_string(),
+                mode: "live".to_string(),
+            })
+            .unwrap();
+
+        db_client.create_ledger_tables().unwrap();
+        assert_eq!(db_client.order_cursor("live", "sub", "spot", "BTCFDUSD").unwrap(), Some(2));
+        assert_eq!(
+            db_client.open_order_ids("live", "default", "spot", "BTCFDUSD").unwrap(),
+            vec![(2, "web_2".to_string())]
+        );
+        let orders = db_client.list_orders(&OrderFilter::default(), 10, 0).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[1].order.position_side, "BOTH");
+    }
+
+    #[test]
+    fn test_pnl_snapshots() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_pnl_table().unwrap();
+        for time in 1..=4 {
+            let record = PnlRecord {
+                time,
+                pnl: Pnl {
+                    realized: time as f64,
+                    ..Pnl::default()
+                },
             };
-            db_client.unwrap().conn.execute(
-            "INSERT INTO orders (symbol, order_id, order_list_id, client_order_id, price, orig_qty, executed_qty, cummulative_quote_qty, status, time_in_force, type_name, side, stop_price, iceberg_qty, time, update_time, is_working, orig_quote_order_qty) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18)",
-                rusqlite::params![
-                    order.symbol,
-                    order.order_id,
-                    order.order_list_id,
-                    order.client_order_id,
-                    order.price,
-                    order.orig_qty,
-                    order.executed_qty,
-                    order.cummulative_quote_qty,
-                    order.status,
-                    order.time_in_force,
-                    order.type_name,
-                    order.side,
-                    order.stop_price,
-                    order.iceberg_qty,
-                    order.time,
-                    order.update_time,
-                    order.is_working as i32, // Assuming is_working is a bool, SQLite uses integers for boolean values (0 for false, 1 for true)
-                    order.orig_quote_order_qty,
-                ],
-        ).unwrap();
-
-            let mut stmt = db_client.unwrap().conn.prepare("SELECT symbol, order_id, order_list_id, client_order_id, price, orig_qty, executed_qty, cummulative_quote_qty, status, time_in_force, type_name, side, stop_price, iceberg_qty, time, update_time, is_working, orig_quote_order_qty
-        FROM orders").unwrap();
-            let orders_iter = stmt
-                .query_map([], |row| {
-                    Ok(Order {
-                        // Assuming you have a constructor or a way to create an Order from a row. Adjust the types as necessary.
-                        symbol: row.get(0)?,
-                        order_id: row.get(1)?,
-                        order_list_id: row.get(2)?,
-                        client_order_id: row.get(3)?,
-                        price: row.get(4)?,
-                        orig_qty: row.get(5)?,
-                        executed_qty: row.get(6)?,
-                        cummulative_quote_qty: row.get(7)?,
-                        status: row.get(8)?,
-                        time_in_force: row.get(9)?,
-                        type_name: row.get(10)?,
-                        side: row.get(11)?,
-                        stop_price: row.get(12)?,
-                        iceberg_qty: row.get(13)?,
-                        time: row.get(14)?,
-                        update_time: row.get(15)?,
-                        is_working: row.get(16)?,
-                        orig_quote_order_qty: row.get(17)?,
-                    })
-                })
-                .unwrap(); */
+            db_client.save_pnl("robot-1", &record).unwrap();
+        }
+        let series = db_client.list_pnl("robot-1", 2, 10, 2).unwrap();
+        assert_eq!(series.iter().map(|record| record.time).collect::<Vec<i64>>(), vec![3, 4]);
+        assert_eq!(series[1].pnl.realized, 4.0);
     }
 }
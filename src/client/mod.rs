@@ -1,3 +1,5 @@
 pub mod binance_domain;
 pub mod binance_client;
 pub mod db_client;
+pub mod rate_limiter;
+pub mod server_clock;
@@ -0,0 +1,366 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+use reqwest::header::HeaderMap;
+use reqwest::Method;
+use salvo::{handler, prelude::Json, Response};
+use serde::Serialize;
+
+use crate::client::binance_client::is_futures_host;
+use crate::util::binance_error::{BinanceError, Result};
+use crate::util::time_util::now_timestamp;
+
+/// Share of a limit used before requests are held back, leaving room for requests already in
+/// flight and for other processes behind the same IP.
+const HEADROOM: f64 = 0.9;
+/// Longest a request is queued for; beyond it the request fails as rate limited.
+const MAX_WAIT_MS: u64 = 60_000;
+
+const USED_WEIGHT_HEADER: &str = "x-mbx-used-weight-";
+const ORDER_COUNT_HEADER: &str = "x-mbx-order-count-";
+
+/// Request weight and order counts seen by every Binance client of the process.
+pub static RATE_LIMITER: Lazy<Mutex<RateLimiter>> = Lazy::new(|| Mutex::new(RateLimiter::default()));
+
+/// Usage of one Binance limit over a fixed window, aligned like Binance's to the epoch.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Counter {
+    /// Window as Binance names it in headers, e.g. `1m`, `10s` or `1d`.
+    pub interval: String,
+    pub used: u64,
+    pub limit: u64,
+    /// Start of the current window in milliseconds.
+    pub window_start: u64,
+    #[serde(skip)]
+    interval_ms: u64,
+}
+
+impl Counter {
+    fn new(interval: &str, limit: u64) -> Counter {
+        Counter {
+            interval: interval.to_string(),
+            used: 0,
+            limit,
+            window_start: 0,
+            interval_ms: interval_ms(interval).unwrap_or(60_000),
+        }
+    }
+
+    fn roll(&mut self, now: u64) {
+        let start = now - now % self.interval_ms;
+        if start != self.window_start {
+            self.window_start = start;
+            self.used = 0;
+        }
+    }
+
+    /// Milliseconds until `cost` more fits under the headroom, 0 if it fits now.
+    fn wait(&mut self, now: u64, cost: u64) -> u64 {
+        self.roll(now);
+        if cost == 0 || self.used + cost <= (self.limit as f64 * HEADROOM) as u64 {
+            0
+        } else {
+            self.window_start + self.interval_ms - now
+        }
+    }
+}
+
+/// The counters sharing one scope: request weight is counted per IP, so per host, and orders
+/// per account on a host.
+#[derive(Debug, Clone, Default)]
+struct Bucket {
+    counters: Vec<Counter>,
+    /// Set from `Retry-After` on a 429 or 418; no request is sent before it.
+    banned_until: Option<u64>,
+}
+
+impl Bucket {
+    fn counter(&mut self, interval: &str) -> Option<&mut Counter> {
+        self.counters.iter_mut().find(|counter| counter.interval == interval)
+    }
+}
+
+/// Usage of one host, or of one account on a host, as shown on `GET /admin/rate-limits`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Usage {
+    pub host: String,
+    /// Set for order counts, which Binance keeps per account.
+    pub account: Option<String>,
+    pub counters: Vec<Counter>,
+    pub banned_until: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    buckets: HashMap<(String, Option<String>), Bucket>,
+}
+
+impl RateLimiter {
+    fn bucket(&mut self, host: &str, account: Option<&str>) -> &mut Bucket {
+        self.buckets
+            .entry((host.to_string(), account.map(str::to_string)))
+            .or_insert_with(|| Bucket {
+                counters: match account {
+                    Some(_) => order_limits(host),
+                    None => weight_limits(host),
+                },
+                banned_until: None,
+            })
+    }
+
+    /// Books a request of `weight`, and one order if `order`, when it fits under every limit and
+    /// returns 0; otherwise returns the milliseconds to wait before trying again. Fails when the
+    /// wait would be longer than a request may be queued for.
+    pub fn reserve(&mut self, host: &str, account: &str, weight: u64, order: bool, now: u64) -> Result<u64> {
+        let orders = if order { 1 } else { 0 };
+        let mut wait = 0;
+        for (account, cost) in [(None, weight), (Some(account), orders)] {
+            let bucket = self.bucket(host, account);
+            if let Some(until) = bucket.banned_until {
+                if until > now {
+                    wait = wait.max(until - now);
+                } else {
+                    bucket.banned_until = None;
+                }
+            }
+            for counter in bucket.counters.iter_mut() {
+                wait = wait.max(counter.wait(now, cost));
+            }
+        }
+        if wait > MAX_WAIT_MS {
+            return Err(BinanceError::RateLimited {
+                code: 0,
+                msg: format!("{} is over its request limits for another {} ms", host, wait),
+                retry_after: Some(wait.div_ceil(1000)),
+            });
+        }
+        if wait == 0 {
+            for (account, cost) in [(None, weight), (Some(account), orders)] {
+                for counter in self.bucket(host, account).counters.iter_mut() {
+                    counter.used += cost;
+                }
+            }
+        }
+        Ok(wait)
+    }
+
+    /// Replaces the booked usage with what Binance reports in the `X-MBX-USED-WEIGHT-*` and
+    /// `X-MBX-ORDER-COUNT-*` headers of a response.
+    pub fn record(&mut self, host: &str, account: &str, headers: &HeaderMap, now: u64) {
+        for (name, value) in headers {
+            let used = match value.to_str().ok().and_then(|value| value.parse::<u64>().ok()) {
+                Some(used) => used,
+                None => continue,
+            };
+            let name = name.as_str();
+            let (bucket, interval) = if let Some(interval) = name.strip_prefix(USED_WEIGHT_HEADER) {
+                (self.bucket(host, None), interval)
+            } else if let Some(interval) = name.strip_prefix(ORDER_COUNT_HEADER) {
+                (self.bucket(host, Some(account)), interval)
+            } else {
+                continue;
+            };
+            if let Some(counter) = bucket.counter(interval) {
+                counter.roll(now);
+                counter.used = used;
+            }
+        }
+    }
+
+    /// Holds every request to `host` back for `retry_after` seconds.
+    pub fn ban(&mut self, host: &str, retry_after: u64, now: u64) {
+        let until = now + retry_after * 1000;
+        let bucket = self.bucket(host, None);
+        bucket.banned_until = Some(bucket.banned_until.unwrap_or(0).max(until));
+    }
+
+    pub fn usage(&mut self, now: u64) -> Vec<Usage> {
+        let mut usage: Vec<Usage> = self
+            .buckets
+            .iter_mut()
+            .map(|((host, account), bucket)| {
+                bucket.counters.iter_mut().for_each(|counter| counter.roll(now));
+                Usage {
+                    host: host.clone(),
+                    account: account.clone(),
+                    counters: bucket.counters.clone(),
+                    banned_until: bucket.banned_until.filter(|until| *until > now),
+                }
+            })
+            .collect();
+        usage.sort_by(|a, b| (&a.host, &a.account).cmp(&(&b.host, &b.account)));
+        usage
+    }
+}
+
+fn now() -> u64 {
+    now_timestamp() as u64
+}
+
+/// Waits until a request of `weight` (and one order if `order`) fits under the limits of `host`
+/// and `account`, then books it.
+pub async fn acquire(host: &str, account: &str, weight: u64, order: bool) -> Result<()> {
+    loop {
+        let wait = RATE_LIMITER.lock().unwrap().reserve(host, account, weight, order, now())?;
+        if wait == 0 {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(wait)).await;
+    }
+}
+
+pub fn record(host: &str, account: &str, headers: &HeaderMap) {
+    RATE_LIMITER.lock().unwrap().record(host, account, headers, now());
+}
+
+pub fn ban(host: &str, retry_after: u64) {
+    RATE_LIMITER.lock().unwrap().ban(host, retry_after, now());
+}
+
+/// Current request weight and order counts of every host and account used so far.
+#[handler]
+pub fn get_rate_limits(res: &mut Response) {
+    res.render(Json(RATE_LIMITER.lock().unwrap().usage(now())));
+}
+
+fn interval_ms(interval: &str) -> Option<u64> {
+    let split = interval.len().checked_sub(1)?;
+    let count: u64 = interval[..split].parse().ok()?;
+    let unit = match &interval[split..] {
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        _ => return None,
+    };
+    Some(count * unit)
+}
+
+/// Binance's `REQUEST_WEIGHT` limits, per IP.
+fn weight_limits(host: &str) -> Vec<Counter> {
+    if is_futures_host(host) {
+        vec![Counter::new("1m", 2400)]
+    } else {
+        vec![Counter::new("1m", 6000)]
+    }
+}
+
+/// Binance's `ORDERS` limits, per account.
+fn order_limits(host: &str) -> Vec<Counter> {
+    if is_futures_host(host) {
+        vec![Counter::new("10s", 300), Counter::new("1m", 1200)]
+    } else {
+        vec![Counter::new("10s", 100), Counter::new("1d", 200_000)]
+    }
+}
+
+/// Weight Binance charges for a request, from its endpoint and whether it names a symbol.
+pub fn request_weight(method: &Method, path: &str, query: &str) -> u64 {
+    let symbol = query.split('&').any(|param| param.starts_with("symbol="));
+    match (path, *method == Method::GET) {
+        ("/api/v3/openOrders", true) if symbol => 6,
+        ("/api/v3/openOrders", true) => 80,
+        ("/api/v3/ticker/24hr", _) if symbol => 2,
+        ("/api/v3/ticker/24hr", _) => 80,
+        ("/api/v3/ticker/price", _) if symbol => 2,
+        ("/api/v3/ticker/price", _) => 4,
+        ("/api/v3/klines", _) => 2,
+        ("/api/v3/allOrders" | "/api/v3/myTrades" | "/api/v3/account" | "/api/v3/exchangeInfo", _) => 20,
+        ("/fapi/v1/openOrders", true) if symbol => 1,
+        ("/fapi/v1/openOrders", true) => 40,
+        ("/fapi/v1/ticker/price", _) if symbol => 1,
+        ("/fapi/v1/ticker/price", _) => 2,
+        ("/fapi/v1/positionSide/dual", true) => 30,
+        ("/fapi/v1/klines" | "/fapi/v1/allOrders" | "/fapi/v1/userTrades", _) => 5,
+        ("/fapi/v2/account" | "/fapi/v2/balance" | "/fapi/v2/positionRisk", _) => 5,
+        _ => 1,
+    }
+}
+
+/// Whether the request counts against the `ORDERS` limits.
+pub fn is_order(method: &Method, path: &str) -> bool {
+    *method == Method::POST && matches!(path, "/api/v3/order" | "/fapi/v1/order")
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::header::{HeaderMap, HeaderValue};
+    use reqwest::Method;
+
+    use crate::util::binance_error::BinanceError;
+
+    use super::{is_order, request_weight, RateLimiter};
+
+    const SPOT: &str = "https://api.binance.com";
+    const FUTURES: &str = "https://fapi.binance.com";
+
+    #[test]
+    fn test_reserve_waits_for_next_window() {
+        let mut limiter = RateLimiter::default();
+        let now = 120_000;
+        assert_eq!(limiter.reserve(FUTURES, "default", 2000, false, now).unwrap(), 0);
+        assert_eq!(limiter.reserve(FUTURES, "default", 100, false, now).unwrap(), 0);
+        assert_eq!(limiter.reserve(FUTURES, "default", 100, false, now + 15_000).unwrap(), 45_000);
+        assert_eq!(limiter.reserve(FUTURES, "default", 100, false, now + 60_000).unwrap(), 0);
+        // Weight is shared by every account on the host.
+        assert_eq!(limiter.reserve(FUTURES, "sub", 2100, false, now + 60_000).unwrap(), 60_000);
+        assert_eq!(limiter.reserve(SPOT, "default", 2000, false, now).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reserve_counts_orders_per_account() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..90 {
+            assert_eq!(limiter.reserve(SPOT, "default", 1, true, 5_000).unwrap(), 0);
+        }
+        assert_eq!(limiter.reserve(SPOT, "default", 1, true, 5_000).unwrap(), 5_000);
+        assert_eq!(limiter.reserve(SPOT, "default", 1, false, 5_000).unwrap(), 0);
+        assert_eq!(limiter.reserve(SPOT, "sub", 1, true, 5_000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_record_headers() {
+        let mut limiter = RateLimiter::default();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-mbx-used-weight-1m", HeaderValue::from_static("5990"));
+        headers.insert("x-mbx-used-weight", HeaderValue::from_static("5990"));
+        headers.insert("x-mbx-order-count-10s", HeaderValue::from_static("7"));
+        limiter.record(SPOT, "default", &headers, 61_000);
+
+        let usage = limiter.usage(61_000);
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].account, None);
+        assert_eq!(usage[0].counters[0].used, 5990);
+        assert_eq!(usage[1].account.as_deref(), Some("default"));
+        assert_eq!(usage[1].counters[0].used, 7);
+        assert_eq!(limiter.reserve(SPOT, "default", 1, false, 61_000).unwrap(), 59_000);
+        assert_eq!(limiter.usage(120_000)[0].counters[0].used, 0);
+    }
+
+    #[test]
+    fn test_ban() {
+        let mut limiter = RateLimiter::default();
+        limiter.ban(SPOT, 30, 1_000);
+        assert_eq!(limiter.reserve(SPOT, "default", 1, false, 11_000).unwrap(), 20_000);
+        assert_eq!(limiter.reserve(SPOT, "default", 1, false, 31_000).unwrap(), 0);
+        assert_eq!(limiter.usage(31_000)[0].banned_until, None);
+
+        limiter.ban(SPOT, 7200, 40_000);
+        match limiter.reserve(SPOT, "default", 1, false, 40_000) {
+            Err(BinanceError::RateLimited { retry_after, .. }) => assert_eq!(retry_after, Some(7200)),
+            other => panic!("expected rate limited, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_request_weight() {
+        assert_eq!(request_weight(&Method::GET, "/api/v3/openOrders", "symbol=BTCFDUSD&timestamp=1"), 6);
+        assert_eq!(request_weight(&Method::GET, "/api/v3/openOrders", "timestamp=1"), 80);
+        assert_eq!(request_weight(&Method::DELETE, "/api/v3/openOrders", "symbol=BTCFDUSD"), 1);
+        assert_eq!(request_weight(&Method::GET, "/fapi/v1/klines", "symbol=BTCUSDT"), 5);
+        assert!(is_order(&Method::POST, "/fapi/v1/order"));
+        assert!(!is_order(&Method::DELETE, "/api/v3/order"));
+    }
+}
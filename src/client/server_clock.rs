@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+use crate::client::binance_client::BinanceClient;
+use crate::config::app_config::AppConfig;
+use crate::notify::wechat_client::WechatClient;
+use crate::util::binance_error::Result;
+use crate::util::time_util::now_timestamp;
+
+/// Largest `recvWindow` Binance accepts.
+const MAX_RECV_WINDOW_MS: u64 = 60_000;
+
+/// Last sample of each host's server time.
+static CLOCKS: Lazy<Mutex<HashMap<String, HostClock>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// How far a host's clock is from the local one, as of its last sample.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct HostClock {
+    /// Server time minus local time, in milliseconds.
+    pub offset_ms: i64,
+    /// Round trip of the sample; the offset is known to within half of it.
+    pub rtt_ms: u64,
+    pub sampled_at: i64,
+    /// Whether the drift alert has been raised and not cleared since.
+    alerted: bool,
+}
+
+impl HostClock {
+    /// Takes the server time to have been read halfway through the round trip.
+    fn estimate(before: i64, server_time: i64, after: i64) -> HostClock {
+        HostClock {
+            offset_ms: server_time - (before + after) / 2,
+            rtt_ms: (after - before).max(0) as u64,
+            sampled_at: after,
+            alerted: false,
+        }
+    }
+
+    /// Room for the request to travel and for the uncertainty of the offset on top of `base`.
+    fn recv_window(&self, base: u64) -> u64 {
+        (base + 2 * self.rtt_ms).min(MAX_RECV_WINDOW_MS)
+    }
+}
+
+/// Stores `sample` for `host` and returns the alert to raise when the drift crosses
+/// `drift_alert_ms`, either way.
+fn record(host: &str, mut sample: HostClock, drift_alert_ms: u64) -> Option<String> {
+    let mut clocks = CLOCKS.lock().unwrap();
+    let alerted = clocks.get(host).map(|clock| clock.alerted).unwrap_or(false);
+    let drift = sample.offset_ms.unsigned_abs();
+    sample.alerted = drift > drift_alert_ms;
+    clocks.insert(host.to_string(), sample);
+    match (alerted, sample.alerted) {
+        (false, true) => Some(format!(
+            "Local clock is {} ms off {} (round trip {} ms), above the {} ms threshold",
+            sample.offset_ms, host, sample.rtt_ms, drift_alert_ms
+        )),
+        (true, false) => Some(format!("Local clock is back within {} ms of {}", drift_alert_ms, host)),
+        _ => None,
+    }
+}
+
+fn alert(message: String) {
+    println!("{}", message);
+    if AppConfig::get().wechat.url.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        WechatClient::new().send("Clock drift", &message).await;
+    });
+}
+
+pub fn clock(host: &str) -> Option<HostClock> {
+    CLOCKS.lock().unwrap().get(host).copied()
+}
+
+/// Local time corrected by the host's offset, in milliseconds; local time until it is sampled.
+pub fn timestamp(host: &str) -> i64 {
+    now_timestamp() + clock(host).map(|clock| clock.offset_ms).unwrap_or(0)
+}
+
+pub fn recv_window(host: &str) -> u64 {
+    let base = AppConfig::get().clock.recv_window_ms;
+    clock(host).map(|clock| clock.recv_window(base)).unwrap_or(base)
+}
+
+/// Reads the server time of the client's host and updates its offset.
+pub async fn sample(client: &BinanceClient) -> Result<HostClock> {
+    let before = now_timestamp();
+    let server_time = client.server_time().await?;
+    let sample = HostClock::estimate(before, server_time, now_timestamp());
+    if let Some(message) = record(client.host(), sample, AppConfig::get().clock.drift_alert_ms) {
+        alert(message);
+    }
+    Ok(sample)
+}
+
+/// Samples the client's host the first time it signs a request; later samples are left to
+/// `start_sync`.
+pub async fn ensure_sampled(client: &BinanceClient) {
+    if clock(client.host()).is_some() {
+        return;
+    }
+    if let Err(err) = sample(client).await {
+        println!("Failed to read the server time of {}: {}", client.host(), err);
+    }
+}
+
+/// Re-samples every host a request has been signed for.
+pub async fn start_sync() {
+    let interval = Duration::from_secs(AppConfig::get().clock.sample_interval_secs);
+    loop {
+        tokio::time::sleep(interval).await;
+        let hosts: Vec<String> = CLOCKS.lock().unwrap().keys().cloned().collect();
+        for host in hosts {
+            if let Err(err) = sample(&BinanceClient::new_with_host(&host)).await {
+                println!("Failed to read the server time of {}: {}", host, err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clock, record, HostClock};
+
+    #[test]
+    fn test_estimate() {
+        let sample = HostClock::estimate(1_000, 1_550, 1_100);
+        assert_eq!(sample.offset_ms, 500);
+        assert_eq!(sample.rtt_ms, 100);
+        assert_eq!(sample.recv_window(5_000), 5_200);
+        assert_eq!(HostClock::estimate(0, 0, 40_000).recv_window(5_000), 60_000);
+    }
+
+    #[test]
+    fn test_drift_alert() {
+        let host = "https://clock.test";
+        assert_eq!(record(host, HostClock::estimate(0, 300, 0), 1_000), None);
+        assert!(record(host, HostClock::estimate(0, -1_500, 0), 1_000).unwrap().contains("-1500 ms"));
+        assert_eq!(record(host, HostClock::estimate(0, 1_200, 0), 1_000), None);
+        assert!(record(host, HostClock::estimate(0, 200, 0), 1_000).unwrap().contains("back within"));
+        assert_eq!(clock(host).unwrap().offset_ms, 200);
+    }
+}
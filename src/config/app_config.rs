@@ -0,0 +1,369 @@
+use std::{collections::HashMap, env, fs};
+
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+
+pub const DEFAULT_ACCOUNT: &str = "default";
+const DEFAULT_CONFIG_PATH: &str = "quanpass.toml";
+const ENV_PREFIX: &str = "QUANPASS_";
+const ACCOUNT_ENV_PREFIX: &str = "QUANPASS_ACCOUNT_";
+
+/// Never falls back to the defaults on a broken file, which would silently lift the risk limits.
+static APP_CONFIG: Lazy<Result<AppConfig, String>> =
+    Lazy::new(|| AppConfig::load().map_err(|err| format!("{:#}", err)));
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct AccountConfig {
+    #[serde(default)]
+    pub api_key: String,
+    #[serde(default)]
+    pub secret_key: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct WechatConfig {
+    #[serde(default)]
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct EmailConfig {
+    #[serde(default)]
+    pub smtp_host: String,
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default)]
+    pub password: String,
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        EmailConfig {
+            smtp_host: String::new(),
+            smtp_port: default_smtp_port(),
+            username: String::new(),
+            password: String::new(),
+        }
+    }
+}
+
+fn default_smtp_port() -> u16 {
+    465
+}
+
+/// Limits checked before an order is sent. Unset limits are not enforced.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct RiskLimits {
+    /// Largest position on a symbol across robots, long or short, in base asset, counting the
+    /// ordering robot's open orders.
+    pub max_position: Option<f64>,
+    /// Most open orders a robot may have on a symbol.
+    pub max_open_orders: Option<usize>,
+    /// Largest price × quantity of a single order, in quote asset.
+    pub max_order_notional: Option<f64>,
+    /// Largest drop of a robot's net PnL since UTC midnight, in quote asset.
+    pub max_daily_loss: Option<f64>,
+    /// Largest distance of the order price from the last trade price, as a fraction.
+    pub max_price_deviation: Option<f64>,
+}
+
+impl RiskLimits {
+    /// `self` with the limits set in `overrides` replaced.
+    fn merge(&self, overrides: &RiskLimits) -> RiskLimits {
+        RiskLimits {
+            max_position: overrides.max_position.or(self.max_position),
+            max_open_orders: overrides.max_open_orders.or(self.max_open_orders),
+            max_order_notional: overrides.max_order_notional.or(self.max_order_notional),
+            max_daily_loss: overrides.max_daily_loss.or(self.max_daily_loss),
+            max_price_deviation: overrides.max_price_deviation.or(self.max_price_deviation),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct RiskConfig {
+    #[serde(flatten)]
+    pub limits: RiskLimits,
+    /// Per-symbol overrides of `limits`.
+    #[serde(default)]
+    pub symbols: HashMap<String, RiskLimits>,
+    /// Suspend a robot whose order breaches a limit, besides blocking the order.
+    #[serde(default)]
+    pub suspend_on_breach: bool,
+}
+
+impl RiskConfig {
+    pub fn limits_for(&self, symbol: &str) -> RiskLimits {
+        match self.symbols.get(symbol) {
+            Some(overrides) => self.limits.merge(overrides),
+            None => self.limits.clone(),
+        }
+    }
+}
+
+/// Settings of the HTTP client shared by every Binance client.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct HttpConfig {
+    /// Time allowed for a whole request, response body included.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default = "default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+    /// Idle connections kept open per host.
+    #[serde(default = "default_max_idle_per_host")]
+    pub max_idle_per_host: usize,
+    /// Times a request is sent again after a transient failure.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u32,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            timeout_ms: default_timeout_ms(),
+            connect_timeout_ms: default_connect_timeout_ms(),
+            max_idle_per_host: default_max_idle_per_host(),
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_connect_timeout_ms() -> u64 {
+    3_000
+}
+
+fn default_max_idle_per_host() -> usize {
+    32
+}
+
+fn default_max_retries() -> u32 {
+    3
+}
+
+/// Settings of the server clock kept for signed requests.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ClockConfig {
+    /// How often each host's server time is sampled.
+    #[serde(default = "default_sample_interval_secs")]
+    pub sample_interval_secs: u64,
+    /// `recvWindow` of signed requests on a host with no latency; it grows with the round trip.
+    #[serde(default = "default_recv_window_ms")]
+    pub recv_window_ms: u64,
+    /// Alert once the local clock is this far from a host's.
+    #[serde(default = "default_drift_alert_ms")]
+    pub drift_alert_ms: u64,
+}
+
+impl Default for ClockConfig {
+    fn default() -> Self {
+        ClockConfig {
+            sample_interval_secs: default_sample_interval_secs(),
+            recv_window_ms: default_recv_window_ms(),
+            drift_alert_ms: default_drift_alert_ms(),
+        }
+    }
+}
+
+fn default_sample_interval_secs() -> u64 {
+    60
+}
+
+fn default_recv_window_ms() -> u64 {
+    5_000
+}
+
+fn default_drift_alert_ms() -> u64 {
+    1_000
+}
+
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub accounts: HashMap<String, AccountConfig>,
+    #[serde(default)]
+    pub wechat: WechatConfig,
+    #[serde(default)]
+    pub email: EmailConfig,
+    #[serde(default)]
+    pub risk: RiskConfig,
+    #[serde(default)]
+    pub http: HttpConfig,
+    #[serde(default)]
+    pub clock: ClockConfig,
+}
+
+impl AppConfig {
+    /// The loaded configuration. Panics when the file cannot be read as one; `main` checks
+    /// `try_get` before anything else runs.
+    pub fn get() -> &'static AppConfig {
+        match AppConfig::try_get() {
+            Ok(config) => config,
+            Err(err) => panic!("Failed to load config: {:#}", err),
+        }
+    }
+
+    pub fn try_get() -> Result<&'static AppConfig, anyhow::Error> {
+        APP_CONFIG.as_ref().map_err(|err| anyhow!("{}", err))
+    }
+
+    /// Reads the TOML file named by `QUANPASS_CONFIG` (default `quanpass.toml`), then applies
+    /// `QUANPASS_*` environment overrides on top. A missing file is not an error.
+    pub fn load() -> Result<AppConfig, anyhow::Error> {
+        let path = env::var("QUANPASS_CONFIG").unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+        let mut config = match fs::read_to_string(&path) {
+            Ok(content) => AppConfig::parse(&content)
+                .with_context(|| format!("invalid config file {}", path))?,
+            Err(_) => AppConfig::default(),
+        };
+        config.apply_env(env::vars());
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<AppConfig, anyhow::Error> {
+        Ok(toml::from_str(content)?)
+    }
+
+    /// Account overrides use `QUANPASS_ACCOUNT_<NAME>_API_KEY` / `_SECRET_KEY`, the profile
+    /// name being lowercased, so `QUANPASS_ACCOUNT_DEFAULT_API_KEY` sets `accounts.default`.
+    pub fn apply_env<I>(&mut self, vars: I)
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (key, value) in vars {
+            if let Some(rest) = key.strip_prefix(ACCOUNT_ENV_PREFIX) {
+                if let Some(name) = rest.strip_suffix("_API_KEY") {
+                    self.accounts.entry(name.to_lowercase()).or_default().api_key = value;
+                } else if let Some(name) = rest.strip_suffix("_SECRET_KEY") {
+                    self.accounts.entry(name.to_lowercase()).or_default().secret_key = value;
+                }
+                continue;
+            }
+            match key.strip_prefix(ENV_PREFIX) {
+                Some("WECHAT_URL") => self.wechat.url = value,
+                Some("SMTP_HOST") => self.email.smtp_host = value,
+                Some("SMTP_PORT") => {
+                    if let Ok(port) = value.parse() {
+                        self.email.smtp_port = port;
+                    }
+                }
+                Some("SMTP_USERNAME") => self.email.username = value,
+                Some("SMTP_PASSWORD") => self.email.password = value,
+                _ => {}
+            }
+        }
+    }
+
+    pub fn account(&self, name: &str) -> Option<&AccountConfig> {
+        self.accounts.get(name)
+    }
+
+    /// Checks that every account in `required` has credentials. Paper robots need none.
+    pub fn validate(&self, required: &[String]) -> Result<(), anyhow::Error> {
+        let mut missing: Vec<String> = required
+            .iter()
+            .filter(|name| match self.account(name) {
+                Some(account) => account.api_key.is_empty() || account.secret_key.is_empty(),
+                None => true,
+            })
+            .cloned()
+            .collect();
+        missing.sort();
+        missing.dedup();
+        if !missing.is_empty() {
+            bail!(
+                "missing Binance credentials for account(s): {}. Set [accounts.<name>] api_key/secret_key in {} or QUANPASS_ACCOUNT_<NAME>_API_KEY/_SECRET_KEY",
+                missing.join(", "),
+                DEFAULT_CONFIG_PATH
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AppConfig;
+
+    #[test]
+    fn test_parse_and_env_override() {
+        let mut config = AppConfig::parse(
+            r#"
+            [accounts.default]
+            api_key = "file-key"
+            secret_key = "file-secret"
+
+            [wechat]
+            url = "https://example.com/send"
+            "#,
+        )
+        .unwrap();
+        config.apply_env(vec![
+            (
+                "QUANPASS_ACCOUNT_DEFAULT_API_KEY".to_string(),
+                "env-key".to_string(),
+            ),
+            ("QUANPASS_ACCOUNT_SUB_API_KEY".to_string(), "sub-key".to_string()),
+            ("QUANPASS_SMTP_PORT".to_string(), "587".to_string()),
+        ]);
+
+        let default = config.account("default").unwrap();
+        assert_eq!(default.api_key, "env-key");
+        assert_eq!(default.secret_key, "file-secret");
+        assert_eq!(config.account("sub").unwrap().api_key, "sub-key");
+        assert_eq!(config.wechat.url, "https://example.com/send");
+        assert_eq!(config.email.smtp_port, 587);
+        assert_eq!(config.http.timeout_ms, 10_000);
+        assert_eq!(config.http.max_retries, 3);
+        assert_eq!(config.clock.recv_window_ms, 5_000);
+    }
+
+    #[test]
+    fn test_risk_limits() {
+        let config = AppConfig::parse(
+            r#"
+            [risk]
+            max_open_orders = 10
+            max_order_notional = 500.0
+            suspend_on_breach = true
+
+            [risk.symbols.BTCFDUSD]
+            max_position = 0.05
+            max_order_notional = 1000.0
+            "#,
+        )
+        .unwrap();
+        assert!(config.risk.suspend_on_breach);
+
+        let limits = config.risk.limits_for("BTCFDUSD");
+        assert_eq!(limits.max_open_orders, Some(10));
+        assert_eq!(limits.max_order_notional, Some(1000.0));
+        assert_eq!(limits.max_position, Some(0.05));
+
+        let limits = config.risk.limits_for("ETHFDUSD");
+        assert_eq!(limits.max_order_notional, Some(500.0));
+        assert_eq!(limits.max_position, None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut config = AppConfig::default();
+        assert!(config.validate(&[]).is_ok());
+        assert!(config.validate(&["default".to_string()]).is_err());
+
+        config.apply_env(vec![
+            ("QUANPASS_ACCOUNT_DEFAULT_API_KEY".to_string(), "k".to_string()),
+            ("QUANPASS_ACCOUNT_DEFAULT_SECRET_KEY".to_string(), "s".to_string()),
+        ]);
+        assert!(config.validate(&["default".to_string()]).is_ok());
+
+        let err = config.validate(&["sub".to_string()]).unwrap_err();
+        assert!(err.to_string().contains("sub"));
+    }
+}
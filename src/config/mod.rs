@@ -0,0 +1 @@
+pub mod app_config;
\ No newline at end of file
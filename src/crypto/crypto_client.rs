@@ -1,127 +1,358 @@
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
+use anyhow::bail;
+use async_trait::async_trait;
 use serde_json::Value;
 
 use crate::client::{
     binance_client::BinanceClient,
-    binance_domain::{CurrentPrice, Kline, Order, Ticker, Transaction},
+    binance_domain::{
+        AccountInformation, CanceledOrder, CurrentPrice, ExchangeInformation, Kline, Order,
+        Ticker, Trade, Transaction,
+    },
 };
+use crate::crypto::exchange::{
+    default_position_side, place_once, Balance, Exchange, ExchangeOrder, ExchangePosition, ExchangeTrade,
+    OrderRequest,
+};
+use crate::crypto::market_stream;
+use crate::crypto::paper_book::{PaperBook, PaperOrder};
+use crate::crypto::symbol_rules::{cached_rules, SymbolRules};
+use crate::crypto::trading_mode::TradingMode;
+use crate::ledger::pnl::split_symbol;
+use crate::util::binance_error::BinanceError;
 
 #[derive(Clone)]
 pub struct CryptoClient {
     binance_client: BinanceClient,
+    paper: Option<Arc<Mutex<PaperBook>>>,
 }
 
 impl CryptoClient {
     pub fn new() -> CryptoClient {
         CryptoClient {
             binance_client: BinanceClient::new(),
+            paper: None,
+        }
+    }
+
+    /// In paper mode only public market data endpoints are called; orders rest on a local book.
+    pub fn new_with_mode(account: &str, mode: TradingMode) -> CryptoClient {
+        CryptoClient {
+            binance_client: BinanceClient::new_with_account(account, mode.spot_host()),
+            paper: match mode {
+                TradingMode::Paper => Some(Arc::new(Mutex::new(PaperBook::default()))),
+                _ => None,
+            },
+        }
+    }
+
+    /// Paper client whose orders rest on `book`, which outlives the client.
+    pub fn new_paper(account: &str, book: Arc<Mutex<PaperBook>>) -> CryptoClient {
+        CryptoClient {
+            binance_client: BinanceClient::new_with_account(account, TradingMode::Paper.spot_host()),
+            paper: Some(book),
         }
     }
 
-    pub fn limit_buy(
+    fn place_paper(
         &self,
+        side: &str,
         symbol: &str,
-        quantity: f32,
+        quantity: f64,
         price: f64,
         client_order_id: &str,
-    ) -> Transaction {
+    ) -> Option<Transaction> {
+        self.paper.as_ref().map(|book| {
+            book.lock()
+                .unwrap()
+                .place(PaperOrder::new(
+                    symbol,
+                    side,
+                    "LIMIT",
+                    price,
+                    quantity,
+                    client_order_id,
+                ))
+                .to_transaction()
+        })
+    }
+
+    pub async fn limit_buy(
+        &self,
+        symbol: &str,
+        quantity: f64,
+        price: f64,
+        client_order_id: &str,
+    ) -> Result<Transaction, BinanceError> {
+        if let Some(transaction) = self.place_paper("BUY", symbol, quantity, price, client_order_id) {
+            return Ok(transaction);
+        }
+        let rules = self.symbol_rules(symbol).await?;
         let mut order_parameters: BTreeMap<String, String> = BTreeMap::new();
 
         order_parameters.insert("symbol".into(), symbol.to_owned());
         order_parameters.insert("side".into(), "BUY".to_owned());
         order_parameters.insert("timeInForce".into(), "GTC".to_owned());
-        order_parameters.insert("quantity".into(), quantity.to_string());
+        order_parameters.insert("quantity".into(), rules.format_quantity(quantity));
         order_parameters.insert("type".into(), "LIMIT".to_owned());
-        order_parameters.insert("price".into(), price.to_string());
+        order_parameters.insert("price".into(), rules.format_price(price));
         order_parameters.insert("newClientOrderId".into(), client_order_id.to_owned());
-        let request = self.binance_client.build_signed_request(order_parameters);
-        match self
-            .binance_client
+        let request = self.binance_client.build_request(order_parameters);
+        self.binance_client
             .post_signed("/api/v3/order", Some(request))
-        {
-            Ok(_transaction) => return _transaction,
-            Err(_err) => return Transaction::default(),
-        }
+            .await
     }
 
-    pub fn limit_sell(
+    pub async fn limit_sell(
         &self,
         symbol: &str,
-        quantity: f32,
+        quantity: f64,
         price: f64,
         client_order_id: &str,
-    ) -> Transaction {
+    ) -> Result<Transaction, BinanceError> {
+        if let Some(transaction) = self.place_paper("SELL", symbol, quantity, price, client_order_id) {
+            return Ok(transaction);
+        }
+        let rules = self.symbol_rules(symbol).await?;
         let mut order_parameters: BTreeMap<String, String> = BTreeMap::new();
 
         order_parameters.insert("symbol".into(), symbol.to_owned());
         order_parameters.insert("side".into(), "SELL".to_owned());
         order_parameters.insert("timeInForce".into(), "GTC".to_owned());
-        order_parameters.insert("quantity".into(), quantity.to_string());
+        order_parameters.insert("quantity".into(), rules.format_quantity(quantity));
         order_parameters.insert("type".into(), "LIMIT".to_owned());
-        order_parameters.insert("price".into(), price.to_string());
+        order_parameters.insert("price".into(), rules.format_price(price));
         order_parameters.insert("newClientOrderId".into(), client_order_id.to_owned());
-        let request = self.binance_client.build_signed_request(order_parameters);
-        match self
-            .binance_client
+        let request = self.binance_client.build_request(order_parameters);
+        self.binance_client
             .post_signed("/api/v3/order", Some(request))
-        {
-            Ok(_transaction) => return _transaction,
-            Err(_err) => return Transaction::default(),
-        }
+            .await
     }
 
-    pub fn open_orders(&self) -> Vec<Order> {
+    pub async fn open_orders(&self, symbol: &str) -> Result<Vec<Order>, BinanceError> {
+        if let Some(book) = &self.paper {
+            self.current_price(symbol).await?;
+            return Ok(book
+                .lock()
+                .unwrap()
+                .open_orders()
+                .iter()
+                .filter(|order| order.symbol == symbol)
+                .map(|order| order.to_order())
+                .collect());
+        }
         let mut parameters: BTreeMap<String, String> = BTreeMap::new();
-        parameters.insert("recvWindow".into(), "2000".to_owned());
+        parameters.insert("symbol".into(), symbol.into());
 
-        let request = self.binance_client.build_signed_request(parameters);
-        return self
-            .binance_client
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
             .get_signed("/api/v3/openOrders", Some(request))
-            .unwrap();
+            .await
     }
 
-    pub fn current_price(&self, symbol: &str) -> CurrentPrice {
+    pub async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<CanceledOrder, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .cancel(symbol, order_id)
+                .map(|order| order.to_canceled_order())
+                .ok_or_else(|| BinanceError::no_such_order(&order_id.to_string()));
+        }
         let mut parameters: BTreeMap<String, String> = BTreeMap::new();
         parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("orderId".into(), order_id.to_string());
+
         let request = self.binance_client.build_request(parameters);
-        match self
+        self.binance_client
+            .delete_signed("/api/v3/order", Some(request))
+            .await
+    }
+
+    pub async fn cancel_order_by_client_id(
+        &self,
+        symbol: &str,
+        client_order_id: &str,
+    ) -> Result<CanceledOrder, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .cancel_by_client_id(symbol, client_order_id)
+                .map(|order| order.to_canceled_order())
+                .ok_or_else(|| BinanceError::no_such_order(client_order_id));
+        }
+        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+        parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("origClientOrderId".into(), client_order_id.into());
+
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .delete_signed("/api/v3/order", Some(request))
+            .await
+    }
+
+    pub async fn cancel_all_orders(&self, symbol: &str) -> Result<Vec<CanceledOrder>, BinanceError> {
+        if let Some(book) = &self.paper {
+            return Ok(book
+                .lock()
+                .unwrap()
+                .cancel_all(symbol)
+                .iter()
+                .map(|order| order.to_canceled_order())
+                .collect());
+        }
+        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+        parameters.insert("symbol".into(), symbol.into());
+
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .delete_signed("/api/v3/openOrders", Some(request))
+            .await
+    }
+
+    pub async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<Order, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .find(symbol, client_order_id)
+                .map(|order| order.to_order())
+                .ok_or_else(|| BinanceError::no_such_order(client_order_id));
+        }
+        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+        parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("origClientOrderId".into(), client_order_id.into());
+
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .get_signed("/api/v3/order", Some(request))
+            .await
+    }
+
+    /// Orders of `symbol` in any state, from `from_order_id` on (at most 1000), or the latest ones.
+    pub async fn all_orders(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<Order>, BinanceError> {
+        if let Some(book) = &self.paper {
+            return Ok(book
+                .lock()
+                .unwrap()
+                .history(symbol, from_order_id)
+                .iter()
+                .map(|order| order.to_order())
+                .collect());
+        }
+        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+        parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("limit".into(), "1000".into());
+        if let Some(order_id) = from_order_id {
+            parameters.insert("orderId".into(), order_id.to_string());
+        }
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .get_signed("/api/v3/allOrders", Some(request))
+            .await
+    }
+
+    /// Fills of `symbol` from `from_trade_id` on (at most 1000), or the latest ones.
+    pub async fn my_trades(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>, BinanceError> {
+        if let Some(book) = &self.paper {
+            return Ok(book.lock().unwrap().trades(symbol, from_trade_id));
+        }
+        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+        parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("limit".into(), "1000".into());
+        if let Some(trade_id) = from_trade_id {
+            parameters.insert("fromId".into(), trade_id.to_string());
+        }
+        let request = self.binance_client.build_request(parameters);
+        let trades: Vec<Trade> = self
             .binance_client
-            .get("/api/v3/ticker/price", Some(request))
-        {
-            Ok(_price) => return _price,
-            Err(_err) => {
-                return CurrentPrice {
-                    symbol: symbol.to_owned(),
-                    price: 0.0,
-                }
+            .get_signed("/api/v3/myTrades", Some(request))
+            .await?;
+        Ok(trades.into_iter().map(|trade| trade.into()).collect())
+    }
+
+    pub async fn account(&self) -> Result<AccountInformation, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(AccountInformation {
+                can_trade: true,
+                balances: Vec::new(),
+            });
+        }
+        let parameters: BTreeMap<String, String> = BTreeMap::new();
+        let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .get_signed("/api/v3/account", Some(request))
+            .await
+    }
+
+    pub async fn exchange_info(&self) -> Result<ExchangeInformation, BinanceError> {
+        self.binance_client.get("/api/v3/exchangeInfo", None).await
+    }
+
+    /// Filters of `symbol`, from an exchangeInfo cached per host.
+    pub async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules, BinanceError> {
+        cached_rules(self.binance_client.host(), symbol, || self.exchange_info()).await
+    }
+
+    pub async fn current_price(&self, symbol: &str) -> Result<CurrentPrice, BinanceError> {
+        let price = match market_stream::price(self.binance_client.host(), symbol) {
+            Some(price) => CurrentPrice {
+                symbol: symbol.to_string(),
+                price,
+            },
+            None => {
+                let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+                parameters.insert("symbol".into(), symbol.into());
+                let request = self.binance_client.build_request(parameters);
+                self.binance_client.get("/api/v3/ticker/price", Some(request)).await?
             }
+        };
+        if let Some(book) = &self.paper {
+            self.fill_paper(book, symbol, price.price).await;
         }
+        Ok(price)
+    }
+
+    /// Fills the paper orders of `symbol` that `price` crosses, or that the 1m candles since the
+    /// last check went through, so a wick between two ticks is not missed.
+    async fn fill_paper(&self, book: &Mutex<PaperBook>, symbol: &str, price: f64) {
+        let from = book.lock().unwrap().candles_from(symbol);
+        let candles = match from {
+            Some(from) => self.klines(symbol, "1m", Some(1000), Some(from), None).await.unwrap_or_else(|err| {
+                println!("Failed to read the {} candles paper orders fill against: {}", symbol, err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        book.lock().unwrap().fill_candles(symbol, &candles, price);
     }
 
-    pub fn tickers(&self, ) -> Vec<Ticker> {
+    pub async fn tickers(&self) -> Result<Vec<Ticker>, BinanceError> {
         let parameters: BTreeMap<String, String> = BTreeMap::new();
         let request = self.binance_client.build_request(parameters);
-        match self
-            .binance_client
+        self.binance_client
             .get("/api/v3/ticker/24hr", Some(request))
-        {
-            Ok(_tickers) => return _tickers,
-            Err(_err) => 
-                return Vec::new()
-        }
-        
+            .await
     }
 
-    pub fn klines(
+    pub async fn klines(
         &self,
         symbol: &str,
         interval: &str,
         limit: Option<u16>,
         start_time: Option<u64>,
         end_time: Option<u64>,
-    ) -> Vec<Kline> {
+    ) -> Result<Vec<Kline>, BinanceError> {
+        // Only the latest candles are cached, ranges always go to REST.
+        let latest = start_time.is_none() && end_time.is_none();
+        if latest {
+            if let Some(klines) = market_stream::klines(self.binance_client.host(), symbol, interval, limit) {
+                return Ok(klines);
+            }
+        }
         let mut parameters: BTreeMap<String, String> = BTreeMap::new();
 
         parameters.insert("symbol".into(), symbol.into());
@@ -140,62 +371,301 @@ impl CryptoClient {
         let data: Vec<Vec<Value>> = self
             .binance_client
             .get("/api/v3/klines", Some(request))
-            .unwrap();
+            .await?;
 
-        return data
+        let klines = data
             .iter()
             .map(|row| row.try_into())
             .collect::<Result<Vec<Kline>, _>>()
-            .unwrap();
+            .map_err(|err| BinanceError::Decode(format!("{:?}", err)))?;
+        if latest {
+            market_stream::seed_klines(self.binance_client.host(), symbol, interval, &klines);
+        }
+        Ok(klines)
+    }
+}
+
+impl From<Order> for ExchangeOrder {
+    fn from(order: Order) -> Self {
+        ExchangeOrder {
+            order_id: order.order_id,
+            client_order_id: order.client_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.type_name,
+            price: order.price,
+            stop_price: order.stop_price,
+            orig_qty: order.orig_qty.parse().unwrap_or(0.0),
+            executed_qty: order.executed_qty.parse().unwrap_or(0.0),
+            status: order.status,
+            position_side: default_position_side(),
+            time: order.time,
+            update_time: order.update_time,
+        }
+    }
+}
+
+impl From<CanceledOrder> for ExchangeOrder {
+    fn from(order: CanceledOrder) -> Self {
+        ExchangeOrder {
+            order_id: order.order_id,
+            client_order_id: order.orig_client_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.type_name,
+            price: order.price,
+            stop_price: 0.0,
+            orig_qty: order.orig_qty,
+            executed_qty: order.executed_qty,
+            status: order.status,
+            position_side: default_position_side(),
+            time: order.transact_time,
+            update_time: order.transact_time,
+        }
+    }
+}
+
+impl From<Transaction> for ExchangeOrder {
+    fn from(transaction: Transaction) -> Self {
+        ExchangeOrder {
+            order_id: transaction.order_id,
+            client_order_id: transaction.client_order_id,
+            symbol: transaction.symbol,
+            side: transaction.side,
+            order_type: transaction.type_name,
+            price: transaction.price,
+            stop_price: transaction.stop_price,
+            orig_qty: transaction.orig_qty,
+            executed_qty: transaction.executed_qty,
+            status: transaction.status,
+            position_side: default_position_side(),
+            time: transaction.transact_time,
+            update_time: transaction.transact_time,
+        }
+    }
+}
+
+impl From<Trade> for ExchangeTrade {
+    fn from(trade: Trade) -> Self {
+        ExchangeTrade {
+            trade_id: trade.id,
+            order_id: trade.order_id,
+            symbol: trade.symbol,
+            side: if trade.is_buyer { "BUY" } else { "SELL" }.to_string(),
+            price: trade.price,
+            quantity: trade.qty,
+            quote_qty: trade.quote_qty,
+            commission: trade.commission,
+            commission_asset: trade.commission_asset,
+            realized_pnl: 0.0,
+            maker: trade.is_maker,
+            time: trade.time,
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for CryptoClient {
+    async fn current_price(&self, symbol: &str) -> Result<f64, anyhow::Error> {
+        Ok(CryptoClient::current_price(self, symbol).await?.price)
+    }
+
+    async fn klines(&self, symbol: &str, interval: &str, limit: Option<u16>) -> Result<Vec<Kline>, anyhow::Error> {
+        Ok(CryptoClient::klines(self, symbol, interval, limit, None, None).await?)
+    }
+
+    /// Snaps the request to the symbol's filters once, so every attempt sends the same order.
+    async fn place_order(&self, request: &OrderRequest) -> Result<ExchangeOrder, anyhow::Error> {
+        if request.order_type != "LIMIT" {
+            bail!("Unsupported spot order type {}", request.order_type);
+        }
+        let request = &CryptoClient::symbol_rules(self, &request.symbol).await?.normalize(request)?;
+        let place = || async {
+            let transaction = match request.side.as_str() {
+                "BUY" => self.limit_buy(
+                    &request.symbol,
+                    request.quantity,
+                    request.price,
+                    &request.client_order_id,
+                ).await,
+                _ => self.limit_sell(
+                    &request.symbol,
+                    request.quantity,
+                    request.price,
+                    &request.client_order_id,
+                ).await,
+            }?;
+            Ok(transaction.into())
+        };
+        let query = || async {
+            Ok(CryptoClient::query_order(self, &request.symbol, &request.client_order_id).await?.into())
+        };
+        Ok(place_once(&request.client_order_id, place, query).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<ExchangeOrder, anyhow::Error> {
+        Ok(CryptoClient::cancel_order(self, symbol, order_id).await?.into())
+    }
+
+    async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<ExchangeOrder, anyhow::Error> {
+        Ok(CryptoClient::query_order(self, symbol, client_order_id).await?.into())
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<ExchangeOrder>, anyhow::Error> {
+        Ok(CryptoClient::open_orders(self, symbol).await?
+            .into_iter()
+            .map(|order| order.into())
+            .collect())
+    }
+
+    async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules, anyhow::Error> {
+        Ok(CryptoClient::symbol_rules(self, symbol).await?)
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>, anyhow::Error> {
+        Ok(self
+            .account()
+            .await?
+            .balances
+            .into_iter()
+            .map(|balance| Balance {
+                asset: balance.asset,
+                free: balance.free,
+                locked: balance.locked,
+            })
+            .collect())
+    }
+
+    async fn order_history(
+        &self,
+        symbol: &str,
+        from_order_id: Option<u64>,
+    ) -> Result<Vec<ExchangeOrder>, anyhow::Error> {
+        Ok(self
+            .all_orders(symbol, from_order_id)
+            .await?
+            .into_iter()
+            .map(|order| order.into())
+            .collect())
+    }
+
+    async fn trade_history(
+        &self,
+        symbol: &str,
+        from_trade_id: Option<u64>,
+    ) -> Result<Vec<ExchangeTrade>, anyhow::Error> {
+        Ok(self.my_trades(symbol, from_trade_id).await?)
+    }
+
+    /// Spot holds no position as such: a paper book reports what its fills add up to, and a live
+    /// account the base asset it holds, free or locked, valued at the last price.
+    async fn positions(&self, symbol: &str) -> Result<Vec<ExchangePosition>, anyhow::Error> {
+        if let Some(book) = &self.paper {
+            return Ok(book.lock().unwrap().positions(symbol));
+        }
+        let (base, _) = split_symbol(symbol);
+        let quantity: f64 = self
+            .account()
+            .await?
+            .balances
+            .iter()
+            .filter(|balance| balance.asset == base)
+            .map(|balance| balance.free + balance.locked)
+            .sum();
+        if quantity == 0.0 {
+            return Ok(Vec::new());
+        }
+        let price = CryptoClient::current_price(self, symbol).await?.price;
+        Ok(vec![ExchangePosition {
+            symbol: symbol.to_string(),
+            position_side: default_position_side(),
+            quantity,
+            entry_price: price,
+            mark_price: price,
+            liquidation_price: None,
+            unrealized_pnl: 0.0,
+        }])
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::config::app_config::DEFAULT_ACCOUNT;
+    use crate::crypto::paper_book::PaperBook;
     use crate::util::string_util::generate_random_id;
 
     use super::CryptoClient;
 
-    #[test]
-    fn test_limit_sell_and_limit_buy() {
+    #[tokio::test]
+    async fn test_limit_sell_and_limit_buy() {
         let client = CryptoClient::new();
-        let transaction_buy = client.limit_buy("MANTAFDUSD", 10.0, 0.69, &generate_random_id());
-        let transaction_sell = client.limit_sell("MANTAFDUSD", 10.0, 0.72, &generate_random_id());
+        let transaction_buy = client.limit_buy("MANTAFDUSD", 10.0, 0.69, &generate_random_id()).await;
+        let transaction_sell = client.limit_sell("MANTAFDUSD", 10.0, 0.72, &generate_random_id()).await;
         println!("{:?}", transaction_buy);
         println!("{:?}", transaction_sell);
     }
 
-    #[test]
-    fn test_get_current_price() {
+    #[tokio::test]
+    async fn test_cancel_orders() {
+        let book = Arc::new(Mutex::new(PaperBook::default()));
+        let client = CryptoClient::new_paper(DEFAULT_ACCOUNT, book.clone());
+        for (price, client_order_id) in [(0.69, "a"), (0.68, "b"), (0.67, "c")] {
+            client.limit_buy("MANTAFDUSD", 10.0, price, client_order_id).await.unwrap();
+        }
+        client.limit_sell("BTCFDUSD", 1.0, 110.0, "other").await.unwrap();
+
+        let canceled = client.cancel_order_by_client_id("MANTAFDUSD", "a").await.unwrap();
+        assert_eq!(canceled.status, "CANCELED");
+        assert!(client.cancel_order_by_client_id("MANTAFDUSD", "a").await.is_err());
+        assert_eq!(book.lock().unwrap().open_orders().len(), 3);
+
+        assert_eq!(client.cancel_all_orders("MANTAFDUSD").await.unwrap().len(), 2);
+        let book = book.lock().unwrap();
+        for client_order_id in ["a", "b", "c"] {
+            assert_eq!(book.find("MANTAFDUSD", client_order_id).unwrap().status, "CANCELED");
+        }
+        let open = book.open_orders();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].client_order_id, "other");
+    }
+
+    #[tokio::test]
+    async fn test_get_current_price() {
         let client = CryptoClient::new();
-        let result = client.current_price("PORTALFDUSD");
+        let result = client.current_price("PORTALFDUSD").await;
         println!("{:?}", result);
     }
 
-    #[test]
-    fn test_get_open_orders() {
+    #[tokio::test]
+    async fn test_get_open_orders() {
         let client = CryptoClient::new();
-        client.open_orders().iter().for_each(|order| {
+        client.open_orders("MANTAFDUSD").await.unwrap_or_default().iter().for_each(|order| {
             println!("{:?}", order);
         });
     }
 
-    #[test]
-    fn test_get_klines() {
+    #[tokio::test]
+    async fn test_get_klines() {
         let client = CryptoClient::new();
         client
             .klines("GALAFDUSD", "1d", Some(30), None, None)
+            .await
+            .unwrap_or_default()
             .iter()
             .for_each(|kline| {
                 println!("{:?}", kline);
             })
     }
 
-    #[test]
-    fn test_get_tickers() {
+    #[tokio::test]
+    async fn test_get_tickers() {
         let client = CryptoClient::new();
         client
             .tickers()
+            .await
+            .unwrap_or_default()
             .iter()
             .filter(|ticker| ticker.symbol.contains("FDUSD"))
             .for_each(|ticker| {
@@ -0,0 +1,314 @@
+use std::future::Future;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::client::binance_client::backoff;
+use crate::client::binance_domain::Kline;
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::util::binance_error::BinanceError;
+
+/// Most times an order is sent while its earlier attempts stay unaccounted for.
+const ORDER_ATTEMPTS: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderRequest {
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub price: f64,
+    pub quantity: f64,
+    pub stop_price: Option<f64>,
+    pub client_order_id: String,
+    /// `LONG` or `SHORT` in futures hedge mode, unset in one-way mode and on spot.
+    pub position_side: Option<String>,
+    /// Futures only: the order may only shrink the position. Not allowed in hedge mode.
+    pub reduce_only: bool,
+    /// Futures stop and take-profit market orders only: close the whole position, in place of
+    /// a quantity.
+    pub close_position: bool,
+    /// `GTC` unless set; `GTX` makes a futures order post-only.
+    pub time_in_force: Option<String>,
+}
+
+impl OrderRequest {
+    pub fn limit(symbol: &str, side: &str, price: f64, quantity: f64, client_order_id: &str) -> Self {
+        OrderRequest {
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            order_type: "LIMIT".to_string(),
+            price,
+            quantity,
+            stop_price: None,
+            client_order_id: client_order_id.to_string(),
+            position_side: None,
+            reduce_only: false,
+            close_position: false,
+            time_in_force: None,
+        }
+    }
+
+    /// A market order of `order_type`, e.g. `STOP_MARKET` or `TAKE_PROFIT_MARKET`, sent once
+    /// the price reaches `stop_price`.
+    pub fn triggered(
+        symbol: &str,
+        side: &str,
+        order_type: &str,
+        stop_price: f64,
+        quantity: f64,
+        client_order_id: &str,
+    ) -> Self {
+        let mut request = OrderRequest::limit(symbol, side, 0.0, quantity, client_order_id);
+        request.order_type = order_type.to_string();
+        request.stop_price = Some(stop_price);
+        request
+    }
+
+    pub fn position_side(mut self, position_side: &str) -> Self {
+        self.position_side = Some(position_side.to_string());
+        self
+    }
+
+    pub fn reduce_only(mut self) -> Self {
+        self.reduce_only = true;
+        self
+    }
+
+    pub fn close_position(mut self) -> Self {
+        self.close_position = true;
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: &str) -> Self {
+        self.time_in_force = Some(time_in_force.to_string());
+        self
+    }
+
+    pub fn client_order_id(mut self, client_order_id: &str) -> Self {
+        self.client_order_id = client_order_id.to_string();
+        self
+    }
+}
+
+/// Places an order without ever placing it twice. When an attempt fails without telling
+/// whether the order went through (timeout, 5xx, dropped connection), or is refused as a
+/// duplicate, the order is looked up by its client order id and only sent again if Binance does
+/// not know it. This relies on the id being new to this order, see `next_client_order_id`.
+/// Orders without a client order id are sent once.
+pub async fn place_once<P, PF, Q, QF>(
+    client_order_id: &str,
+    place: P,
+    query: Q,
+) -> std::result::Result<ExchangeOrder, BinanceError>
+where
+    P: Fn() -> PF,
+    PF: Future<Output = std::result::Result<ExchangeOrder, BinanceError>>,
+    Q: Fn() -> QF,
+    QF: Future<Output = std::result::Result<ExchangeOrder, BinanceError>>,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let err = match place().await {
+            Ok(order) => return Ok(order),
+            Err(err) if !client_order_id.is_empty() && (err.is_transient() || err.is_duplicate_order()) => err,
+            Err(err) => return Err(err),
+        };
+        tokio::time::sleep(backoff(attempt)).await;
+        match query().await {
+            Ok(order) => return Ok(order),
+            Err(query_err) if query_err.is_unknown_order() && attempt < ORDER_ATTEMPTS => {
+                println!("Order {} was not placed, sending it again after: {}", client_order_id, err);
+            }
+            Err(_) => return Err(err),
+        }
+    }
+}
+
+/// Venue-neutral view of an order, whatever the exchange returned for it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeOrder {
+    pub order_id: u64,
+    pub client_order_id: String,
+    pub symbol: String,
+    pub side: String,
+    pub order_type: String,
+    pub price: f64,
+    /// Trigger price of stop and take-profit orders, 0 for the others.
+    #[serde(default)]
+    pub stop_price: f64,
+    pub orig_qty: f64,
+    pub executed_qty: f64,
+    pub status: String,
+    /// `BOTH` on spot and in futures one-way mode.
+    #[serde(default = "default_position_side")]
+    pub position_side: String,
+    /// Creation and last update time, in milliseconds.
+    #[serde(default)]
+    pub time: u64,
+    #[serde(default)]
+    pub update_time: u64,
+}
+
+pub fn default_position_side() -> String {
+    "BOTH".to_string()
+}
+
+/// Whether an order in `status` left the book without filling.
+pub fn is_closed_unfilled(status: &str) -> bool {
+    matches!(status, "CANCELED" | "EXPIRED" | "EXPIRED_IN_MATCH" | "REJECTED")
+}
+
+/// Venue-neutral view of a fill.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangeTrade {
+    pub trade_id: u64,
+    pub order_id: u64,
+    pub symbol: String,
+    pub side: String,
+    pub price: f64,
+    pub quantity: f64,
+    pub quote_qty: f64,
+    pub commission: f64,
+    pub commission_asset: String,
+    /// Reported by futures venues only.
+    pub realized_pnl: f64,
+    pub maker: bool,
+    pub time: u64,
+}
+
+/// Venue-neutral view of a derivatives position.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExchangePosition {
+    pub symbol: String,
+    /// `BOTH` in one-way mode, `LONG` or `SHORT` in hedge mode.
+    pub position_side: String,
+    /// Negative when short.
+    pub quantity: f64,
+    pub entry_price: f64,
+    pub mark_price: f64,
+    /// `None` when the venue reports none, as for flat positions or paper books.
+    pub liquidation_price: Option<f64>,
+    pub unrealized_pnl: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Balance {
+    pub asset: String,
+    pub free: f64,
+    pub locked: f64,
+}
+
+/// What a strategy needs from a trading venue. Binance spot and futures clients implement it,
+/// as does the in-memory `MockExchange` the backtester replays candles against.
+#[async_trait]
+pub trait Exchange: Send + Sync {
+    async fn current_price(&self, symbol: &str) -> Result<f64>;
+    async fn klines(&self, symbol: &str, interval: &str, limit: Option<u16>) -> Result<Vec<Kline>>;
+    async fn place_order(&self, request: &OrderRequest) -> Result<ExchangeOrder>;
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<ExchangeOrder>;
+    async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<ExchangeOrder>;
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<ExchangeOrder>>;
+    async fn balances(&self) -> Result<Vec<Balance>>;
+    async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules>;
+    /// Orders of `symbol` in any state, from `from_order_id` on, or the most recent ones.
+    async fn order_history(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<ExchangeOrder>>;
+    /// Fills of `symbol`, from `from_trade_id` on, or the most recent ones.
+    async fn trade_history(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>>;
+
+    /// Open positions of `symbol`. Venues without positions report none.
+    async fn positions(&self, _symbol: &str) -> Result<Vec<ExchangePosition>> {
+        Ok(Vec::new())
+    }
+
+    /// Only meaningful on derivatives venues.
+    async fn change_leverage(&self, _symbol: &str, _leverage: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Switches between hedge (dual-side) and one-way position mode, if not already in it. Only
+    /// meaningful on derivatives venues.
+    async fn set_hedge_mode(&self, _hedge: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::util::binance_error::BinanceError;
+
+    use super::{place_once, ExchangeOrder};
+
+    fn order(client_order_id: &str) -> ExchangeOrder {
+        ExchangeOrder {
+            order_id: 1,
+            client_order_id: client_order_id.to_string(),
+            symbol: "BTCFDUSD".to_string(),
+            side: "BUY".to_string(),
+            order_type: "LIMIT".to_string(),
+            price: 100.0,
+            stop_price: 0.0,
+            orig_qty: 1.0,
+            executed_qty: 0.0,
+            status: "NEW".to_string(),
+            position_side: "BOTH".to_string(),
+            time: 0,
+            update_time: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_place_once() {
+        // The first attempt timed out but reached Binance: the order is found, not sent again.
+        let sent = Mutex::new(0);
+        let placed = place_once(
+            "tag-1",
+            || async {
+                *sent.lock().unwrap() += 1;
+                Err(BinanceError::Timeout("operation timed out".to_string()))
+            },
+            || async { Ok(order("tag-1")) },
+        )
+        .await;
+        assert_eq!(placed.unwrap().client_order_id, "tag-1");
+        assert_eq!(*sent.lock().unwrap(), 1);
+
+        // Binance never saw it: sent again.
+        let sent = Mutex::new(0);
+        let placed = place_once(
+            "tag-2",
+            || async {
+                let mut sent = sent.lock().unwrap();
+                *sent += 1;
+                match *sent {
+                    1 => Err(BinanceError::Server {
+                        status: 503,
+                        msg: "Service Unavailable".to_string(),
+                    }),
+                    _ => Ok(order("tag-2")),
+                }
+            },
+            || async { Err(BinanceError::no_such_order("tag-2")) },
+        )
+        .await;
+        assert!(placed.is_ok());
+        assert_eq!(*sent.lock().unwrap(), 2);
+
+        // Rejections are final.
+        let placed = place_once(
+            "tag-3",
+            || async {
+                Err(BinanceError::FilterViolation {
+                    code: -1013,
+                    msg: "Filter failure: PRICE_FILTER".to_string(),
+                })
+            },
+            || async { Ok(order("tag-3")) },
+        )
+        .await;
+        assert!(matches!(placed, Err(BinanceError::FilterViolation { .. })));
+    }
+}
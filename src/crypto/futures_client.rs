@@ -1,26 +1,52 @@
 use crate::{
     client::{
         binance_client::BinanceClient,
-        binance_domain::{CurrentPrice, FutureTransaction, Kline, Transaction},
+        binance_domain::{
+            CancelAllResponse, CurrentPrice, ExchangeInformation, FutureAccount, FutureBalance,
+            FutureCanceledOrder, FutureTrade, FutureTransaction, Kline, PositionMode, PositionRisk,
+        },
     },
     util::json_util::string_or_float,
 };
-use anyhow::{bail, Result};
+use crate::crypto::exchange::{
+    default_position_side, place_once, Balance, Exchange, ExchangeOrder, ExchangePosition, ExchangeTrade,
+    OrderRequest,
+};
+use crate::crypto::market_stream;
+use crate::crypto::paper_book::{PaperBook, PaperOrder};
+use crate::crypto::symbol_rules::{cached_rules, SymbolRules};
+use crate::crypto::trading_mode::TradingMode;
+use crate::util::binance_error::BinanceError;
+use anyhow::Result;
+use async_trait::async_trait;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
 
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Order {
     pub order_id: i64,
+    #[serde(default)]
+    pub client_order_id: String,
     pub symbol: String,
     pub status: String,
     #[serde(with = "string_or_float")]
     pub price: f64,
+    #[serde(with = "string_or_float", default)]
+    pub stop_price: f64,
+    #[serde(with = "string_or_float", default)]
+    pub orig_qty: f64,
+    #[serde(with = "string_or_float", default)]
+    pub executed_qty: f64,
+    #[serde(rename = "type", default)]
+    pub type_name: String,
     pub time_in_force: String,
     pub side: String,
     pub position_side: String,
+    #[serde(default)]
+    pub time: i64,
     pub update_time: i64,
 }
 
@@ -37,58 +63,106 @@ pub struct Future {
 #[derive(Clone)]
 pub struct FutureClient {
     binance_client: BinanceClient,
+    paper: Option<Arc<Mutex<PaperBook>>>,
 }
 
 impl FutureClient {
     pub fn new() -> FutureClient {
         FutureClient {
             binance_client: BinanceClient::new_with_host("https://fapi.binance.com"),
+            paper: None,
         }
     }
 
-    pub fn change_leverage(&self, symbol: &str, leverage: u8) -> Result<Value> {
-        let mut parameters: BTreeMap<String, String> = BTreeMap::new();
-        parameters.insert("symbol".into(), symbol.into());
-        parameters.insert("leverage".into(), leverage.to_string());
+    pub fn new_with_mode(account: &str, mode: TradingMode) -> FutureClient {
+        FutureClient {
+            binance_client: BinanceClient::new_with_account(account, mode.futures_host()),
+            paper: match mode {
+                TradingMode::Paper => Some(Arc::new(Mutex::new(PaperBook::default()))),
+                _ => None,
+            },
+        }
+    }
 
-        let request = self.binance_client.build_signed_request(parameters);
-        match self
-            .binance_client
-            .post_signed("/fapi/v1/leverage", Some(request))
-        {
-            Ok(response) => Ok(response),
-            Err(err) => {
-                println!("{:?}", err);
-                bail!("Failed to change leverage")
-            }
+    /// Paper client whose orders rest on `book`, which outlives the client.
+    pub fn new_paper(account: &str, book: Arc<Mutex<PaperBook>>) -> FutureClient {
+        FutureClient {
+            binance_client: BinanceClient::new_with_account(account, TradingMode::Paper.futures_host()),
+            paper: Some(book),
         }
     }
 
-    pub fn current_price(&self, symbol: &str) -> Result<CurrentPrice, anyhow::Error> {
+    pub async fn change_leverage(&self, symbol: &str, leverage: u8) -> Result<Value, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(json!({ "symbol": symbol, "leverage": leverage }));
+        }
         let mut parameters: BTreeMap<String, String> = BTreeMap::new();
         parameters.insert("symbol".into(), symbol.into());
+        parameters.insert("leverage".into(), leverage.to_string());
+
         let request = self.binance_client.build_request(parameters);
+        self.binance_client
+            .post_signed("/fapi/v1/leverage", Some(request))
+            .await
+    }
 
-        match self
-            .binance_client
-            .get("/fapi/v1/ticker/price", Some(request))
-        {
-            Ok(current_price) => Ok(current_price),
-            Err(err) => {
-                println!("{:?}", err);
-                bail!("Failed to get future current price")
+    pub async fn exchange_info(&self) -> Result<ExchangeInformation, BinanceError> {
+        self.binance_client.get("/fapi/v1/exchangeInfo", None).await
+    }
+
+    /// Filters of `symbol`, from an exchangeInfo cached per host.
+    pub async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules, BinanceError> {
+        cached_rules(self.binance_client.host(), symbol, || self.exchange_info()).await
+    }
+
+    pub async fn current_price(&self, symbol: &str) -> Result<CurrentPrice, BinanceError> {
+        let current_price = match market_stream::price(self.binance_client.host(), symbol) {
+            Some(price) => CurrentPrice {
+                symbol: symbol.to_string(),
+                price,
+            },
+            None => {
+                let mut parameters: BTreeMap<String, String> = BTreeMap::new();
+                parameters.insert("symbol".into(), symbol.into());
+                let request = self.binance_client.build_request(parameters);
+                self.binance_client.get("/fapi/v1/ticker/price", Some(request)).await?
             }
+        };
+        if let Some(book) = &self.paper {
+            self.fill_paper(book, symbol, current_price.price).await;
         }
+        Ok(current_price)
+    }
+
+    /// Fills the paper orders of `symbol` that `price` crosses, or that the 1m candles since the
+    /// last check went through, so a wick between two ticks is not missed.
+    async fn fill_paper(&self, book: &Mutex<PaperBook>, symbol: &str, price: f64) {
+        let from = book.lock().unwrap().candles_from(symbol);
+        let candles = match from {
+            Some(from) => self.kline(symbol, "1m", Some(1000), Some(from), None).await.unwrap_or_else(|err| {
+                println!("Failed to read the {} candles paper orders fill against: {}", symbol, err);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        book.lock().unwrap().fill_candles(symbol, &candles, price);
     }
 
-    pub fn kline(
+    pub async fn kline(
         &self,
         symbol: &str,
         interval: &str,
         limit: Option<u16>,
         start_time: Option<u64>,
         end_time: Option<u64>,
-    ) -> Result<Vec<Kline>, anyhow::Error> {
+    ) -> Result<Vec<Kline>, BinanceError> {
+        // Only the latest candles are cached, ranges always go to REST.
+        let latest = start_time.is_none() && end_time.is_none();
+        if latest {
+            if let Some(klines) = market_stream::klines(self.binance_client.host(), symbol, interval, limit) {
+                return Ok(klines);
+            }
+        }
         let mut parameters: BTreeMap<String, String> = BTreeMap::new();
 
         parameters.insert("symbol".into(), symbol.into());
@@ -107,25 +181,20 @@ impl FutureClient {
         let data: Vec<Vec<Value>> = self
             .binance_client
             .get("/fapi/v1/klines", Some(request))
-            .map_err(|err| {
-                println!("{:?}", err);
-                anyhow::anyhow!("Failed to get kline data")
-            })?;
-        println!("{:?}", data);
+            .await?;
 
         let klines = data
             .iter()
             .map(|row| row.try_into())
             .collect::<Result<Vec<Kline>, _>>()
-            .map_err(|err| {
-                println!("{:?}", err);
-                anyhow::anyhow!("Failed to parse kline data")
-            })?;
-
+            .map_err(|err| BinanceError::Decode(format!("{:?}", err)))?;
+        if latest {
+            market_stream::seed_klines(self.binance_client.host(), symbol, interval, &klines);
+        }
         Ok(klines)
     }
 
-    pub fn place_order(
+    pub async fn place_order(
         &self,
         symbol: String,
         side: String,
@@ -133,52 +202,501 @@ impl FutureClient {
         price: f64,
         quantity: f64,
         stop_price: Option<f64>,
-    ) -> Result<FutureTransaction, anyhow::Error> {
-        let mut params: BTreeMap<String, _> = BTreeMap::new();
-        params.insert("symbol".to_owned(), symbol);
-        params.insert("side".to_owned(), side);
-        params.insert("type".to_owned(), order_type);
-        params.insert("price".to_owned(), price.to_string());
-        params.insert("quantity".to_owned(), quantity.to_string());
-        params.insert("timeInForce".to_owned(), "GTC".to_owned());
-        if let Some(stop_price) = stop_price {
-            params.insert("stopPrice".into(), stop_price.to_string());
-        }
-        let request = self
-            .binance_client
-            .build_future_signed_request(params, 5000);
-        match self
-            .binance_client
+    ) -> Result<FutureTransaction, BinanceError> {
+        let mut request = OrderRequest::limit(&symbol, &side, price, quantity, "");
+        request.order_type = order_type;
+        request.stop_price = stop_price;
+        self.submit_order(&request).await
+    }
+
+    pub async fn submit_order(&self, request: &OrderRequest) -> Result<FutureTransaction, BinanceError> {
+        if let Some(book) = &self.paper {
+            let mut order = PaperOrder::new(
+                &request.symbol,
+                &request.side,
+                &request.order_type,
+                request.price,
+                request.quantity,
+                &request.client_order_id,
+            );
+            order.stop_price = request.stop_price.unwrap_or(0.0);
+            order.position_side = request.position_side.clone().unwrap_or(default_position_side());
+            order.reduce_only = request.reduce_only;
+            return Ok(book.lock().unwrap().place(order).to_future_transaction());
+        }
+        let rules = self.symbol_rules(&request.symbol).await?;
+        let params = order_params(request, &rules);
+        let request = self.binance_client.build_request(params);
+        self.binance_client
             .post_signed("/fapi/v1/order", Some(request))
-        {
-            Ok(transaction) => Ok(transaction),
-            Err(err) => bail!("place open orders failed, {}", err),
+            .await
+    }
+
+    /// Whether the account is in hedge (dual-side) position mode. Paper books are one-way.
+    pub async fn position_mode(&self) -> Result<PositionMode, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(PositionMode::default());
+        }
+        let params: BTreeMap<String, String> = BTreeMap::new();
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v1/positionSide/dual", Some(request))
+            .await
+    }
+
+    /// Binance refuses the change while the account has open positions or orders.
+    pub async fn change_position_mode(&self, dual_side_position: bool) -> Result<Value, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(json!({ "code": 200, "msg": "success" }));
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("dualSidePosition".to_owned(), dual_side_position.to_string());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .post_signed("/fapi/v1/positionSide/dual", Some(request))
+            .await
+    }
+
+    pub async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<Order, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .find(symbol, client_order_id)
+                .map(|order| order.to_future_order())
+                .ok_or_else(|| BinanceError::no_such_order(client_order_id));
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        params.insert("origClientOrderId".to_owned(), client_order_id.to_owned());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v1/order", Some(request))
+            .await
+    }
+
+    pub async fn open_orders(&self, symbol: &str) -> Result<Vec<Order>, BinanceError> {
+        if let Some(book) = &self.paper {
+            self.current_price(symbol).await?;
+            return Ok(book
+                .lock()
+                .unwrap()
+                .open_orders()
+                .iter()
+                .filter(|order| order.symbol == symbol)
+                .map(|order| order.to_future_order())
+                .collect());
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v1/openOrders", Some(request))
+            .await
+    }
+
+    /// Orders of `symbol` in any state, from `from_order_id` on (at most 1000), or the latest ones.
+    pub async fn all_orders(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<Order>, BinanceError> {
+        if let Some(book) = &self.paper {
+            return Ok(book
+                .lock()
+                .unwrap()
+                .history(symbol, from_order_id)
+                .iter()
+                .map(|order| order.to_future_order())
+                .collect());
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        params.insert("limit".to_owned(), "1000".to_owned());
+        if let Some(order_id) = from_order_id {
+            params.insert("orderId".to_owned(), order_id.to_string());
+        }
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v1/allOrders", Some(request))
+            .await
+    }
+
+    /// Fills of `symbol` from `from_trade_id` on (at most 1000), or the latest ones.
+    pub async fn user_trades(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>, BinanceError> {
+        if let Some(book) = &self.paper {
+            return Ok(book.lock().unwrap().trades(symbol, from_trade_id));
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        params.insert("limit".to_owned(), "1000".to_owned());
+        if let Some(trade_id) = from_trade_id {
+            params.insert("fromId".to_owned(), trade_id.to_string());
+        }
+        let request = self.binance_client.build_request(params);
+        let trades: Vec<FutureTrade> = self
+            .binance_client
+            .get_signed("/fapi/v1/userTrades", Some(request))
+            .await?;
+        Ok(trades.into_iter().map(|trade| trade.into()).collect())
+    }
+
+    pub async fn balances(&self) -> Result<Vec<FutureBalance>, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(Vec::new());
+        }
+        let params: BTreeMap<String, String> = BTreeMap::new();
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v2/balance", Some(request))
+            .await
+    }
+
+    /// Positions of `symbol`, or of every symbol. Paper books report one net position.
+    pub async fn position_risk(&self, symbol: Option<&str>) -> Result<Vec<PositionRisk>, BinanceError> {
+        if let Some(book) = &self.paper {
+            let book = book.lock().unwrap();
+            let symbols = match symbol {
+                Some(symbol) => vec![symbol.to_string()],
+                None => book.symbols(),
+            };
+            return Ok(symbols
+                .iter()
+                .flat_map(|symbol| book.positions(symbol))
+                .map(|position| position.into())
+                .collect());
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        if let Some(symbol) = symbol {
+            params.insert("symbol".to_owned(), symbol.to_owned());
+        }
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v2/positionRisk", Some(request))
+            .await
+    }
+
+    /// Balance and margin totals of the account. Paper books have no margin account.
+    pub async fn account(&self) -> Result<FutureAccount, BinanceError> {
+        if self.paper.is_some() {
+            return Ok(FutureAccount::default());
+        }
+        let params: BTreeMap<String, String> = BTreeMap::new();
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .get_signed("/fapi/v2/account", Some(request))
+            .await
+    }
+
+    pub async fn cancel_order(&self, symbol: &str, order_id: i64) -> Result<FutureCanceledOrder, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .cancel(symbol, order_id as u64)
+                .map(|order| order.to_future_canceled_order())
+                .ok_or_else(|| BinanceError::no_such_order(&order_id.to_string()));
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        params.insert("orderId".to_owned(), order_id.to_string());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .delete_signed("/fapi/v1/order", Some(request))
+            .await
+    }
+
+    pub async fn cancel_order_by_client_id(
+        &self,
+        symbol: &str,
+        client_order_id: &str,
+    ) -> Result<FutureCanceledOrder, BinanceError> {
+        if let Some(book) = &self.paper {
+            return book
+                .lock()
+                .unwrap()
+                .cancel_by_client_id(symbol, client_order_id)
+                .map(|order| order.to_future_canceled_order())
+                .ok_or_else(|| BinanceError::no_such_order(client_order_id));
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        params.insert("origClientOrderId".to_owned(), client_order_id.to_owned());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .delete_signed("/fapi/v1/order", Some(request))
+            .await
+    }
+
+    pub async fn cancel_all_orders(&self, symbol: &str) -> Result<CancelAllResponse, BinanceError> {
+        if let Some(book) = &self.paper {
+            book.lock().unwrap().cancel_all(symbol);
+            return Ok(CancelAllResponse {
+                code: 200,
+                msg: "The operation of cancel all open order is done.".to_string(),
+            });
+        }
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".to_owned(), symbol.to_owned());
+        let request = self.binance_client.build_request(params);
+        self.binance_client
+            .delete_signed("/fapi/v1/allOpenOrders", Some(request))
+            .await
+    }
+}
+
+/// Params of `POST /fapi/v1/order`, with prices and quantities written to the symbol's tick and
+/// step size. Market types take no price or time in force, and orders closing the whole position
+/// no quantity.
+fn order_params(request: &OrderRequest, rules: &SymbolRules) -> BTreeMap<String, String> {
+    let mut params: BTreeMap<String, String> = BTreeMap::new();
+    params.insert("symbol".to_owned(), request.symbol.clone());
+    params.insert("side".to_owned(), request.side.clone());
+    params.insert("type".to_owned(), request.order_type.clone());
+    if !request.order_type.ends_with("MARKET") {
+        params.insert("price".to_owned(), rules.format_price(request.price));
+        let time_in_force = request.time_in_force.clone().unwrap_or("GTC".to_owned());
+        params.insert("timeInForce".to_owned(), time_in_force);
+    }
+    if request.close_position {
+        params.insert("closePosition".to_owned(), "true".to_owned());
+    } else {
+        params.insert("quantity".to_owned(), rules.format_quantity(request.quantity));
+    }
+    if let Some(stop_price) = request.stop_price {
+        params.insert("stopPrice".into(), rules.format_price(stop_price));
+    }
+    if let Some(position_side) = &request.position_side {
+        params.insert("positionSide".into(), position_side.clone());
+    }
+    if request.reduce_only {
+        params.insert("reduceOnly".into(), "true".to_owned());
+    }
+    if !request.client_order_id.is_empty() {
+        params.insert("newClientOrderId".into(), request.client_order_id.clone());
+    }
+    params
+}
+
+impl From<Order> for ExchangeOrder {
+    fn from(order: Order) -> Self {
+        ExchangeOrder {
+            order_id: order.order_id as u64,
+            client_order_id: order.client_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.type_name,
+            price: order.price,
+            stop_price: order.stop_price,
+            orig_qty: order.orig_qty,
+            executed_qty: order.executed_qty,
+            status: order.status,
+            position_side: order.position_side,
+            time: order.time as u64,
+            update_time: order.update_time as u64,
+        }
+    }
+}
+
+impl From<FutureCanceledOrder> for ExchangeOrder {
+    fn from(order: FutureCanceledOrder) -> Self {
+        ExchangeOrder {
+            order_id: order.order_id as u64,
+            client_order_id: order.client_order_id,
+            symbol: order.symbol,
+            side: order.side,
+            order_type: order.type_name,
+            price: order.price,
+            stop_price: order.stop_price,
+            orig_qty: order.orig_qty,
+            executed_qty: order.executed_qty,
+            status: order.status,
+            position_side: order.position_side,
+            time: order.update_time as u64,
+            update_time: order.update_time as u64,
+        }
+    }
+}
+
+impl From<FutureTrade> for ExchangeTrade {
+    fn from(trade: FutureTrade) -> Self {
+        ExchangeTrade {
+            trade_id: trade.id,
+            order_id: trade.order_id,
+            symbol: trade.symbol,
+            side: trade.side,
+            price: trade.price,
+            quantity: trade.qty,
+            quote_qty: trade.quote_qty,
+            commission: trade.commission,
+            commission_asset: trade.commission_asset,
+            realized_pnl: trade.realized_pnl,
+            maker: trade.maker,
+            time: trade.time,
+        }
+    }
+}
+
+impl From<PositionRisk> for ExchangePosition {
+    fn from(position: PositionRisk) -> Self {
+        ExchangePosition {
+            symbol: position.symbol,
+            position_side: position.position_side,
+            quantity: position.position_amt,
+            entry_price: position.entry_price,
+            mark_price: position.mark_price,
+            liquidation_price: Some(position.liquidation_price).filter(|price| *price > 0.0),
+            unrealized_pnl: position.un_realized_profit,
+        }
+    }
+}
+
+/// Paper positions as Binance would report them in one-way mode.
+impl From<ExchangePosition> for PositionRisk {
+    fn from(position: ExchangePosition) -> Self {
+        PositionRisk {
+            symbol: position.symbol,
+            position_amt: position.quantity,
+            entry_price: position.entry_price,
+            mark_price: position.mark_price,
+            un_realized_profit: position.unrealized_pnl,
+            liquidation_price: position.liquidation_price.unwrap_or(0.0),
+            leverage: 1.0,
+            margin_type: "cross".to_string(),
+            position_side: position.position_side,
+            notional: position.quantity * position.mark_price,
+            update_time: 0,
+        }
+    }
+}
+
+#[async_trait]
+impl Exchange for FutureClient {
+    async fn current_price(&self, symbol: &str) -> Result<f64> {
+        Ok(FutureClient::current_price(self, symbol).await?.price)
+    }
+
+    async fn klines(&self, symbol: &str, interval: &str, limit: Option<u16>) -> Result<Vec<Kline>> {
+        Ok(self.kline(symbol, interval, limit, None, None).await?)
+    }
+
+    /// Snaps the request to the symbol's filters once, so every attempt sends the same order.
+    async fn place_order(&self, request: &OrderRequest) -> Result<ExchangeOrder> {
+        let request = &FutureClient::symbol_rules(self, &request.symbol).await?.normalize(request)?;
+        let place = || async {
+            let transaction = self.submit_order(request).await?;
+            Ok(ExchangeOrder {
+                order_id: transaction.order_id,
+                client_order_id: transaction.client_order_id,
+                symbol: transaction.symbol,
+                side: transaction.side,
+                order_type: transaction.r#type,
+                price: request.price,
+                stop_price: request.stop_price.unwrap_or(0.0),
+                orig_qty: request.quantity,
+                executed_qty: 0.0,
+                status: transaction.status,
+                position_side: request.position_side.clone().unwrap_or(default_position_side()),
+                time: 0,
+                update_time: 0,
+            })
+        };
+        let query = || async {
+            Ok(FutureClient::query_order(self, &request.symbol, &request.client_order_id).await?.into())
+        };
+        Ok(place_once(&request.client_order_id, place, query).await?)
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<ExchangeOrder> {
+        Ok(FutureClient::cancel_order(self, symbol, order_id as i64).await?.into())
+    }
+
+    async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<ExchangeOrder> {
+        Ok(FutureClient::query_order(self, symbol, client_order_id).await?.into())
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<ExchangeOrder>> {
+        Ok(FutureClient::open_orders(self, symbol).await?
+            .into_iter()
+            .map(|order| order.into())
+            .collect())
+    }
+
+    async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules> {
+        Ok(FutureClient::symbol_rules(self, symbol).await?)
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>> {
+        Ok(FutureClient::balances(self).await?
+            .into_iter()
+            .map(|balance| Balance {
+                asset: balance.asset,
+                free: balance.available_balance,
+                locked: balance.balance - balance.available_balance,
+            })
+            .collect())
+    }
+
+    async fn order_history(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<ExchangeOrder>> {
+        Ok(self
+            .all_orders(symbol, from_order_id)
+            .await?
+            .into_iter()
+            .map(|order| order.into())
+            .collect())
+    }
+
+    async fn trade_history(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>> {
+        Ok(self.user_trades(symbol, from_trade_id).await?)
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<ExchangePosition>> {
+        Ok(self
+            .position_risk(Some(symbol))
+            .await?
+            .into_iter()
+            .map(|position| position.into())
+            .collect())
+    }
+
+    async fn change_leverage(&self, symbol: &str, leverage: u8) -> Result<()> {
+        FutureClient::change_leverage(self, symbol, leverage).await?;
+        Ok(())
+    }
+
+    async fn set_hedge_mode(&self, hedge: bool) -> Result<()> {
+        if self.position_mode().await?.dual_side_position != hedge {
+            self.change_position_mode(hedge).await?;
         }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
 
-    use super::FutureClient;
+    use crate::client::binance_domain::{FutureAccount, PositionRisk};
+    use crate::config::app_config::DEFAULT_ACCOUNT;
+    use crate::crypto::exchange::{ExchangePosition, OrderRequest};
+    use crate::crypto::paper_book::PaperBook;
+    use crate::crypto::symbol_rules::SymbolRules;
 
-    #[test]
-    fn test_current_price() {
+    use super::{order_params, FutureClient};
+
+    #[tokio::test]
+    async fn test_current_price() {
         let client = FutureClient::new();
-        let result = client.current_price("BTCUSDT");
+        let result = client.current_price("BTCUSDT").await;
         assert!(result.is_ok());
         let current_price = result.unwrap();
         println!("Current price of BTCUSDT: {:?}", current_price);
         assert!(current_price.price > 0.0);
     }
 
-    #[test]
-    fn test_change_leverage() {
+    #[tokio::test]
+    async fn test_change_leverage() {
         let client = FutureClient::new();
         let symbol = "BTCUSDT";
         let leverage = 3;
 
-        let result = client.change_leverage(symbol, leverage);
+        let result = client.change_leverage(symbol, leverage).await;
         match result {
             Ok(order_response) => {
                 println!("Buy order response: {:?}", order_response);
@@ -189,8 +707,8 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_kline() {
+    #[tokio::test]
+    async fn test_kline() {
         let client = FutureClient::new();
         let symbol = "GALAUSDT";
         let interval = "1h";
@@ -198,7 +716,7 @@ mod tests {
         let start_time = None;
         let end_time = None;
 
-        let result = client.kline(symbol, interval, limit, start_time, end_time);
+        let result = client.kline(symbol, interval, limit, start_time, end_time).await;
         assert!(result.is_ok());
 
         let klines = result.unwrap();
@@ -211,8 +729,8 @@ mod tests {
         });
     }
 
-    #[test]
-    fn test_limit_buy_with_stop_loss() {
+    #[tokio::test]
+    async fn test_limit_buy_with_stop_loss() {
         let client = FutureClient::new();
         let symbol = "BTCUSDT";
         let side = "SELL";
@@ -229,7 +747,7 @@ mod tests {
             price,
             quantity,
             Some(stop_loss_price),
-        );
+        ).await;
         match result {
             Ok(order_response) => {
                 println!("Buy order response: {:?}", order_response);
@@ -240,8 +758,8 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_limit_buy() {
+    #[tokio::test]
+    async fn test_limit_buy() {
         let client = FutureClient::new();
         let symbol = "BTCUSDT";
         let quantity = 0.01;
@@ -254,7 +772,7 @@ mod tests {
             price,
             quantity,
             None,
-        );
+        ).await;
         match result {
             Ok(order_response) => {
                 println!("Buy order response: {:?}", order_response);
@@ -264,4 +782,87 @@ mod tests {
             }
         }
     }
+
+    #[tokio::test]
+    async fn test_cancel_orders() {
+        let book = Arc::new(Mutex::new(PaperBook::default()));
+        let client = FutureClient::new_paper(DEFAULT_ACCOUNT, book.clone());
+        for (price, client_order_id) in [(90.0, "a"), (89.0, "b"), (88.0, "c")] {
+            let request = OrderRequest::limit("BTCUSDT", "BUY", price, 1.0, client_order_id);
+            client.submit_order(&request).await.unwrap();
+        }
+        let other = OrderRequest::limit("ETHUSDT", "SELL", 110.0, 1.0, "other");
+        client.submit_order(&other).await.unwrap();
+
+        let canceled = client.cancel_order_by_client_id("BTCUSDT", "a").await.unwrap();
+        assert_eq!(canceled.status, "CANCELED");
+        assert!(client.cancel_order_by_client_id("BTCUSDT", "a").await.is_err());
+        assert_eq!(book.lock().unwrap().open_orders().len(), 3);
+
+        assert_eq!(client.cancel_all_orders("BTCUSDT").await.unwrap().code, 200);
+        let book = book.lock().unwrap();
+        for client_order_id in ["a", "b", "c"] {
+            assert_eq!(book.find("BTCUSDT", client_order_id).unwrap().status, "CANCELED");
+        }
+        let open = book.open_orders();
+        assert_eq!(open.len(), 1);
+        assert_eq!(open[0].client_order_id, "other");
+    }
+
+    #[test]
+    fn test_decode_position_and_account() {
+        let positions: Vec<PositionRisk> = serde_json::from_str(
+            r#"[{"symbol": "BTCUSDT", "positionAmt": "-0.010", "entryPrice": "60000.0",
+                "breakEvenPrice": "60024.0", "markPrice": "61000.0", "unRealizedProfit": "-10.0",
+                "liquidationPrice": "118000.5", "leverage": "10", "maxNotionalValue": "1000000",
+                "marginType": "cross", "isolatedMargin": "0.0", "isAutoAddMargin": "false",
+                "positionSide": "BOTH", "notional": "-610.0", "isolatedWallet": "0",
+                "updateTime": 1700000000000},
+               {"symbol": "ETHUSDT", "positionAmt": "0.000", "entryPrice": "0.0",
+                "markPrice": "3000.0", "unRealizedProfit": "0.0", "liquidationPrice": "0",
+                "leverage": "20", "marginType": "cross", "positionSide": "BOTH"}]"#,
+        )
+        .unwrap();
+        let positions: Vec<ExchangePosition> = positions.into_iter().map(|position| position.into()).collect();
+        assert_eq!(positions[0].quantity, -0.01);
+        assert_eq!(positions[0].liquidation_price, Some(118000.5));
+        assert_eq!(positions[1].liquidation_price, None);
+
+        let account: FutureAccount = serde_json::from_str(
+            r#"{"totalWalletBalance": "1000", "totalUnrealizedProfit": "-10",
+                "totalMarginBalance": "990", "totalInitialMargin": "61",
+                "totalMaintMargin": "9.9", "availableBalance": "929", "assets": []}"#,
+        )
+        .unwrap();
+        assert!((account.margin_ratio() - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_order_params() {
+        let mut rules = SymbolRules::unrestricted("BTCUSDT");
+        rules.tick_size = 0.1;
+        rules.step_size = 0.001;
+        let request = OrderRequest::limit("BTCUSDT", "SELL", 61000.0, 0.01, "tag-1")
+            .position_side("LONG")
+            .time_in_force("GTX");
+        let params = order_params(&request, &rules);
+        assert_eq!(params["positionSide"], "LONG");
+        assert_eq!(params["timeInForce"], "GTX");
+        assert_eq!(params["price"], "61000.0");
+        assert_eq!(params["quantity"], "0.010");
+        assert!(!params.contains_key("reduceOnly"));
+
+        let mut request = OrderRequest::limit("BTCUSDT", "SELL", 0.0, 0.0, "").close_position();
+        request.order_type = "STOP_MARKET".to_string();
+        request.stop_price = Some(58000.0);
+        let params = order_params(&request, &rules);
+        assert_eq!(params["closePosition"], "true");
+        assert_eq!(params["stopPrice"], "58000.0");
+        assert!(!params.contains_key("price"));
+        assert!(!params.contains_key("timeInForce"));
+        assert!(!params.contains_key("quantity"));
+
+        let request = OrderRequest::limit("BTCUSDT", "BUY", 59000.0, 0.01, "").reduce_only();
+        assert_eq!(order_params(&request, &rules)["reduceOnly"], "true");
+    }
 }
@@ -0,0 +1,413 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{connect, Message, WebSocket};
+
+use crate::client::binance_domain::{AggTradeEvent, BookTickerEvent, Kline, KlineEvent};
+use crate::crypto::trading_mode::{FUTURES_HOST, FUTURES_TESTNET_HOST, SPOT_HOST, SPOT_TESTNET_HOST};
+use crate::util::time_util::now_timestamp;
+
+/// Prices and candles not updated by the stream for this long are not served.
+const FRESH_MS: i64 = 10_000;
+/// Streams nobody read for this long are unsubscribed.
+const IDLE_MS: i64 = 10 * 60 * 1000;
+/// Candles kept per symbol and interval, the klines endpoint's maximum.
+const MAX_CANDLES: usize = 1000;
+/// Limit of the klines endpoint when none is given.
+const DEFAULT_LIMIT: usize = 500;
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Streams of every REST host, started on first use.
+static STREAMS: Lazy<Mutex<HashMap<String, Arc<MarketStream>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// WebSocket base URL serving the market data of a REST host. Unknown hosts have no stream and
+/// are always read over REST.
+fn stream_url(rest_host: &str) -> Option<&'static str> {
+    match rest_host {
+        SPOT_HOST => Some("wss://stream.binance.com:9443"),
+        SPOT_TESTNET_HOST => Some("wss://stream.testnet.binance.vision"),
+        FUTURES_HOST => Some("wss://fstream.binance.com"),
+        FUTURES_TESTNET_HOST => Some("wss://stream.binancefuture.com"),
+        _ => None,
+    }
+}
+
+fn for_host(rest_host: &str) -> Option<Arc<MarketStream>> {
+    let url = stream_url(rest_host)?;
+    let mut streams = STREAMS.lock().unwrap();
+    Some(
+        streams
+            .entry(url.to_string())
+            .or_insert_with(|| MarketStream::start(url))
+            .clone(),
+    )
+}
+
+/// Latest price of `symbol` on the stream of `rest_host`, if fresh. Subscribes to the symbol's
+/// trades and book ticker, so a miss is served by the stream a moment later.
+pub fn price(rest_host: &str, symbol: &str) -> Option<f64> {
+    for_host(rest_host)?.price(symbol)
+}
+
+/// The last `limit` candles of `symbol`, if the cache holds that many and the stream keeps them
+/// up to date. Subscribes to the symbol's kline stream.
+pub fn klines(rest_host: &str, symbol: &str, interval: &str, limit: Option<u16>) -> Option<Vec<Kline>> {
+    for_host(rest_host)?.klines(symbol, interval, limit)
+}
+
+/// Fills the candle cache with klines read over REST; the stream only pushes the current candle.
+pub fn seed_klines(rest_host: &str, symbol: &str, interval: &str, klines: &[Kline]) {
+    if let Some(stream) = for_host(rest_host) {
+        stream.seed_klines(symbol, interval, klines);
+    }
+}
+
+/// Price and time it was received.
+type Stamped = (f64, i64);
+
+#[derive(Default)]
+struct Quote {
+    last_trade: Option<Stamped>,
+    mid: Option<Stamped>,
+}
+
+#[derive(Default)]
+struct Candles {
+    klines: Vec<Kline>,
+    /// Last kline event, 0 until the stream pushed one.
+    updated: i64,
+}
+
+#[derive(Default)]
+struct StreamState {
+    /// Stream name to when it was last read.
+    wanted: HashMap<String, i64>,
+    quotes: HashMap<String, Quote>,
+    candles: HashMap<(String, String), Candles>,
+}
+
+impl StreamState {
+    fn want(&mut self, stream: String, now: i64) {
+        self.wanted.insert(stream, now);
+    }
+
+    /// Drops the streams nobody read lately and returns the others.
+    fn streams(&mut self, now: i64) -> BTreeSet<String> {
+        self.wanted.retain(|_, read| now - *read < IDLE_MS);
+        self.wanted.keys().cloned().collect()
+    }
+}
+
+#[derive(Deserialize)]
+struct Envelope {
+    stream: String,
+    data: Value,
+}
+
+/// One combined-stream connection to a Binance market data host. Subscriptions follow what is
+/// read: a symbol's streams are added on its first read and removed once idle. The connection is
+/// re-opened with every wanted stream after a disconnect.
+pub struct MarketStream {
+    url: String,
+    state: Mutex<StreamState>,
+}
+
+impl MarketStream {
+    pub fn start(url: &str) -> Arc<MarketStream> {
+        let stream = Arc::new(MarketStream {
+            url: url.to_string(),
+            state: Mutex::new(StreamState::default()),
+        });
+        let runner = stream.clone();
+        thread::spawn(move || runner.run());
+        stream
+    }
+
+    pub fn price(&self, symbol: &str) -> Option<f64> {
+        let now = now_timestamp();
+        let stream = symbol.to_lowercase();
+        let mut state = self.state.lock().unwrap();
+        state.want(format!("{}@aggTrade", stream), now);
+        state.want(format!("{}@bookTicker", stream), now);
+        let quote = state.quotes.get(&symbol.to_uppercase())?;
+        let fresh = |stamped: Option<Stamped>| stamped.filter(|(_, at)| now - at <= FRESH_MS);
+        fresh(quote.last_trade).or(fresh(quote.mid)).map(|(price, _)| price)
+    }
+
+    pub fn klines(&self, symbol: &str, interval: &str, limit: Option<u16>) -> Option<Vec<Kline>> {
+        let now = now_timestamp();
+        let mut state = self.state.lock().unwrap();
+        state.want(format!("{}@kline_{}", symbol.to_lowercase(), interval), now);
+        let candles = state.candles.get(&(symbol.to_uppercase(), interval.to_string()))?;
+        let limit = limit.map_or(DEFAULT_LIMIT, usize::from);
+        if now - candles.updated > FRESH_MS || candles.klines.len() < limit {
+            return None;
+        }
+        Some(candles.klines[candles.klines.len() - limit..].to_vec())
+    }
+
+    pub fn seed_klines(&self, symbol: &str, interval: &str, klines: &[Kline]) {
+        let mut state = self.state.lock().unwrap();
+        let candles = state
+            .candles
+            .entry((symbol.to_uppercase(), interval.to_string()))
+            .or_default();
+        if klines.len() <= candles.klines.len() {
+            return;
+        }
+        // Candles the stream pushed since the request went out are newer than the REST ones.
+        let last_open = klines.last().map_or(i64::MIN, |kline| kline.open_time);
+        let mut merged = klines.to_vec();
+        merged.retain(|kline| {
+            kline.open_time < last_open
+                || !candles.klines.iter().any(|cached| cached.open_time == kline.open_time)
+        });
+        merged.extend(
+            candles
+                .klines
+                .drain(..)
+                .filter(|cached| cached.open_time >= last_open),
+        );
+        merged.sort_by_key(|kline| kline.open_time);
+        candles.klines = merged;
+        truncate(&mut candles.klines);
+    }
+
+    fn run(self: Arc<Self>) {
+        let mut backoff = Duration::from_secs(1);
+        loop {
+            let streams = self.state.lock().unwrap().streams(now_timestamp());
+            if streams.is_empty() {
+                thread::sleep(READ_TIMEOUT);
+                continue;
+            }
+            match self.session(&streams) {
+                Ok(()) => backoff = Duration::from_secs(1),
+                Err(err) => println!("Failed to connect to market stream {}: {}", self.url, err),
+            }
+            // Candles pushed while disconnected are missing, they are seeded again over REST.
+            self.state.lock().unwrap().candles.clear();
+            thread::sleep(backoff);
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Reads one connection until it drops, keeping its subscriptions in line with the wanted
+    /// streams. Only failing to connect is an error.
+    fn session(&self, streams: &BTreeSet<String>) -> anyhow::Result<()> {
+        let url = format!(
+            "{}/stream?streams={}",
+            self.url,
+            streams.iter().cloned().collect::<Vec<String>>().join("/")
+        );
+        let (mut socket, _) = connect(url.as_str())?;
+        if let Err(err) = set_read_timeout(&socket, READ_TIMEOUT) {
+            println!("Market stream {} has no read timeout: {}", self.url, err);
+        }
+        let mut subscribed = streams.clone();
+        let mut checked = Instant::now();
+        let mut request_id = 0;
+        loop {
+            match socket.read_message() {
+                Ok(Message::Text(text)) => self.handle(&text),
+                Ok(Message::Close(_)) => {
+                    println!("Market stream {} closed", self.url);
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {}
+                Err(err) => {
+                    println!("Market stream {} dropped: {}", self.url, err);
+                    return Ok(());
+                }
+            }
+            if checked.elapsed() < READ_TIMEOUT {
+                continue;
+            }
+            checked = Instant::now();
+            let wanted = self.state.lock().unwrap().streams(now_timestamp());
+            for (method, params) in [
+                ("SUBSCRIBE", wanted.difference(&subscribed).cloned().collect::<Vec<String>>()),
+                ("UNSUBSCRIBE", subscribed.difference(&wanted).cloned().collect::<Vec<String>>()),
+            ] {
+                if params.is_empty() {
+                    continue;
+                }
+                request_id += 1;
+                let message = json!({"method": method, "params": params, "id": request_id});
+                if let Err(err) = socket.write_message(Message::Text(message.to_string())) {
+                    println!("Market stream {} dropped: {}", self.url, err);
+                    return Ok(());
+                }
+            }
+            subscribed = wanted;
+        }
+    }
+
+    /// Applies one combined-stream message. Replies to SUBSCRIBE and unknown events are ignored.
+    fn handle(&self, text: &str) {
+        let Ok(envelope) = serde_json::from_str::<Envelope>(text) else {
+            return;
+        };
+        let now = now_timestamp();
+        let mut state = self.state.lock().unwrap();
+        if envelope.stream.ends_with("@aggTrade") {
+            if let Ok(event) = serde_json::from_value::<AggTradeEvent>(envelope.data) {
+                state.quotes.entry(event.symbol).or_default().last_trade = Some((event.price, now));
+            }
+        } else if envelope.stream.ends_with("@bookTicker") {
+            if let Ok(event) = serde_json::from_value::<BookTickerEvent>(envelope.data) {
+                let mid = (event.bid_price + event.ask_price) / 2.0;
+                state.quotes.entry(event.symbol).or_default().mid = Some((mid, now));
+            }
+        } else if envelope.stream.contains("@kline_") {
+            if let Ok(event) = serde_json::from_value::<KlineEvent>(envelope.data) {
+                let key = (event.symbol, event.kline.interval.clone());
+                let candles = state.candles.entry(key).or_default();
+                let kline = Kline::from(event.kline);
+                match candles.klines.last_mut() {
+                    Some(last) if last.open_time == kline.open_time => *last = kline,
+                    Some(last) if last.open_time > kline.open_time => {}
+                    _ => candles.klines.push(kline),
+                }
+                truncate(&mut candles.klines);
+                candles.updated = now;
+            }
+        }
+    }
+}
+
+fn truncate(klines: &mut Vec<Kline>) {
+    if klines.len() > MAX_CANDLES {
+        klines.drain(..klines.len() - MAX_CANDLES);
+    }
+}
+
+fn set_read_timeout(socket: &WebSocket<MaybeTlsStream<TcpStream>>, timeout: Duration) -> io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref().set_read_timeout(Some(timeout)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+    use tungstenite::handshake::server::{Callback, ErrorResponse, Request, Response};
+    use tungstenite::{accept_hdr, Message};
+
+    use crate::client::binance_domain::Kline;
+
+    use super::MarketStream;
+
+    fn kline(open_time: i64, close: &str) -> Kline {
+        Kline {
+            open_time,
+            open: "1".to_string(),
+            high: "1".to_string(),
+            low: "1".to_string(),
+            close: close.to_string(),
+            volume: "1".to_string(),
+            close_time: open_time + 59_999,
+            quote_asset_volume: "1".to_string(),
+            number_of_trades: 1,
+            taker_buy_base_asset_volume: "1".to_string(),
+            taker_buy_quote_asset_volume: "1".to_string(),
+        }
+    }
+
+    /// Hands the path of each handshake, which names the subscribed streams, to the test.
+    struct PathSender(mpsc::Sender<String>);
+
+    impl Callback for PathSender {
+        fn on_request(self, request: &Request, response: Response) -> Result<Response, ErrorResponse> {
+            self.0.send(request.uri().to_string()).unwrap();
+            Ok(response)
+        }
+    }
+
+    fn wait_for<T>(mut read: impl FnMut() -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(value) = read() {
+                return value;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "timed out");
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn test_stream_cache_and_resubscription() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (paths, connected) = mpsc::channel();
+        let (messages, received) = mpsc::channel();
+        thread::spawn(move || {
+            for round in 0..2 {
+                let (tcp, _) = listener.accept().unwrap();
+                let mut socket = accept_hdr(tcp, PathSender(paths.clone())).unwrap();
+                let trade = json!({"stream": "btcusdt@aggTrade",
+                    "data": {"e": "aggTrade", "s": "BTCUSDT", "p": "100.5", "q": "1", "T": 1}});
+                socket.write_message(Message::Text(trade.to_string())).unwrap();
+                if round == 1 {
+                    // Keep the second connection open until the test is done.
+                    while let Ok(message) = socket.read_message() {
+                        let _ = messages.send(message.to_string());
+                    }
+                    return;
+                }
+                let candle = json!({"stream": "btcusdt@kline_1m", "data": {"e": "kline", "E": 2,
+                    "s": "BTCUSDT", "k": {"t": 120000, "T": 179999, "i": "1m", "o": "1", "h": "1",
+                    "l": "1", "c": "101", "v": "1", "n": 1, "x": false, "q": "1", "V": "1", "Q": "1"}}});
+                socket.write_message(Message::Text(candle.to_string())).unwrap();
+                let message = socket.read_message().unwrap();
+                messages.send(message.to_string()).unwrap();
+                socket.close(None).unwrap();
+                let _ = socket.read_message();
+            }
+        });
+
+        let stream = MarketStream::start(&format!("ws://127.0.0.1:{}", port));
+        assert_eq!(stream.price("BTCUSDT"), None);
+        stream.seed_klines("BTCUSDT", "1m", &[kline(0, "99"), kline(60000, "100"), kline(120000, "100")]);
+        assert!(stream.klines("BTCUSDT", "1m", Some(3)).is_none());
+
+        let path = connected.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(path.contains("btcusdt@aggTrade"));
+        assert!(path.contains("btcusdt@bookTicker"));
+        assert!(path.contains("btcusdt@kline_1m"));
+        assert_eq!(wait_for(|| stream.price("BTCUSDT")), 100.5);
+        let klines = wait_for(|| stream.klines("BTCUSDT", "1m", Some(3)));
+        assert_eq!(klines.len(), 3);
+        assert_eq!(klines[2].close, "101");
+        assert!(stream.klines("BTCUSDT", "1m", Some(4)).is_none());
+
+        stream.price("ETHUSDT");
+        let subscribe = received.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(subscribe.contains("SUBSCRIBE"));
+        assert!(subscribe.contains("ethusdt@aggTrade"));
+
+        // The server closed the first connection: the stream comes back with every symbol.
+        let path = connected.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(path.contains("btcusdt@kline_1m"));
+        assert!(path.contains("ethusdt@bookTicker"));
+        assert!(stream.klines("BTCUSDT", "1m", Some(3)).is_none());
+    }
+}
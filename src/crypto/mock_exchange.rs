@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+
+use crate::client::binance_domain::Kline;
+use crate::crypto::exchange::{
+    default_position_side, Balance, Exchange, ExchangeOrder, ExchangePosition, ExchangeTrade, OrderRequest,
+};
+use crate::crypto::paper_book::{PaperBook, PaperOrder};
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::util::binance_error::BinanceError;
+
+/// In-memory exchange backed by a replay `PaperBook`, which the backtester replays candles
+/// against. Prices are whatever the backtester or a test last pushed in, and resting orders fill
+/// when a later price crosses them. The setters that script a venue are for tests only.
+pub struct MockExchange {
+    book: Arc<Mutex<PaperBook>>,
+    rules: Mutex<HashMap<String, SymbolRules>>,
+    liquidation_prices: Mutex<HashMap<String, f64>>,
+    leverages: Mutex<HashMap<String, u8>>,
+    hedge_mode: Mutex<Option<bool>>,
+    executed: Mutex<HashMap<String, f64>>,
+}
+
+impl MockExchange {
+    pub fn new() -> MockExchange {
+        MockExchange::with_book(Arc::new(Mutex::new(PaperBook::replay())))
+    }
+
+    pub fn with_book(book: Arc<Mutex<PaperBook>>) -> MockExchange {
+        MockExchange {
+            book,
+            rules: Mutex::new(HashMap::new()),
+            liquidation_prices: Mutex::new(HashMap::new()),
+            leverages: Mutex::new(HashMap::new()),
+            hedge_mode: Mutex::new(None),
+            executed: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl MockExchange {
+    /// Moves the price of `symbol`, filling every resting order it crosses.
+    pub fn set_price(&self, symbol: &str, price: f64) -> Vec<PaperOrder> {
+        self.book.lock().unwrap().fill(symbol, price)
+    }
+
+    /// Symbols without rules accept any price and quantity.
+    pub fn set_rules(&self, rules: SymbolRules) {
+        self.rules.lock().unwrap().insert(rules.symbol.clone(), rules);
+    }
+
+    /// Liquidation price reported for the position of `symbol`, which the book has no notion of.
+    pub fn set_liquidation_price(&self, symbol: &str, price: f64) {
+        self.liquidation_prices.lock().unwrap().insert(symbol.to_string(), price);
+    }
+
+    /// Leverage last set on `symbol`, if any.
+    pub fn leverage(&self, symbol: &str) -> Option<u8> {
+        self.leverages.lock().unwrap().get(symbol).cloned()
+    }
+
+    /// Position mode last set, if any.
+    pub fn hedge_mode(&self) -> Option<bool> {
+        *self.hedge_mode.lock().unwrap()
+    }
+
+    /// Reports `quantity` executed for the order, a partial fill the book does not simulate.
+    pub fn set_executed(&self, client_order_id: &str, quantity: f64) {
+        self.executed.lock().unwrap().insert(client_order_id.to_string(), quantity);
+    }
+
+    pub fn book(&self) -> Arc<Mutex<PaperBook>> {
+        self.book.clone()
+    }
+}
+
+impl Default for MockExchange {
+    fn default() -> Self {
+        MockExchange::new()
+    }
+}
+
+#[async_trait]
+impl Exchange for MockExchange {
+    async fn current_price(&self, symbol: &str) -> Result<f64> {
+        self.book
+            .lock()
+            .unwrap()
+            .replay_price(symbol)
+            .ok_or_else(|| anyhow!("No price for {}", symbol))
+    }
+
+    async fn klines(&self, symbol: &str, _interval: &str, limit: Option<u16>) -> Result<Vec<Kline>> {
+        Ok(self
+            .book
+            .lock()
+            .unwrap()
+            .replay_klines(symbol, limit)
+            .unwrap_or_default())
+    }
+
+    async fn place_order(&self, request: &OrderRequest) -> Result<ExchangeOrder> {
+        if request.quantity <= 0.0 {
+            bail!("Invalid quantity {}", request.quantity);
+        }
+        let mut order = PaperOrder::new(
+            &request.symbol,
+            &request.side,
+            &request.order_type,
+            request.price,
+            request.quantity,
+            &request.client_order_id,
+        );
+        order.stop_price = request.stop_price.unwrap_or(0.0);
+        order.position_side = request.position_side.clone().unwrap_or(default_position_side());
+        order.reduce_only = request.reduce_only;
+        let mut book = self.book.lock().unwrap();
+        // Like Binance, refuse a client order id an open order of the symbol already has.
+        let duplicate = !request.client_order_id.is_empty()
+            && book.open_orders().iter().any(|open| {
+                open.symbol == request.symbol && open.client_order_id == request.client_order_id
+            });
+        if duplicate {
+            return Err(BinanceError::Api {
+                code: -2010,
+                msg: "Duplicate order sent.".to_string(),
+            }
+            .into());
+        }
+        Ok(book.place(order).to_exchange_order())
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<ExchangeOrder> {
+        self.book
+            .lock()
+            .unwrap()
+            .cancel(symbol, order_id)
+            .map(|order| order.to_exchange_order())
+            .ok_or_else(|| anyhow!("Unknown order {}", order_id))
+    }
+
+    async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<ExchangeOrder> {
+        let mut order = self
+            .book
+            .lock()
+            .unwrap()
+            .find(symbol, client_order_id)
+            .map(|order| order.to_exchange_order())
+            .ok_or_else(|| BinanceError::no_such_order(client_order_id))?;
+        if let Some(executed) = self.executed.lock().unwrap().get(client_order_id) {
+            order.executed_qty = *executed;
+        }
+        Ok(order)
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<ExchangeOrder>> {
+        Ok(self
+            .book
+            .lock()
+            .unwrap()
+            .open_orders()
+            .iter()
+            .filter(|order| order.symbol == symbol)
+            .map(|order| order.to_exchange_order())
+            .collect())
+    }
+
+    /// Holds no balances: the backtester keeps the account itself.
+    async fn balances(&self) -> Result<Vec<Balance>> {
+        Ok(Vec::new())
+    }
+
+    async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules> {
+        Ok(self
+            .rules
+            .lock()
+            .unwrap()
+            .get(symbol)
+            .cloned()
+            .unwrap_or_else(|| SymbolRules::unrestricted(symbol)))
+    }
+
+    async fn order_history(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<ExchangeOrder>> {
+        Ok(self
+            .book
+            .lock()
+            .unwrap()
+            .history(symbol, from_order_id)
+            .iter()
+            .map(|order| order.to_exchange_order())
+            .collect())
+    }
+
+    async fn trade_history(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>> {
+        Ok(self.book.lock().unwrap().trades(symbol, from_trade_id))
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<ExchangePosition>> {
+        let liquidation_price = self.liquidation_prices.lock().unwrap().get(symbol).cloned();
+        let mut positions = self.book.lock().unwrap().positions(symbol);
+        for position in positions.iter_mut() {
+            position.liquidation_price = liquidation_price;
+        }
+        Ok(positions)
+    }
+
+    async fn change_leverage(&self, symbol: &str, leverage: u8) -> Result<()> {
+        self.leverages.lock().unwrap().insert(symbol.to_string(), leverage);
+        Ok(())
+    }
+
+    async fn set_hedge_mode(&self, hedge: bool) -> Result<()> {
+        *self.hedge_mode.lock().unwrap() = Some(hedge);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::exchange::{place_once, Exchange, OrderRequest};
+    use crate::util::binance_error::BinanceError;
+
+    use super::MockExchange;
+
+    fn binance_error(err: anyhow::Error) -> BinanceError {
+        err.downcast::<BinanceError>().unwrap_or_else(|err| BinanceError::Api {
+            code: 0,
+            msg: err.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_mock_exchange() {
+        let exchange = MockExchange::new();
+        assert!(exchange.current_price("BTCFDUSD").await.is_err());
+
+        exchange.set_price("BTCFDUSD", 100.0);
+        assert_eq!(exchange.current_price("BTCFDUSD").await.unwrap(), 100.0);
+
+        let buy = exchange
+            .place_order(&OrderRequest::limit("BTCFDUSD", "BUY", 99.0, 1.0, "buy"))
+            .await
+            .unwrap();
+        exchange
+            .place_order(&OrderRequest::limit("BTCFDUSD", "SELL", 101.0, 1.0, "sell"))
+            .await
+            .unwrap();
+        assert_eq!(exchange.open_orders("BTCFDUSD").await.unwrap().len(), 2);
+
+        exchange.set_price("BTCFDUSD", 98.0);
+        assert_eq!(exchange.query_order("BTCFDUSD", "buy").await.unwrap().status, "FILLED");
+        assert!(exchange.cancel_order("BTCFDUSD", buy.order_id).await.is_err());
+        assert_eq!(exchange.open_orders("BTCFDUSD").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_client_order_id() {
+        let exchange = MockExchange::new();
+        exchange.set_price("BTCFDUSD", 100.0);
+        let request = OrderRequest::limit("BTCFDUSD", "BUY", 99.0, 1.0, "tag-1");
+        let first = exchange.place_order(&request).await.unwrap();
+
+        let err = binance_error(exchange.place_order(&request).await.unwrap_err());
+        assert!(err.is_duplicate_order());
+
+        // A retry refused as a duplicate resolves to the order already resting.
+        let placed = place_once(
+            &request.client_order_id,
+            || async { exchange.place_order(&request).await.map_err(binance_error) },
+            || async {
+                exchange
+                    .query_order("BTCFDUSD", &request.client_order_id)
+                    .await
+                    .map_err(binance_error)
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(placed.order_id, first.order_id);
+        assert_eq!(exchange.open_orders("BTCFDUSD").await.unwrap().len(), 1);
+
+        let unknown = exchange.query_order("BTCFDUSD", "tag-2").await.unwrap_err();
+        assert!(binance_error(unknown).is_unknown_order());
+    }
+}
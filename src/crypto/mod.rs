@@ -1,2 +1,9 @@
 pub mod crypto_client;
-pub mod futures_client;
\ No newline at end of file
+pub mod futures_client;
+pub mod market_stream;
+pub mod paper_book;
+pub mod risk_guard;
+pub mod symbol_rules;
+pub mod trading_mode;
+pub mod exchange;
+pub mod mock_exchange;
\ No newline at end of file
@@ -0,0 +1,711 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::client::db_client::DBClient;
+use crate::client::binance_domain::{
+    CanceledOrder, FutureCanceledOrder, FutureTransaction, Kline, Order, Transaction,
+};
+use crate::crypto::exchange::{ExchangeOrder, ExchangePosition, ExchangeTrade};
+use crate::crypto::futures_client::Order as FutureOrder;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaperOrder {
+    pub order_id: u64,
+    pub client_order_id: String,
+    pub symbol: String,
+    pub side: String,
+    pub type_name: String,
+    pub price: f64,
+    pub quantity: f64,
+    pub stop_price: f64,
+    pub status: String,
+    pub time: u64,
+    /// Futures only, see `OrderRequest`. Reduce-only orders fill like any other.
+    pub position_side: String,
+    pub reduce_only: bool,
+}
+
+impl PaperOrder {
+    pub fn new(
+        symbol: &str,
+        side: &str,
+        type_name: &str,
+        price: f64,
+        quantity: f64,
+        client_order_id: &str,
+    ) -> PaperOrder {
+        PaperOrder {
+            order_id: 0,
+            client_order_id: client_order_id.to_string(),
+            symbol: symbol.to_string(),
+            side: side.to_string(),
+            type_name: type_name.to_string(),
+            price,
+            quantity,
+            stop_price: 0.0,
+            status: "NEW".to_string(),
+            time: 0,
+            position_side: "BOTH".to_string(),
+            reduce_only: false,
+        }
+    }
+
+    pub fn to_order(&self) -> Order {
+        let executed_qty = if self.status == "FILLED" { self.quantity } else { 0.0 };
+        Order {
+            symbol: self.symbol.clone(),
+            order_id: self.order_id,
+            order_list_id: -1,
+            client_order_id: self.client_order_id.clone(),
+            price: self.price,
+            orig_qty: self.quantity.to_string(),
+            executed_qty: executed_qty.to_string(),
+            cummulative_quote_qty: (executed_qty * self.price).to_string(),
+            status: self.status.clone(),
+            time_in_force: "GTC".to_string(),
+            type_name: self.type_name.clone(),
+            side: self.side.clone(),
+            stop_price: self.stop_price,
+            iceberg_qty: "0".to_string(),
+            time: self.time,
+            update_time: self.time,
+            is_working: self.status == "NEW",
+            orig_quote_order_qty: "0".to_string(),
+        }
+    }
+
+    pub fn to_exchange_order(&self) -> ExchangeOrder {
+        ExchangeOrder {
+            order_id: self.order_id,
+            client_order_id: self.client_order_id.clone(),
+            symbol: self.symbol.clone(),
+            side: self.side.clone(),
+            order_type: self.type_name.clone(),
+            price: self.price,
+            stop_price: self.stop_price,
+            orig_qty: self.quantity,
+            executed_qty: if self.status == "FILLED" { self.quantity } else { 0.0 },
+            status: self.status.clone(),
+            position_side: self.position_side.clone(),
+            time: self.time,
+            update_time: self.time,
+        }
+    }
+
+    /// The single fill of a filled paper order; it reuses the order id as trade id.
+    pub fn to_exchange_trade(&self) -> ExchangeTrade {
+        ExchangeTrade {
+            trade_id: self.order_id,
+            order_id: self.order_id,
+            symbol: self.symbol.clone(),
+            side: self.side.clone(),
+            price: self.price,
+            quantity: self.quantity,
+            quote_qty: self.price * self.quantity,
+            commission: 0.0,
+            commission_asset: String::new(),
+            realized_pnl: 0.0,
+            maker: true,
+            time: self.time,
+        }
+    }
+
+    pub fn to_transaction(&self) -> Transaction {
+        Transaction {
+            symbol: self.symbol.clone(),
+            order_id: self.order_id,
+            order_list_id: Some(-1),
+            client_order_id: self.client_order_id.clone(),
+            transact_time: self.time,
+            price: self.price,
+            orig_qty: self.quantity,
+            executed_qty: 0.0,
+            cummulative_quote_qty: 0.0,
+            stop_price: self.stop_price,
+            status: self.status.clone(),
+            time_in_force: "GTC".to_string(),
+            type_name: self.type_name.clone(),
+            side: self.side.clone(),
+        }
+    }
+
+    pub fn to_canceled_order(&self) -> CanceledOrder {
+        CanceledOrder {
+            symbol: self.symbol.clone(),
+            orig_client_order_id: self.client_order_id.clone(),
+            order_id: self.order_id,
+            order_list_id: -1,
+            client_order_id: self.client_order_id.clone(),
+            transact_time: now(),
+            price: self.price,
+            orig_qty: self.quantity,
+            executed_qty: 0.0,
+            cummulative_quote_qty: 0.0,
+            status: self.status.clone(),
+            time_in_force: "GTC".to_string(),
+            type_name: self.type_name.clone(),
+            side: self.side.clone(),
+        }
+    }
+
+    pub fn to_future_transaction(&self) -> FutureTransaction {
+        FutureTransaction {
+            order_id: self.order_id,
+            client_order_id: self.client_order_id.clone(),
+            symbol: self.symbol.clone(),
+            status: self.status.clone(),
+            r#type: self.type_name.clone(),
+            side: self.side.clone(),
+        }
+    }
+
+    pub fn to_future_order(&self) -> FutureOrder {
+        FutureOrder {
+            order_id: self.order_id as i64,
+            client_order_id: self.client_order_id.clone(),
+            symbol: self.symbol.clone(),
+            status: self.status.clone(),
+            price: self.price,
+            stop_price: self.stop_price,
+            orig_qty: self.quantity,
+            executed_qty: if self.status == "FILLED" { self.quantity } else { 0.0 },
+            type_name: self.type_name.clone(),
+            time_in_force: "GTC".to_string(),
+            side: self.side.clone(),
+            position_side: self.position_side.clone(),
+            time: self.time as i64,
+            update_time: self.time as i64,
+        }
+    }
+
+    pub fn to_future_canceled_order(&self) -> FutureCanceledOrder {
+        FutureCanceledOrder {
+            order_id: self.order_id as i64,
+            client_order_id: self.client_order_id.clone(),
+            symbol: self.symbol.clone(),
+            status: self.status.clone(),
+            price: self.price,
+            orig_qty: self.quantity,
+            executed_qty: 0.0,
+            stop_price: self.stop_price,
+            time_in_force: "GTC".to_string(),
+            type_name: self.type_name.clone(),
+            side: self.side.clone(),
+            position_side: self.position_side.clone(),
+            reduce_only: self.reduce_only,
+            update_time: now() as i64,
+        }
+    }
+}
+
+/// Whether a price range reaches an order. Limit orders fill at their price or better. Stop
+/// orders trigger when the price moves against their side through the stop price, take-profit
+/// orders when it moves in their favour; both then fill at the stop price.
+fn crossed(order: &PaperOrder, low: f64, high: f64) -> bool {
+    let buy = order.side == "BUY";
+    if order.type_name.starts_with("STOP") {
+        return match buy {
+            true => high >= order.stop_price,
+            false => low <= order.stop_price,
+        };
+    }
+    if order.type_name.starts_with("TAKE_PROFIT") {
+        return match buy {
+            true => low <= order.stop_price,
+            false => high >= order.stop_price,
+        };
+    }
+    match buy {
+        true => low <= order.price,
+        false => high >= order.price,
+    }
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp_millis() as u64
+}
+
+/// Local order book used in paper mode. Resting limit orders are filled whenever the live price,
+/// or the range of a candle since they were placed, crosses them; nothing is ever sent to the
+/// exchange.
+///
+/// A replay book is driven by a backtest instead: prices and klines come from the candles
+/// pushed into it rather than from the exchange.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaperBook {
+    next_id: u64,
+    open: Vec<PaperOrder>,
+    filled: Vec<PaperOrder>,
+    /// Only looked up by `find`, as Binance still answers for cancelled orders.
+    canceled: Vec<PaperOrder>,
+    #[serde(skip)]
+    replay: bool,
+    prices: HashMap<String, f64>,
+    /// Open time of the last candle each symbol's orders were checked against.
+    checked: HashMap<String, u64>,
+    #[serde(skip)]
+    candles: HashMap<String, Vec<Kline>>,
+}
+
+impl PaperBook {
+    pub fn replay() -> PaperBook {
+        PaperBook {
+            replay: true,
+            ..PaperBook::default()
+        }
+    }
+
+    /// Fills the orders crossed by the candle's high/low at their limit price, then makes the
+    /// candle's close the current price.
+    pub fn push_candle(&mut self, symbol: &str, kline: &Kline) -> Vec<PaperOrder> {
+        let low: f64 = kline.low.parse().unwrap_or(0.0);
+        let high: f64 = kline.high.parse().unwrap_or(0.0);
+        let filled = self.fill_range(symbol, low, high);
+        self.prices
+            .insert(symbol.to_string(), kline.close.parse().unwrap_or(0.0));
+        self.candles
+            .entry(symbol.to_string())
+            .or_default()
+            .push(kline.clone());
+        filled
+    }
+
+    pub fn replay_price(&self, symbol: &str) -> Option<f64> {
+        if !self.replay {
+            return None;
+        }
+        self.prices.get(symbol).cloned()
+    }
+
+    pub fn replay_klines(&self, symbol: &str, limit: Option<u16>) -> Option<Vec<Kline>> {
+        if !self.replay {
+            return None;
+        }
+        let candles = self.candles.get(symbol).cloned().unwrap_or_default();
+        let skip = match limit {
+            Some(limit) => candles.len().saturating_sub(limit as usize),
+            None => 0,
+        };
+        Some(candles.into_iter().skip(skip).collect())
+    }
+
+    /// Rests `order` on the book, assigning it an order id and a timestamp.
+    pub fn place(&mut self, mut order: PaperOrder) -> PaperOrder {
+        self.next_id += 1;
+        order.order_id = self.next_id;
+        order.status = "NEW".to_string();
+        order.time = now();
+        self.open.push(order.clone());
+        order
+    }
+
+    pub fn cancel(&mut self, symbol: &str, order_id: u64) -> Option<PaperOrder> {
+        self.cancel_where(|order| order.symbol == symbol && order.order_id == order_id)
+            .pop()
+    }
+
+    pub fn cancel_by_client_id(&mut self, symbol: &str, client_order_id: &str) -> Option<PaperOrder> {
+        self.cancel_where(|order| order.symbol == symbol && order.client_order_id == client_order_id)
+            .pop()
+    }
+
+    pub fn cancel_all(&mut self, symbol: &str) -> Vec<PaperOrder> {
+        self.cancel_where(|order| order.symbol == symbol)
+    }
+
+    fn cancel_where<F>(&mut self, predicate: F) -> Vec<PaperOrder>
+    where
+        F: Fn(&PaperOrder) -> bool,
+    {
+        let canceled = self.take(predicate);
+        self.canceled.extend(canceled.iter().cloned());
+        canceled
+    }
+
+    /// Fills every resting order of `symbol` that `price` crosses and returns the fills.
+    pub fn fill(&mut self, symbol: &str, price: f64) -> Vec<PaperOrder> {
+        self.prices.insert(symbol.to_string(), price);
+        self.fill_range(symbol, price, price)
+    }
+
+    /// Open time from which the candles of `symbol` may fill a resting order: the placement of
+    /// the oldest one, or the last candle already checked if later. `None` without open orders.
+    pub fn candles_from(&self, symbol: &str) -> Option<u64> {
+        let oldest = self
+            .open
+            .iter()
+            .filter(|order| order.symbol == symbol)
+            .map(|order| order.time)
+            .min()?;
+        Some(oldest.max(self.checked.get(symbol).cloned().unwrap_or(0)))
+    }
+
+    /// Fills every resting order of `symbol` that `price`, or the high/low of a candle opened
+    /// after the order was placed, crosses. Then makes `price` the current price.
+    pub fn fill_candles(&mut self, symbol: &str, candles: &[Kline], price: f64) -> Vec<PaperOrder> {
+        let ranges: Vec<(u64, f64, f64)> = candles
+            .iter()
+            .map(|kline| {
+                let low: f64 = kline.low.parse().unwrap_or(price);
+                let high: f64 = kline.high.parse().unwrap_or(price);
+                (kline.open_time as u64, low, high)
+            })
+            .collect();
+        self.prices.insert(symbol.to_string(), price);
+        if let Some(last) = ranges.last() {
+            self.checked.insert(symbol.to_string(), last.0);
+        }
+        self.fill_where(|order| {
+            order.symbol == symbol
+                && (crossed(order, price, price)
+                    || ranges
+                        .iter()
+                        .any(|(open_time, low, high)| *open_time >= order.time && crossed(order, *low, *high)))
+        })
+    }
+
+    fn fill_range(&mut self, symbol: &str, low: f64, high: f64) -> Vec<PaperOrder> {
+        self.fill_where(|order| order.symbol == symbol && crossed(order, low, high))
+    }
+
+    fn fill_where<F>(&mut self, predicate: F) -> Vec<PaperOrder>
+    where
+        F: Fn(&PaperOrder) -> bool,
+    {
+        let mut filled = self.take(predicate);
+        for order in filled.iter_mut() {
+            order.status = "FILLED".to_string();
+            order.time = now();
+            if order.type_name.ends_with("MARKET") {
+                order.price = order.stop_price;
+            }
+        }
+        self.filled.extend(filled.iter().cloned());
+        filled
+    }
+
+    /// Looks an order up by client id among the open, filled and cancelled ones.
+    pub fn find(&self, symbol: &str, client_order_id: &str) -> Option<PaperOrder> {
+        self.open
+            .iter()
+            .chain(self.filled.iter())
+            .chain(self.canceled.iter())
+            .find(|order| order.symbol == symbol && order.client_order_id == client_order_id)
+            .cloned()
+    }
+
+    pub fn open_orders(&self) -> Vec<PaperOrder> {
+        self.open.clone()
+    }
+
+    pub fn filled_orders(&self) -> Vec<PaperOrder> {
+        self.filled.clone()
+    }
+
+    /// Open and filled orders of `symbol` from `from_order_id` on, oldest first. Cancelled
+    /// orders are not kept.
+    pub fn history(&self, symbol: &str, from_order_id: Option<u64>) -> Vec<PaperOrder> {
+        let mut orders: Vec<PaperOrder> = self
+            .open
+            .iter()
+            .chain(self.filled.iter())
+            .filter(|order| order.symbol == symbol && order.order_id >= from_order_id.unwrap_or(0))
+            .cloned()
+            .collect();
+        orders.sort_by_key(|order| order.order_id);
+        orders
+    }
+
+    /// Fills of `symbol` from `from_trade_id` on, oldest first.
+    pub fn trades(&self, symbol: &str, from_trade_id: Option<u64>) -> Vec<ExchangeTrade> {
+        self.history(symbol, from_trade_id)
+            .iter()
+            .filter(|order| order.status == "FILLED")
+            .map(|order| order.to_exchange_trade())
+            .collect()
+    }
+
+    /// Positions the fills of `symbol` add up to, one per position side, at their average entry
+    /// price and valued at the last price seen. A paper book has no margin, so nothing gets
+    /// liquidated.
+    pub fn positions(&self, symbol: &str) -> Vec<ExchangePosition> {
+        let mut sides: Vec<(String, f64, f64)> = Vec::new();
+        for order in self.filled.iter().filter(|order| order.symbol == symbol) {
+            let index = match sides.iter().position(|(side, _, _)| *side == order.position_side) {
+                Some(index) => index,
+                None => {
+                    sides.push((order.position_side.clone(), 0.0, 0.0));
+                    sides.len() - 1
+                }
+            };
+            let (_, quantity, entry_price) = &mut sides[index];
+            let fill = match order.side.as_str() {
+                "BUY" => order.quantity,
+                _ => -order.quantity,
+            };
+            if *quantity * fill >= 0.0 {
+                *entry_price = (*entry_price * quantity.abs() + order.price * fill.abs()) / (*quantity + fill).abs();
+            } else if fill.abs() > quantity.abs() {
+                *entry_price = order.price;
+            }
+            *quantity += fill;
+        }
+        sides
+            .into_iter()
+            .filter(|(_, quantity, _)| quantity.abs() >= 1e-12)
+            .map(|(position_side, quantity, entry_price)| {
+                let mark_price = self.prices.get(symbol).cloned().unwrap_or(entry_price);
+                ExchangePosition {
+                    symbol: symbol.to_string(),
+                    position_side,
+                    quantity,
+                    entry_price,
+                    mark_price,
+                    liquidation_price: None,
+                    unrealized_pnl: (mark_price - entry_price) * quantity,
+                }
+            })
+            .collect()
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        let mut symbols: Vec<String> = self.open.iter().map(|order| order.symbol.clone()).collect();
+        symbols.sort();
+        symbols.dedup();
+        symbols
+    }
+
+    fn take<F>(&mut self, predicate: F) -> Vec<PaperOrder>
+    where
+        F: Fn(&PaperOrder) -> bool,
+    {
+        let (taken, kept): (Vec<PaperOrder>, Vec<PaperOrder>) =
+            self.open.drain(..).partition(|order| predicate(order));
+        self.open = kept;
+        let mut taken = taken;
+        for order in taken.iter_mut() {
+            order.status = "CANCELED".to_string();
+        }
+        taken
+    }
+}
+
+/// Books of the paper robots. A robot keeps its book when its strategy is updated, and the book
+/// is saved next to the robot so a restart picks its orders and positions up again.
+static ROBOT_BOOKS: Lazy<Mutex<HashMap<String, Arc<Mutex<PaperBook>>>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// The paper book of `robot_id`, loaded from the database the first time it is asked for.
+pub fn robot_book(robot_id: &str) -> Arc<Mutex<PaperBook>> {
+    let mut books = ROBOT_BOOKS.lock().unwrap();
+    books
+        .entry(robot_id.to_string())
+        .or_insert_with(|| {
+            let stored = DBClient::get_instance().lock().unwrap().load_paper_book(robot_id);
+            let book = match stored {
+                Ok(Some(book)) => serde_json::from_str(&book).unwrap_or_else(|err| {
+                    println!("Failed to read the paper book of robot {}: {}", robot_id, err);
+                    PaperBook::default()
+                }),
+                Ok(None) => PaperBook::default(),
+                Err(err) => {
+                    println!("Failed to load the paper book of robot {}: {}", robot_id, err);
+                    PaperBook::default()
+                }
+            };
+            Arc::new(Mutex::new(book))
+        })
+        .clone()
+}
+
+/// Stores the robot's paper book, if it has one.
+pub fn save_robot_book(robot_id: &str) {
+    let Some(book) = ROBOT_BOOKS.lock().unwrap().get(robot_id).cloned() else {
+        return;
+    };
+    let book = serde_json::to_string(&*book.lock().unwrap()).unwrap_or_default();
+    if let Err(err) = DBClient::get_instance().lock().unwrap().save_paper_book(robot_id, &book) {
+        println!("Failed to save the paper book of robot {}: {}", robot_id, err);
+    }
+}
+
+/// Net quantity of `symbol` the fills of every paper robot add up to, negative when short.
+pub fn robot_books_position(symbol: &str) -> f64 {
+    let books: Vec<Arc<Mutex<PaperBook>>> = ROBOT_BOOKS.lock().unwrap().values().cloned().collect();
+    books
+        .iter()
+        .flat_map(|book| book.lock().unwrap().positions(symbol))
+        .map(|position| position.quantity)
+        .sum()
+}
+
+/// Drops the robot's paper book, from memory and from the database.
+pub fn delete_robot_book(robot_id: &str) {
+    ROBOT_BOOKS.lock().unwrap().remove(robot_id);
+    if let Err(err) = DBClient::get_instance().lock().unwrap().delete_paper_book(robot_id) {
+        println!("Failed to delete the paper book of robot {}: {}", robot_id, err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::binance_domain::Kline;
+
+    use super::{delete_robot_book, robot_book, save_robot_book, PaperBook, PaperOrder, ROBOT_BOOKS};
+
+    fn kline(open_time: u64, low: &str, high: &str, close: &str) -> Kline {
+        Kline {
+            open_time: open_time as i64,
+            open: "100".to_string(),
+            high: high.to_string(),
+            low: low.to_string(),
+            close: close.to_string(),
+            volume: "0".to_string(),
+            close_time: open_time as i64 + 59_999,
+            quote_asset_volume: "0".to_string(),
+            number_of_trades: 0,
+            taker_buy_base_asset_volume: "0".to_string(),
+            taker_buy_quote_asset_volume: "0".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_fill_crossing_orders() {
+        let mut book = PaperBook::default();
+        book.place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 99.0, 1.0, "buy"));
+        book.place(PaperOrder::new("BTCFDUSD", "SELL", "LIMIT", 101.0, 1.0, "sell"));
+        book.place(PaperOrder::new("ETHFDUSD", "BUY", "LIMIT", 99.0, 1.0, "other"));
+
+        assert!(book.fill("BTCFDUSD", 100.0).is_empty());
+
+        let filled = book.fill("BTCFDUSD", 98.5);
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled[0].client_order_id, "buy");
+        assert_eq!(filled[0].status, "FILLED");
+        assert_eq!(book.open_orders().len(), 2);
+        assert_eq!(book.filled_orders().len(), 1);
+    }
+
+    #[test]
+    fn test_position() {
+        let mut book = PaperBook::default();
+        for (side, price) in [("BUY", 100.0), ("BUY", 90.0), ("SELL", 110.0)] {
+            book.place(PaperOrder::new("BTCUSDT", side, "LIMIT", price, 1.0, ""));
+        }
+        assert!(book.positions("BTCUSDT").is_empty());
+
+        book.fill("BTCUSDT", 90.0);
+        let position = &book.positions("BTCUSDT")[0];
+        assert_eq!(position.quantity, 2.0);
+        assert_eq!(position.entry_price, 95.0);
+        assert_eq!(position.unrealized_pnl, -10.0);
+
+        book.fill("BTCUSDT", 110.0);
+        let position = &book.positions("BTCUSDT")[0];
+        assert_eq!(position.quantity, 1.0);
+        assert_eq!(position.entry_price, 95.0);
+        assert_eq!(position.unrealized_pnl, 15.0);
+
+        let mut short = PaperOrder::new("BTCUSDT", "SELL", "LIMIT", 105.0, 2.0, "");
+        short.position_side = "SHORT".to_string();
+        book.place(short);
+        book.fill("BTCUSDT", 105.0);
+        let positions = book.positions("BTCUSDT");
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[1].position_side, "SHORT");
+        assert_eq!(positions[1].quantity, -2.0);
+    }
+
+    #[test]
+    fn test_stop_orders() {
+        let mut book = PaperBook::default();
+        let mut stop = PaperOrder::new("BTCUSDT", "SELL", "STOP_MARKET", 0.0, 1.0, "stop");
+        stop.stop_price = 95.0;
+        book.place(stop);
+        let mut take_profit = PaperOrder::new("BTCUSDT", "SELL", "TAKE_PROFIT_MARKET", 0.0, 1.0, "tp");
+        take_profit.stop_price = 110.0;
+        book.place(take_profit);
+
+        assert!(book.fill("BTCUSDT", 100.0).is_empty());
+        let filled = book.fill("BTCUSDT", 94.0);
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled[0].client_order_id, "stop");
+        assert_eq!(filled[0].price, 95.0);
+        assert_eq!(book.fill("BTCUSDT", 111.0)[0].client_order_id, "tp");
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut book = PaperBook::default();
+        let order = book.place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 99.0, 1.0, "a"));
+        book.place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 98.0, 1.0, "b"));
+
+        let canceled = book.cancel("BTCFDUSD", order.order_id).unwrap();
+        assert_eq!(canceled.status, "CANCELED");
+        assert!(book.cancel_by_client_id("BTCFDUSD", "a").is_none());
+        assert_eq!(book.find("BTCFDUSD", "a").unwrap().status, "CANCELED");
+        assert_eq!(book.cancel_all("BTCFDUSD").len(), 1);
+        assert!(book.open_orders().is_empty());
+    }
+
+    #[test]
+    fn test_push_candle() {
+        let mut book = PaperBook::replay();
+        book.place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 95.0, 1.0, "buy"));
+        book.place(PaperOrder::new("BTCFDUSD", "SELL", "LIMIT", 110.0, 1.0, "sell"));
+        let kline = kline(0, "94", "105", "101");
+
+        let filled = book.push_candle("BTCFDUSD", &kline);
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled[0].client_order_id, "buy");
+        assert_eq!(book.replay_price("BTCFDUSD"), Some(101.0));
+        assert_eq!(book.replay_klines("BTCFDUSD", Some(10)).unwrap().len(), 1);
+        assert_eq!(PaperBook::default().replay_price("BTCFDUSD"), None);
+    }
+
+    #[test]
+    fn test_fill_candles() {
+        let mut book = PaperBook::default();
+        assert_eq!(book.candles_from("BTCFDUSD"), None);
+        let buy = book.place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 95.0, 1.0, "buy"));
+        book.place(PaperOrder::new("BTCFDUSD", "SELL", "LIMIT", 110.0, 1.0, "sell"));
+        assert_eq!(book.candles_from("BTCFDUSD"), Some(buy.time));
+
+        // The dip to 94 came before the buy was placed, the one to 94.5 after.
+        let before = kline(buy.time - 60_000, "94", "101", "100");
+        assert!(book.fill_candles("BTCFDUSD", std::slice::from_ref(&before), 100.0).is_empty());
+        let after = kline(buy.time + 60_000, "94.5", "101", "100");
+        let filled = book.fill_candles("BTCFDUSD", &[before, after], 100.0);
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled[0].client_order_id, "buy");
+        assert_eq!(filled[0].price, 95.0);
+        assert_eq!(book.candles_from("BTCFDUSD"), Some(buy.time + 60_000));
+
+        // The live price alone still fills.
+        assert_eq!(book.fill_candles("BTCFDUSD", &[], 111.0).len(), 1);
+    }
+
+    #[test]
+    fn test_robot_book() {
+        let robot_id = "paper-robot";
+        robot_book(robot_id)
+            .lock()
+            .unwrap()
+            .place(PaperOrder::new("BTCFDUSD", "BUY", "LIMIT", 99.0, 1.0, "buy"));
+        robot_book(robot_id).lock().unwrap().fill("BTCFDUSD", 98.0);
+        save_robot_book(robot_id);
+
+        // A restart loads the book back, with its position.
+        ROBOT_BOOKS.lock().unwrap().clear();
+        let book = robot_book(robot_id);
+        assert_eq!(book.lock().unwrap().positions("BTCFDUSD")[0].quantity, 1.0);
+        assert_eq!(book.lock().unwrap().find("BTCFDUSD", "buy").unwrap().status, "FILLED");
+
+        delete_robot_book(robot_id);
+        assert!(robot_book(robot_id).lock().unwrap().filled_orders().is_empty());
+    }
+}
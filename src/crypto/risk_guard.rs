@@ -0,0 +1,378 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::client::binance_domain::Kline;
+use crate::client::db_client::DBClient;
+use crate::config::app_config::{AppConfig, RiskConfig};
+use crate::crypto::exchange::{
+    Balance, Exchange, ExchangeOrder, ExchangePosition, ExchangeTrade, OrderRequest,
+};
+use crate::crypto::paper_book;
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::crypto::trading_mode::TradingMode;
+use crate::ledger::pnl;
+use crate::robot::robot::{Robot, RobotError};
+use crate::strategy::strategy::order_tag;
+use crate::util::time_util::now_timestamp;
+
+/// Quantities below this are treated as zero.
+const EPSILON: f64 = 1e-12;
+
+/// An order blocked by a risk limit, named after its `RiskLimits` field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiskViolation {
+    pub limit: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for RiskViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "risk limit {} breached: {}", self.limit, self.message)
+    }
+}
+
+impl std::error::Error for RiskViolation {}
+
+fn violation(limit: &'static str, message: String) -> anyhow::Error {
+    RiskViolation { limit, message }.into()
+}
+
+/// Exchange of a robot that checks each order against the `[risk]` limits before the venue sees
+/// it. Orders that cannot be checked, because a price, the position or the ledger is
+/// unavailable, are blocked too.
+pub struct RiskGuard {
+    inner: Arc<dyn Exchange>,
+    robot_id: String,
+    mode: TradingMode,
+    config: RiskConfig,
+    db: &'static Mutex<DBClient>,
+}
+
+impl RiskGuard {
+    pub fn new(inner: Arc<dyn Exchange>, robot_id: &str, mode: TradingMode) -> RiskGuard {
+        RiskGuard {
+            inner,
+            robot_id: robot_id.to_string(),
+            mode,
+            config: AppConfig::get().risk.clone(),
+            db: DBClient::get_instance(),
+        }
+    }
+
+    /// Net position on `symbol` across robots, as it stands now: the venue's, which every robot
+    /// on the account shares, or the sum of the paper robots' books.
+    async fn position(&self, symbol: &str) -> Result<f64> {
+        if self.mode == TradingMode::Paper {
+            return Ok(paper_book::robot_books_position(symbol));
+        }
+        Ok(self
+            .inner
+            .positions(symbol)
+            .await?
+            .iter()
+            .map(|position| position.quantity)
+            .sum())
+    }
+
+    async fn check(&self, request: &OrderRequest) -> Result<()> {
+        let limits = self.config.limits_for(&request.symbol);
+        let price = match request.price > 0.0 {
+            true => request.price,
+            false => match request.stop_price {
+                Some(stop_price) => stop_price,
+                None => self.inner.current_price(&request.symbol).await?,
+            },
+        };
+
+        let notional = price * request.quantity;
+        if let Some(max) = limits.max_order_notional {
+            if notional > max {
+                return Err(violation(
+                    "max_order_notional",
+                    format!("order notional {} is above {}", notional, max),
+                ));
+            }
+        }
+
+        if let Some(max) = limits.max_price_deviation {
+            let last = self.inner.current_price(&request.symbol).await?;
+            let deviation = (price - last).abs() / last;
+            if deviation > max {
+                return Err(violation(
+                    "max_price_deviation",
+                    format!("price {} is {:.4} away from the last trade {}, above {}", price, deviation, last, max),
+                ));
+            }
+        }
+
+        if limits.max_open_orders.is_none() && limits.max_position.is_none() && limits.max_daily_loss.is_none() {
+            return Ok(());
+        }
+        let tag = order_tag(&self.robot_id);
+        let open_orders: Vec<ExchangeOrder> = self
+            .inner
+            .open_orders(&request.symbol)
+            .await?
+            .into_iter()
+            .filter(|order| order.client_order_id.starts_with(&tag))
+            .collect();
+        if let Some(max) = limits.max_open_orders {
+            if open_orders.len() >= max {
+                return Err(violation(
+                    "max_open_orders",
+                    format!("robot already has {} open orders on {}", open_orders.len(), request.symbol),
+                ));
+            }
+        }
+
+        if limits.max_position.is_none() && limits.max_daily_loss.is_none() {
+            return Ok(());
+        }
+        let position = self.position(&request.symbol).await?;
+        let quantity = signed(&request.side, request.quantity);
+        let reduces = request.reduce_only
+            || request.close_position
+            || position * quantity < 0.0 && quantity.abs() <= position.abs() + EPSILON;
+
+        if let Some(max) = limits.max_position {
+            // Worst case: every open order on the same side fills along with this one.
+            let pending: f64 = open_orders
+                .iter()
+                .filter(|order| order.side == request.side)
+                .map(|order| signed(&order.side, order.orig_qty - order.executed_qty))
+                .sum();
+            let exposure = position + pending + quantity;
+            if exposure.abs() > max + EPSILON && exposure.abs() > position.abs() {
+                return Err(violation(
+                    "max_position",
+                    format!("position on {} could reach {}, above {}", request.symbol, exposure, max),
+                ));
+            }
+        }
+
+        if let Some(max) = limits.max_daily_loss {
+            let loss = pnl::daily_loss(&self.db.lock().unwrap(), &self.robot_id, now_timestamp())?;
+            if loss >= max && !reduces {
+                return Err(violation(
+                    "max_daily_loss",
+                    format!("robot lost {} today, the limit is {}", loss, max),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Logs a blocked order and, when configured, suspends the robot. The suspension runs on its
+    /// own thread since it blocks on the scheduler and database locks, which the runtime thread
+    /// placing the order must not wait on.
+    fn breach(&self, request: &OrderRequest, violation: &RiskViolation) {
+        println!(
+            "Blocked {} order {} of robot {}: {}",
+            request.side, request.client_order_id, self.robot_id, violation
+        );
+        if !self.config.suspend_on_breach {
+            return;
+        }
+        let robot_id = self.robot_id.clone();
+        thread::spawn(move || match Robot::suspend(&robot_id) {
+            Ok(_) => println!("Suspended robot {} after a risk limit breach", robot_id),
+            Err(RobotError::Conflict(_)) => {}
+            Err(err) => println!("Failed to suspend robot {}: {}", robot_id, err),
+        });
+    }
+}
+
+fn signed(side: &str, quantity: f64) -> f64 {
+    match side {
+        "SELL" => -quantity,
+        _ => quantity,
+    }
+}
+
+#[async_trait]
+impl Exchange for RiskGuard {
+    async fn current_price(&self, symbol: &str) -> Result<f64> {
+        self.inner.current_price(symbol).await
+    }
+
+    async fn klines(&self, symbol: &str, interval: &str, limit: Option<u16>) -> Result<Vec<Kline>> {
+        self.inner.klines(symbol, interval, limit).await
+    }
+
+    async fn place_order(&self, request: &OrderRequest) -> Result<ExchangeOrder> {
+        if let Err(err) = self.check(request).await {
+            if let Some(violation) = err.downcast_ref::<RiskViolation>() {
+                self.breach(request, violation);
+            }
+            return Err(err);
+        }
+        self.inner.place_order(request).await
+    }
+
+    async fn cancel_order(&self, symbol: &str, order_id: u64) -> Result<ExchangeOrder> {
+        self.inner.cancel_order(symbol, order_id).await
+    }
+
+    async fn query_order(&self, symbol: &str, client_order_id: &str) -> Result<ExchangeOrder> {
+        self.inner.query_order(symbol, client_order_id).await
+    }
+
+    async fn open_orders(&self, symbol: &str) -> Result<Vec<ExchangeOrder>> {
+        self.inner.open_orders(symbol).await
+    }
+
+    async fn balances(&self) -> Result<Vec<Balance>> {
+        self.inner.balances().await
+    }
+
+    async fn symbol_rules(&self, symbol: &str) -> Result<SymbolRules> {
+        self.inner.symbol_rules(symbol).await
+    }
+
+    async fn order_history(&self, symbol: &str, from_order_id: Option<u64>) -> Result<Vec<ExchangeOrder>> {
+        self.inner.order_history(symbol, from_order_id).await
+    }
+
+    async fn trade_history(&self, symbol: &str, from_trade_id: Option<u64>) -> Result<Vec<ExchangeTrade>> {
+        self.inner.trade_history(symbol, from_trade_id).await
+    }
+
+    async fn positions(&self, symbol: &str) -> Result<Vec<ExchangePosition>> {
+        self.inner.positions(symbol).await
+    }
+
+    async fn change_leverage(&self, symbol: &str, leverage: u8) -> Result<()> {
+        self.inner.change_leverage(symbol, leverage).await
+    }
+
+    async fn set_hedge_mode(&self, hedge: bool) -> Result<()> {
+        self.inner.set_hedge_mode(hedge).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use rusqlite::Connection;
+
+    use crate::client::db_client::{DBClient, PnlRecord};
+    use crate::config::app_config::{RiskConfig, RiskLimits};
+    use crate::crypto::exchange::{Exchange, OrderRequest};
+    use crate::crypto::mock_exchange::MockExchange;
+    use crate::crypto::paper_book::{robot_book, PaperOrder};
+    use crate::crypto::trading_mode::TradingMode;
+    use crate::ledger::pnl::Pnl;
+
+    use super::{RiskGuard, RiskViolation};
+
+    fn guard(limits: RiskLimits) -> (RiskGuard, Arc<MockExchange>) {
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        (guard_on(limits, exchange.clone(), "robot-1", TradingMode::Live), exchange)
+    }
+
+    fn guard_on(limits: RiskLimits, exchange: Arc<MockExchange>, robot_id: &str, mode: TradingMode) -> RiskGuard {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_ledger_tables().unwrap();
+        db_client.create_pnl_table().unwrap();
+        RiskGuard {
+            inner: exchange,
+            robot_id: robot_id.to_string(),
+            mode,
+            config: RiskConfig {
+                limits,
+                ..RiskConfig::default()
+            },
+            db: Box::leak(Box::new(Mutex::new(db_client))),
+        }
+    }
+
+    fn order(side: &str, price: f64, quantity: f64, id: &str) -> OrderRequest {
+        OrderRequest::limit("BTCUSDT", side, price, quantity, id)
+    }
+
+    async fn blocked_by(guard: &RiskGuard, request: &OrderRequest) -> Option<&'static str> {
+        guard
+            .place_order(request)
+            .await
+            .err()
+            .and_then(|err| err.downcast_ref::<RiskViolation>().map(|violation| violation.limit))
+    }
+
+    #[tokio::test]
+    async fn test_notional_and_deviation() {
+        let (guard, exchange) = guard(RiskLimits {
+            max_order_notional: Some(500.0),
+            max_price_deviation: Some(0.05),
+            ..RiskLimits::default()
+        });
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 6.0, "robot1-1")).await, Some("max_order_notional"));
+        assert_eq!(blocked_by(&guard, &order("BUY", 90.0, 1.0, "robot1-2")).await, Some("max_price_deviation"));
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 1.0, "robot1-3")).await, None);
+        assert_eq!(exchange.open_orders("BTCUSDT").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_open_orders_and_position() {
+        let (guard, exchange) = guard(RiskLimits {
+            max_open_orders: Some(3),
+            max_position: Some(1.0),
+            ..RiskLimits::default()
+        });
+        // Another robot on the account already holds 0.3 and has a buy resting.
+        exchange
+            .place_order(&order("BUY", 98.0, 5.0, "otherrobot-1"))
+            .await
+            .unwrap();
+        exchange
+            .place_order(&order("BUY", 100.0, 0.3, "otherrobot-2"))
+            .await
+            .unwrap();
+        exchange.set_price("BTCUSDT", 100.0);
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 0.6, "robot1-1")).await, None);
+        assert_eq!(blocked_by(&guard, &order("BUY", 98.5, 0.2, "robot1-2")).await, Some("max_position"));
+        assert_eq!(blocked_by(&guard, &order("SELL", 101.0, 0.6, "robot1-3")).await, None);
+        assert_eq!(blocked_by(&guard, &order("SELL", 102.0, 0.3, "robot1-4")).await, None);
+        assert_eq!(blocked_by(&guard, &order("SELL", 103.0, 0.1, "robot1-5")).await, Some("max_open_orders"));
+    }
+
+    #[tokio::test]
+    async fn test_paper_position() {
+        // The paper robots' books add up: another robot bought 0.8 on its own book.
+        let peer = robot_book("risk-peer");
+        peer.lock().unwrap().place(PaperOrder::new("BTCUSDT", "BUY", "LIMIT", 100.0, 0.8, "riskpeer-1"));
+        peer.lock().unwrap().fill("BTCUSDT", 100.0);
+        let exchange = Arc::new(MockExchange::with_book(robot_book("risk-robot")));
+        let limits = RiskLimits {
+            max_position: Some(1.0),
+            ..RiskLimits::default()
+        };
+        let guard = guard_on(limits, exchange, "risk-robot", TradingMode::Paper);
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 0.3, "riskrobot-1")).await, Some("max_position"));
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 0.2, "riskrobot-2")).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_daily_loss() {
+        let (guard, _) = guard(RiskLimits {
+            max_daily_loss: Some(50.0),
+            ..RiskLimits::default()
+        });
+        let yesterday = PnlRecord {
+            time: 0,
+            pnl: Pnl {
+                net: 80.0,
+                ..Pnl::default()
+            },
+        };
+        guard.db.lock().unwrap().save_pnl("robot-1", &yesterday).unwrap();
+        assert_eq!(blocked_by(&guard, &order("BUY", 99.0, 1.0, "robot1-1")).await, Some("max_daily_loss"));
+    }
+}
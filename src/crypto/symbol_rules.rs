@@ -0,0 +1,246 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+
+use crate::client::binance_domain::{ExchangeInformation, SymbolFilter, SymbolInfo};
+use crate::crypto::exchange::OrderRequest;
+use crate::util::binance_error::{BinanceError, Result};
+use crate::util::number_util::{get_precision, round};
+
+/// How long a venue's exchangeInfo is trusted before it is fetched again.
+const RULES_TTL: Duration = Duration::from_secs(3600);
+
+struct CachedRules {
+    fetched_at: Instant,
+    symbols: HashMap<String, SymbolRules>,
+}
+
+/// exchangeInfo per host, shared by every client talking to that host.
+static RULES: Lazy<Mutex<HashMap<String, CachedRules>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Trading filters of one symbol: PRICE_FILTER, LOT_SIZE and (MIN_)NOTIONAL.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolRules {
+    pub symbol: String,
+    pub tick_size: f64,
+    pub step_size: f64,
+    pub min_qty: f64,
+    pub min_notional: f64,
+}
+
+impl SymbolRules {
+    /// Rules that accept anything down to 8 decimals, for venues without filters.
+    pub fn unrestricted(symbol: &str) -> SymbolRules {
+        SymbolRules {
+            symbol: symbol.to_string(),
+            tick_size: 0.00000001,
+            step_size: 0.00000001,
+            min_qty: 0.0,
+            min_notional: 0.0,
+        }
+    }
+
+    pub fn from_info(info: &SymbolInfo) -> SymbolRules {
+        let mut rules = SymbolRules::unrestricted(&info.symbol);
+        for filter in info.filters.iter() {
+            match filter {
+                SymbolFilter::PriceFilter { tick_size } if *tick_size > 0.0 => {
+                    rules.tick_size = *tick_size
+                }
+                SymbolFilter::LotSize { min_qty, step_size } => {
+                    rules.min_qty = *min_qty;
+                    if *step_size > 0.0 {
+                        rules.step_size = *step_size;
+                    }
+                }
+                SymbolFilter::MinNotional { min_notional } | SymbolFilter::Notional { min_notional } => {
+                    rules.min_notional = rules.min_notional.max(*min_notional)
+                }
+                _ => {}
+            }
+        }
+        rules
+    }
+
+    /// Snaps `price` to the nearest multiple of the tick size.
+    pub fn round_price(&self, price: f64) -> f64 {
+        round(
+            (price / self.tick_size).round() * self.tick_size,
+            get_precision(self.tick_size),
+        )
+    }
+
+    /// Snaps `quantity` down to a multiple of the step size, so it never exceeds what was asked.
+    pub fn round_quantity(&self, quantity: f64) -> f64 {
+        // Tolerate float noise such as 0.3 / 0.1 = 2.9999999999999996.
+        let steps = (quantity / self.step_size + 1e-9).floor();
+        round(steps * self.step_size, get_precision(self.step_size))
+    }
+
+    /// `price` written out to the tick size's decimals, as the order params want it.
+    pub fn format_price(&self, price: f64) -> String {
+        format!("{:.*}", get_precision(self.tick_size) as usize, price)
+    }
+
+    /// `quantity` written out to the step size's decimals, as the order params want it.
+    pub fn format_quantity(&self, quantity: f64) -> String {
+        format!("{:.*}", get_precision(self.step_size) as usize, quantity)
+    }
+
+    /// Quantity that spends about `order_size` in quote at `price`.
+    pub fn quantity_for(&self, order_size: f64, price: f64) -> f64 {
+        self.round_quantity(order_size / price)
+    }
+
+    /// Snaps the request's price and quantity and rejects it if it would fail LOT_SIZE or
+    /// MIN_NOTIONAL on the exchange.
+    pub fn normalize(&self, request: &OrderRequest) -> Result<OrderRequest> {
+        let mut request = request.clone();
+        request.price = self.round_price(request.price);
+        request.quantity = self.round_quantity(request.quantity);
+        request.stop_price = request.stop_price.map(|stop_price| self.round_price(stop_price));
+        if request.close_position {
+            return Ok(request);
+        }
+        if request.quantity <= 0.0 || request.quantity < self.min_qty {
+            return Err(BinanceError::FilterViolation {
+                code: -1013,
+                msg: format!(
+                    "Filter failure: LOT_SIZE, {} {} is below {}",
+                    request.symbol, request.quantity, self.min_qty
+                ),
+            });
+        }
+        // Market stop orders are worth their quantity at the stop price.
+        let price = match request.price > 0.0 {
+            true => request.price,
+            false => request.stop_price.unwrap_or(0.0),
+        };
+        let notional = price * request.quantity;
+        if notional < self.min_notional {
+            return Err(BinanceError::FilterViolation {
+                code: -1013,
+                msg: format!(
+                    "Filter failure: MIN_NOTIONAL, {} {} is below {}",
+                    request.symbol, notional, self.min_notional
+                ),
+            });
+        }
+        Ok(request)
+    }
+}
+
+/// Returns the rules of `symbol` on `host`, calling `fetch` for a fresh exchangeInfo when the
+/// cached one is missing, expired, or does not know the symbol.
+pub async fn cached_rules<F, Fut>(host: &str, symbol: &str, fetch: F) -> Result<SymbolRules>
+where
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<ExchangeInformation>>,
+{
+    let fresh = RULES.lock().unwrap().get(host).and_then(|cached| {
+        if cached.fetched_at.elapsed() > RULES_TTL {
+            return None;
+        }
+        cached.symbols.get(symbol).cloned()
+    });
+    if let Some(rules) = fresh {
+        return Ok(rules);
+    }
+
+    let info = fetch().await?;
+    let symbols: HashMap<String, SymbolRules> = info
+        .symbols
+        .iter()
+        .map(|symbol| (symbol.symbol.clone(), SymbolRules::from_info(symbol)))
+        .collect();
+    let rules = symbols.get(symbol).cloned();
+    RULES.lock().unwrap().insert(
+        host.to_string(),
+        CachedRules {
+            fetched_at: Instant::now(),
+            symbols,
+        },
+    );
+    rules.ok_or_else(|| BinanceError::Api {
+        code: -1121,
+        msg: format!("Invalid symbol {}", symbol),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::binance_domain::ExchangeInformation;
+    use crate::crypto::exchange::OrderRequest;
+    use crate::util::binance_error::BinanceError;
+
+    use super::{cached_rules, SymbolRules};
+
+    const EXCHANGE_INFO: &str = r#"{"symbols":[{"symbol":"BTCFDUSD","status":"TRADING","filters":[
+        {"filterType":"PRICE_FILTER","minPrice":"0.01","maxPrice":"1000000.00","tickSize":"0.01000000"},
+        {"filterType":"LOT_SIZE","minQty":"0.00001000","maxQty":"9000.00000000","stepSize":"0.00001000"},
+        {"filterType":"ICEBERG_PARTS","limit":10},
+        {"filterType":"NOTIONAL","minNotional":"5.00000000","applyMinToMarket":true}]}]}"#;
+
+    fn rules() -> SymbolRules {
+        let info: ExchangeInformation = serde_json::from_str(EXCHANGE_INFO).unwrap();
+        SymbolRules::from_info(&info.symbols[0])
+    }
+
+    #[test]
+    fn test_from_info() {
+        let rules = rules();
+        assert_eq!(rules.tick_size, 0.01);
+        assert_eq!(rules.step_size, 0.00001);
+        assert_eq!(rules.min_notional, 5.0);
+
+        let info: ExchangeInformation = serde_json::from_str(
+            r#"{"symbols":[{"symbol":"BTCUSDT","filters":[{"filterType":"MIN_NOTIONAL","notional":"100"}]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(SymbolRules::from_info(&info.symbols[0]).min_notional, 100.0);
+    }
+
+    #[test]
+    fn test_rounding() {
+        let rules = rules();
+        assert_eq!(rules.round_price(65432.1789), 65432.18);
+        assert_eq!(rules.round_quantity(0.123456789), 0.12345);
+        assert_eq!(rules.quantity_for(100.0, 65000.0), 0.00153);
+        assert_eq!(rules.format_quantity(0.00153), "0.00153");
+        assert_eq!(rules.format_price(65432.1), "65432.10");
+
+        let mut rules = SymbolRules::unrestricted("PEPEFDUSD");
+        rules.step_size = 1.0;
+        assert_eq!(rules.format_quantity(123456789.0), "123456789");
+    }
+
+    #[test]
+    fn test_normalize() {
+        let rules = rules();
+        let request = rules
+            .normalize(&OrderRequest::limit("BTCFDUSD", "BUY", 65432.1789, 0.000155, "a"))
+            .unwrap();
+        assert_eq!(request.price, 65432.18);
+        assert_eq!(request.quantity, 0.00015);
+
+        let error = rules
+            .normalize(&OrderRequest::limit("BTCFDUSD", "BUY", 65432.18, 0.00005, "b"))
+            .unwrap_err();
+        assert!(matches!(error, BinanceError::FilterViolation { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_cached_rules() {
+        let host = "https://rules.test";
+        let fetched = cached_rules(host, "BTCFDUSD", || async { Ok(serde_json::from_str(EXCHANGE_INFO)?) })
+            .await
+            .unwrap();
+        let cached = cached_rules(host, "BTCFDUSD", || async { panic!("exchangeInfo fetched twice") })
+            .await
+            .unwrap();
+        assert_eq!(fetched, cached);
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use anyhow::bail;
+
+pub const SPOT_HOST: &str = "https://api.binance.com";
+pub const SPOT_TESTNET_HOST: &str = "https://testnet.binance.vision";
+pub const FUTURES_HOST: &str = "https://fapi.binance.com";
+pub const FUTURES_TESTNET_HOST: &str = "https://testnet.binancefuture.com";
+
+/// Where a robot's orders go: the real exchange, the Binance testnet, or a local paper book
+/// that is filled against live prices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TradingMode {
+    Live,
+    Testnet,
+    Paper,
+}
+
+impl TradingMode {
+    pub fn parse(mode: &str) -> Result<TradingMode, anyhow::Error> {
+        match mode {
+            "live" => Ok(TradingMode::Live),
+            "testnet" => Ok(TradingMode::Testnet),
+            "paper" => Ok(TradingMode::Paper),
+            other => bail!("unknown mode {}, expected live|testnet|paper", other),
+        }
+    }
+
+    pub fn spot_host(&self) -> &'static str {
+        match self {
+            TradingMode::Testnet => SPOT_TESTNET_HOST,
+            _ => SPOT_HOST,
+        }
+    }
+
+    pub fn futures_host(&self) -> &'static str {
+        match self {
+            TradingMode::Testnet => FUTURES_TESTNET_HOST,
+            _ => FUTURES_HOST,
+        }
+    }
+}
+
+impl fmt::Display for TradingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            TradingMode::Live => "live",
+            TradingMode::Testnet => "testnet",
+            TradingMode::Paper => "paper",
+        };
+        write!(f, "{}", mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TradingMode, SPOT_HOST, SPOT_TESTNET_HOST};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(TradingMode::parse("paper").unwrap(), TradingMode::Paper);
+        assert_eq!(TradingMode::parse("testnet").unwrap().spot_host(), SPOT_TESTNET_HOST);
+        assert_eq!(TradingMode::parse("live").unwrap().spot_host(), SPOT_HOST);
+        assert!(TradingMode::parse("papr").is_err());
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::client::db_client::{DBClient, OrderRecord, PnlRecord, TradeRecord};
+use crate::crypto::crypto_client::CryptoClient;
+use crate::crypto::exchange::{Exchange, ExchangeOrder};
+use crate::crypto::futures_client::FutureClient;
+use crate::crypto::trading_mode::TradingMode;
+use crate::ledger::pnl::{self, split_symbol};
+use crate::robot::robot::Robot;
+use crate::strategy::strategy::{account_of, order_tag, StrategyFactory};
+use crate::util::time_util::now_timestamp;
+
+/// How often orders and fills are pulled from Binance.
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+/// Most orders or trades Binance returns per request.
+const PAGE_SIZE: usize = 1000;
+
+/// A symbol one account trades on one venue.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Market {
+    account: String,
+    mode: TradingMode,
+    futures: bool,
+    symbol: String,
+}
+
+pub fn market_name(futures: bool) -> &'static str {
+    if futures {
+        "futures"
+    } else {
+        "spot"
+    }
+}
+
+/// The robot whose tag (see `order_tag`) starts `client_order_id`, if it is still known.
+pub fn robot_of(client_order_id: &str, tags: &HashMap<String, String>) -> Option<String> {
+    client_order_id
+        .split('-')
+        .next()
+        .and_then(|tag| tags.get(tag))
+        .cloned()
+}
+
+/// What one `sync_market` did.
+#[derive(Debug, Default, PartialEq)]
+pub struct Synced {
+    /// Orders fetched, listed or looked up.
+    pub orders: usize,
+    pub trades: usize,
+    /// Orders the ledger holds as open that could not be looked up, and stay open in it.
+    pub unresolved: usize,
+}
+
+/// Pulls the orders, then the fills, of `symbol` newer than what the ledger holds and upserts
+/// them, and brings the orders it holds as open up to date.
+pub async fn sync_market(
+    db: &Mutex<DBClient>,
+    exchange: &dyn Exchange,
+    mode: &str,
+    account: &str,
+    market: &str,
+    symbol: &str,
+    tags: &HashMap<String, String>,
+) -> Result<Synced, anyhow::Error> {
+    let mut orders = 0;
+    let mut cursor = db.lock().unwrap().order_cursor(mode, account, market, symbol)?;
+    loop {
+        let batch = exchange.order_history(symbol, cursor).await?;
+        let db = db.lock().unwrap();
+        for order in batch.iter() {
+            db.upsert_order(&OrderRecord {
+                mode: mode.to_string(),
+                account: account.to_string(),
+                market: market.to_string(),
+                robot_id: robot_of(&order.client_order_id, tags),
+                order: order.clone(),
+            })?;
+        }
+        orders += batch.len();
+        if batch.len() < PAGE_SIZE {
+            break;
+        }
+        cursor = batch.iter().map(|order| order.order_id + 1).max();
+    }
+    let (looked_up, unresolved) = refresh_open_orders(db, exchange, mode, account, market, symbol, tags).await?;
+    orders += looked_up;
+
+    let mut trades = 0;
+    let mut cursor = db.lock().unwrap().trade_cursor(mode, account, market, symbol)?;
+    loop {
+        let batch = exchange.trade_history(symbol, cursor).await?;
+        let db = db.lock().unwrap();
+        for trade in batch.iter() {
+            db.upsert_trade(&TradeRecord {
+                mode: mode.to_string(),
+                account: account.to_string(),
+                market: market.to_string(),
+                robot_id: None,
+                trade: trade.clone(),
+            })?;
+        }
+        trades += batch.len();
+        if batch.len() < PAGE_SIZE {
+            break;
+        }
+        cursor = batch.iter().map(|trade| trade.trade_id + 1).max();
+    }
+    Ok(Synced {
+        orders,
+        trades,
+        unresolved,
+    })
+}
+
+/// Updates the orders the ledger holds as open: the ones still open from the open orders of
+/// `symbol`, the others by looking each up. Returns how many were looked up, and how many could
+/// not be, which stay open in the ledger until a later sync.
+async fn refresh_open_orders(
+    db: &Mutex<DBClient>,
+    exchange: &dyn Exchange,
+    mode: &str,
+    account: &str,
+    market: &str,
+    symbol: &str,
+    tags: &HashMap<String, String>,
+) -> Result<(usize, usize), anyhow::Error> {
+    let stored = db.lock().unwrap().open_order_ids(mode, account, market, symbol)?;
+    if stored.is_empty() {
+        return Ok((0, 0));
+    }
+    let open = exchange.open_orders(symbol).await?;
+    let mut refreshed: Vec<ExchangeOrder> = open
+        .into_iter()
+        .filter(|order| stored.iter().any(|(order_id, _)| *order_id == order.order_id))
+        .collect();
+    let mut looked_up = 0;
+    let mut unresolved = 0;
+    for (order_id, client_order_id) in stored {
+        if refreshed.iter().any(|order| order.order_id == order_id) {
+            continue;
+        }
+        match exchange.query_order(symbol, &client_order_id).await {
+            Ok(order) => {
+                refreshed.push(order);
+                looked_up += 1;
+            }
+            Err(err) => {
+                println!("Failed to look up {} order {}: {}", symbol, order_id, err);
+                unresolved += 1;
+            }
+        }
+    }
+    let db = db.lock().unwrap();
+    for order in refreshed {
+        db.upsert_order(&OrderRecord {
+            mode: mode.to_string(),
+            account: account.to_string(),
+            market: market.to_string(),
+            robot_id: robot_of(&order.client_order_id, tags),
+            order,
+        })?;
+    }
+    Ok((looked_up, unresolved))
+}
+
+/// Records the price of the market's symbol, and of the assets its fees were paid in, for PnL.
+async fn refresh_marks(exchange: &dyn Exchange, market: &Market) -> Result<(), anyhow::Error> {
+    let mode = market.mode.to_string();
+    let market_name = market_name(market.futures);
+    pnl::set_mark(&mode, market_name, &market.symbol, exchange.current_price(&market.symbol).await?);
+
+    let (base, quote) = split_symbol(&market.symbol);
+    let fee_assets = DBClient::get_instance()
+        .lock()
+        .unwrap()
+        .fee_assets(&mode, market_name, &market.symbol)?;
+    let spot = CryptoClient::new_with_mode(&market.account, market.mode);
+    for asset in fee_assets.iter().filter(|asset| **asset != base && **asset != quote) {
+        let symbol = format!("{}{}", asset, quote);
+        pnl::set_mark(&mode, "spot", &symbol, spot.current_price(&symbol).await?.price);
+    }
+    Ok(())
+}
+
+/// Syncs every symbol a live or testnet robot trades, then snapshots each robot's PnL. Paper
+/// orders never reach Binance.
+pub async fn sync() {
+    let factory = StrategyFactory::new();
+    let robots = Robot::all();
+    let tags: HashMap<String, String> = robots
+        .iter()
+        .map(|robot| (order_tag(robot.id()), robot.id().to_string()))
+        .collect();
+    let markets: HashSet<Market> = robots
+        .iter()
+        .filter(|robot| robot.mode() != TradingMode::Paper)
+        .filter_map(|robot| {
+            let params = robot.params();
+            Some(Market {
+                account: account_of(&params),
+                mode: robot.mode(),
+                futures: factory.is_futures(&robot.strategy_name()),
+                symbol: params.get("symbol")?.clone(),
+            })
+        })
+        .collect();
+
+    for market in markets {
+        let exchange: Box<dyn Exchange> = if market.futures {
+            Box::new(FutureClient::new_with_mode(&market.account, market.mode))
+        } else {
+            Box::new(CryptoClient::new_with_mode(&market.account, market.mode))
+        };
+        let synced = match sync_market(
+            DBClient::get_instance(),
+            exchange.as_ref(),
+            &market.mode.to_string(),
+            &market.account,
+            market_name(market.futures),
+            &market.symbol,
+            &tags,
+        )
+        .await
+        {
+            Ok(synced) => refresh_marks(exchange.as_ref(), &market).await.and_then(|_| match synced.unresolved {
+                0 => Ok(()),
+                unresolved => Err(anyhow::anyhow!("{} open order(s) could not be looked up", unresolved)),
+            }),
+            Err(err) => Err(err),
+        };
+        if let Err(err) = synced {
+            println!(
+                "Failed to sync {} {} orders of account {}: {}",
+                market_name(market.futures),
+                market.symbol,
+                market.account,
+                err
+            );
+        }
+    }
+
+    let time = now_timestamp();
+    let db = DBClient::get_instance().lock().unwrap();
+    for robot in robots.iter().filter(|robot| robot.mode() != TradingMode::Paper) {
+        let saved = pnl::robot_pnl(&db, robot.id())
+            .and_then(|pnl| Ok(db.save_pnl(robot.id(), &PnlRecord { time, pnl })?));
+        if let Err(err) = saved {
+            println!("Failed to snapshot PnL of robot {}: {}", robot.id(), err);
+        }
+    }
+}
+
+pub async fn start_sync() {
+    loop {
+        sync().await;
+        tokio::time::sleep(SYNC_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    use rusqlite::Connection;
+
+    use crate::client::db_client::{DBClient, OrderFilter};
+    use crate::crypto::exchange::{Exchange, OrderRequest};
+    use crate::crypto::mock_exchange::MockExchange;
+
+    use super::{robot_of, sync_market, Synced};
+
+    fn synced(orders: usize, trades: usize, unresolved: usize) -> Synced {
+        Synced {
+            orders,
+            trades,
+            unresolved,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sync_market() {
+        let db_client = DBClient {
+            conn: Connection::open_in_memory().unwrap(),
+        };
+        db_client.create_ledger_tables().unwrap();
+        let db = Mutex::new(db_client);
+        let mut tags = HashMap::new();
+        tags.insert("aaaabbbbccccdddd".to_string(), "robot-1".to_string());
+
+        let exchange = MockExchange::new();
+        exchange.set_price("BTCFDUSD", 100.0);
+        for (side, price, client_order_id) in [
+            ("BUY", 99.0, "aaaabbbbccccdddd-1"),
+            ("SELL", 101.0, "aaaabbbbccccdddd-2"),
+            ("BUY", 98.0, "manual"),
+        ] {
+            exchange
+                .place_order(&OrderRequest::limit("BTCFDUSD", side, price, 1.0, client_order_id))
+                .await
+                .unwrap();
+        }
+        assert_eq!(sync_market(&db, &exchange, "live", "default", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(3, 0, 0));
+
+        // Only the order that left the open orders is looked up; nothing newer is listed.
+        exchange.set_price("BTCFDUSD", 98.5);
+        assert_eq!(sync_market(&db, &exchange, "live", "default", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(1, 1, 0));
+        assert_eq!(sync_market(&db, &exchange, "live", "default", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(0, 0, 0));
+
+        exchange
+            .place_order(&OrderRequest::limit("BTCFDUSD", "SELL", 102.0, 1.0, "aaaabbbbccccdddd-3"))
+            .await
+            .unwrap();
+        assert_eq!(sync_market(&db, &exchange, "live", "default", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(1, 0, 0));
+
+        // Another account trading the symbol keeps its own cursor and open orders, even with order
+        // ids below the first account's.
+        let sub = MockExchange::new();
+        sub.place_order(&OrderRequest::limit("BTCFDUSD", "BUY", 90.0, 1.0, "aaaabbbbccccdddd-4"))
+            .await
+            .unwrap();
+        assert_eq!(sync_market(&db, &sub, "live", "sub", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(1, 0, 0));
+        assert_eq!(sync_market(&db, &sub, "live", "sub", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(0, 0, 0));
+
+        // An account that no longer knows the three open orders leaves them open, and says so.
+        let other = MockExchange::new();
+        assert_eq!(sync_market(&db, &other, "live", "default", "spot", "BTCFDUSD", &tags).await.unwrap(), synced(0, 0, 3));
+
+        let db = db.lock().unwrap();
+        let filter = OrderFilter {
+            robot_id: Some("robot-1".to_string()),
+            ..OrderFilter::default()
+        };
+        assert_eq!(db.count_orders(&filter).unwrap(), 4);
+        let filter = OrderFilter {
+            status: Some("FILLED".to_string()),
+            ..OrderFilter::default()
+        };
+        let filled = db.list_orders(&filter, 10, 0).unwrap();
+        assert_eq!(filled.len(), 1);
+        assert_eq!(filled[0].order.client_order_id, "aaaabbbbccccdddd-1");
+    }
+
+    #[test]
+    fn test_robot_of() {
+        let mut tags = HashMap::new();
+        tags.insert("aaaabbbbccccdddd".to_string(), "robot-1".to_string());
+        assert_eq!(robot_of("aaaabbbbccccdddd-42", &tags).as_deref(), Some("robot-1"));
+        assert_eq!(robot_of("web_123", &tags), None);
+    }
+}
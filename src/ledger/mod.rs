@@ -0,0 +1,81 @@
+use std::str::FromStr;
+
+use salvo::{handler, http::StatusCode, prelude::Json, Request, Response};
+use serde_json::{Map, Value};
+
+use crate::client::db_client::{DBClient, OrderFilter};
+
+pub mod ledger;
+pub mod pnl;
+
+const DEFAULT_ORDERS_LIMIT: u32 = 50;
+const MAX_ORDERS_LIMIT: u32 = 500;
+
+/// Reads an optional query param, naming it in the error when it does not parse.
+fn query<T: FromStr>(req: &mut Request, key: &str) -> Result<Option<T>, String> {
+    match req.queries().get(key) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("invalid {}: {}", key, value)),
+        None => Ok(None),
+    }
+}
+
+fn order_filter(req: &mut Request) -> Result<OrderFilter, String> {
+    let upper = |value: Option<String>| value.map(|value| value.to_uppercase());
+    Ok(OrderFilter {
+        robot_id: query(req, "robot")?,
+        symbol: upper(query(req, "symbol")?),
+        side: upper(query(req, "side")?),
+        status: upper(query(req, "status")?),
+        from: query(req, "from")?,
+        to: query(req, "to")?,
+    })
+}
+
+/// Ledger orders, newest first. Filters: `robot`, `symbol`, `side`, `status`, and `from`/`to`
+/// in milliseconds; paged with `limit` (default 50, at most 500) and `offset`.
+#[handler]
+pub async fn get_orders(req: &mut Request, res: &mut Response) {
+    let page = order_filter(req).and_then(|filter| {
+        let limit = query(req, "limit")?.unwrap_or(DEFAULT_ORDERS_LIMIT).min(MAX_ORDERS_LIMIT);
+        let offset = query(req, "offset")?.unwrap_or(0);
+        Ok((filter, limit, offset))
+    });
+    let (filter, limit, offset) = match page {
+        Ok(page) => page,
+        Err(err) => {
+            res.status_code(StatusCode::BAD_REQUEST);
+            res.render(err);
+            return;
+        }
+    };
+
+    // The database lock is shared with the ledger sync, so wait for it off the executor.
+    let orders = tokio::task::spawn_blocking(move || {
+        let db = DBClient::get_instance().lock().unwrap();
+        db.list_orders(&filter, limit, offset)
+            .and_then(|orders| Ok((orders, db.count_orders(&filter)?)))
+    })
+    .await
+    .map_err(anyhow::Error::from)
+    .and_then(|orders| Ok(orders?));
+    match orders {
+        Ok((orders, total)) => {
+            let mut page = Map::new();
+            page.insert("total".to_string(), Value::from(total));
+            page.insert("limit".to_string(), Value::from(limit));
+            page.insert("offset".to_string(), Value::from(offset));
+            page.insert(
+                "orders".to_string(),
+                serde_json::to_value(orders).unwrap_or_default(),
+            );
+            res.render(Json(page));
+        }
+        Err(err) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(format!("failed to load orders: {}", err));
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::client::db_client::{DBClient, TradeRecord};
+use crate::crypto::exchange::ExchangeTrade;
+
+/// Quote assets recognised when splitting a symbol, longest first so FDUSD wins over USD.
+const QUOTE_ASSETS: [&str; 8] = ["FDUSD", "USDT", "USDC", "TUSD", "BUSD", "BTC", "ETH", "BNB"];
+
+/// Quantities below this are treated as zero.
+const EPSILON: f64 = 1e-12;
+
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Last prices seen by the ledger sync, keyed by `mode:market:symbol`.
+static MARKS: Lazy<Mutex<HashMap<String, f64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn mark_key(mode: &str, market: &str, symbol: &str) -> String {
+    format!("{}:{}:{}", mode, market, symbol)
+}
+
+pub fn set_mark(mode: &str, market: &str, symbol: &str, price: f64) {
+    MARKS
+        .lock()
+        .unwrap()
+        .insert(mark_key(mode, market, symbol), price);
+}
+
+pub fn mark(mode: &str, market: &str, symbol: &str) -> Option<f64> {
+    MARKS
+        .lock()
+        .unwrap()
+        .get(&mark_key(mode, market, symbol))
+        .cloned()
+}
+
+/// Splits `symbol` into base and quote asset, e.g. BTCFDUSD into BTC and FDUSD.
+pub fn split_symbol(symbol: &str) -> (String, String) {
+    for quote in QUOTE_ASSETS {
+        if let Some(base) = symbol.strip_suffix(quote) {
+            if !base.is_empty() {
+                return (base.to_string(), quote.to_string());
+            }
+        }
+    }
+    (symbol.to_string(), String::new())
+}
+
+/// Open inventory of one symbol after FIFO matching. A negative quantity is a short.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub symbol: String,
+    pub quantity: f64,
+    pub average_cost: f64,
+    /// Last known price, `None` until the ledger sync has fetched one.
+    pub price: Option<f64>,
+    pub unrealized: f64,
+}
+
+/// PnL of a robot in quote asset. `net` is realized minus fees plus unrealized.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Pnl {
+    pub realized: f64,
+    pub fees: f64,
+    pub unrealized: f64,
+    pub net: f64,
+    pub positions: Vec<Position>,
+    /// Fees in assets no price was known for, by asset; they are not in `fees`.
+    pub unconverted_fees: BTreeMap<String, f64>,
+}
+
+impl Pnl {
+    fn add(&mut self, other: Pnl) {
+        self.realized += other.realized;
+        self.fees += other.fees;
+        self.unrealized += other.unrealized;
+        self.net = self.realized - self.fees + self.unrealized;
+        self.positions.extend(other.positions);
+        for (asset, amount) in other.unconverted_fees {
+            *self.unconverted_fees.entry(asset).or_insert(0.0) += amount;
+        }
+    }
+}
+
+struct Lot {
+    quantity: f64,
+    price: f64,
+}
+
+/// PnL of the fills of one symbol, oldest first. Sells close the oldest buys first and buys the
+/// oldest sells. Fees are converted to quote with the fill price when paid in the base asset and
+/// with `rate` (price of the asset in quote) otherwise. A buy whose fee is taken in the base asset
+/// only adds what is left of it to the inventory.
+pub fn compute<F>(symbol: &str, trades: &[ExchangeTrade], price: Option<f64>, rate: F) -> Pnl
+where
+    F: Fn(&str) -> Option<f64>,
+{
+    let (base, quote) = split_symbol(symbol);
+    let mut pnl = Pnl::default();
+    let mut lots: VecDeque<Lot> = VecDeque::new();
+
+    for trade in trades {
+        let mut remaining = match trade.side.as_str() {
+            "BUY" if trade.commission_asset == base => trade.quantity - trade.commission,
+            "BUY" => trade.quantity,
+            _ => -trade.quantity,
+        };
+        while remaining.abs() > EPSILON {
+            let lot = match lots.front_mut() {
+                Some(lot) if lot.quantity.signum() != remaining.signum() => lot,
+                _ => break,
+            };
+            let matched = remaining.abs().min(lot.quantity.abs());
+            pnl.realized += matched * (trade.price - lot.price) * lot.quantity.signum();
+            lot.quantity -= matched * lot.quantity.signum();
+            remaining -= matched * remaining.signum();
+            if lot.quantity.abs() <= EPSILON {
+                lots.pop_front();
+            }
+        }
+        if remaining.abs() > EPSILON {
+            lots.push_back(Lot {
+                quantity: remaining,
+                price: trade.price,
+            });
+        }
+
+        if trade.commission == 0.0 {
+            continue;
+        }
+        let asset = trade.commission_asset.as_str();
+        let fee = if asset == quote {
+            Some(trade.commission)
+        } else if asset == base {
+            Some(trade.commission * trade.price)
+        } else {
+            rate(asset).map(|rate| trade.commission * rate)
+        };
+        match fee {
+            Some(fee) => pnl.fees += fee,
+            None => *pnl.unconverted_fees.entry(asset.to_string()).or_insert(0.0) += trade.commission,
+        }
+    }
+
+    let quantity: f64 = lots.iter().map(|lot| lot.quantity).sum();
+    if quantity.abs() > EPSILON {
+        let cost: f64 = lots.iter().map(|lot| lot.quantity * lot.price).sum();
+        let unrealized = price.map(|price| price * quantity - cost).unwrap_or(0.0);
+        pnl.unrealized = unrealized;
+        pnl.positions.push(Position {
+            symbol: symbol.to_string(),
+            quantity,
+            average_cost: cost / quantity,
+            price,
+            unrealized,
+        });
+    }
+    pnl.net = pnl.realized - pnl.fees + pnl.unrealized;
+    pnl
+}
+
+/// PnL of every fill the ledger attributes to a robot, valued at the last synced prices.
+pub fn robot_pnl(db: &DBClient, robot_id: &str) -> Result<Pnl, anyhow::Error> {
+    let mut markets: BTreeMap<(String, String, String), Vec<ExchangeTrade>> = BTreeMap::new();
+    for TradeRecord {
+        mode,
+        market,
+        trade,
+        ..
+    } in db.list_trades(robot_id)?
+    {
+        markets
+            .entry((mode, market, trade.symbol.clone()))
+            .or_default()
+            .push(trade);
+    }
+
+    let mut pnl = Pnl::default();
+    for ((mode, market, symbol), trades) in markets {
+        let (_, quote) = split_symbol(&symbol);
+        let rate = |asset: &str| mark(&mode, "spot", &format!("{}{}", asset, quote));
+        pnl.add(compute(&symbol, &trades, mark(&mode, &market, &symbol), rate));
+    }
+    Ok(pnl)
+}
+
+/// How much a robot's net PnL dropped since the last UTC midnight before `now`, measured from
+/// the last snapshot taken by then; negative when it gained. A robot without such a snapshot
+/// started today, from zero.
+pub fn daily_loss(db: &DBClient, robot_id: &str, now: i64) -> Result<f64, anyhow::Error> {
+    let midnight = now - now.rem_euclid(DAY_MS);
+    let baseline = db
+        .list_pnl(robot_id, 0, midnight, 1)?
+        .first()
+        .map(|record| record.pnl.net)
+        .unwrap_or(0.0);
+    Ok(baseline - robot_pnl(db, robot_id)?.net)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::crypto::exchange::ExchangeTrade;
+
+    use super::{compute, split_symbol};
+
+    fn trade(side: &str, price: f64, quantity: f64, commission: f64, asset: &str) -> ExchangeTrade {
+        ExchangeTrade {
+            trade_id: 0,
+            order_id: 0,
+            symbol: "BTCUSDT".to_string(),
+            side: side.to_string(),
+            price,
+            quantity,
+            quote_qty: price * quantity,
+            commission,
+            commission_asset: asset.to_string(),
+            realized_pnl: 0.0,
+            maker: true,
+            time: 0,
+        }
+    }
+
+    #[test]
+    fn test_split_symbol() {
+        assert_eq!(split_symbol("BTCFDUSD"), ("BTC".to_string(), "FDUSD".to_string()));
+        assert_eq!(split_symbol("ETHBTC"), ("ETH".to_string(), "BTC".to_string()));
+        assert_eq!(split_symbol("XYZ"), ("XYZ".to_string(), String::new()));
+    }
+
+    #[test]
+    fn test_fifo() {
+        let trades = vec![
+            trade("BUY", 100.0, 1.0, 0.1, "USDT"),
+            trade("BUY", 110.0, 1.0, 0.001, "BTC"),
+            trade("SELL", 120.0, 1.5, 0.5, "BNB"),
+        ];
+        let pnl = compute("BTCUSDT", &trades, Some(100.0), |asset| match asset {
+            "BNB" => Some(600.0),
+            _ => None,
+        });
+        // 1 @ 100 and 0.5 @ 110 are closed at 120; the second buy only brought 0.999 in.
+        assert!((pnl.realized - 25.0).abs() < 1e-9);
+        assert!((pnl.fees - (0.1 + 0.11 + 300.0)).abs() < 1e-9);
+        assert_eq!(pnl.positions.len(), 1);
+        assert!((pnl.positions[0].quantity - 0.499).abs() < 1e-9);
+        assert!((pnl.positions[0].average_cost - 110.0).abs() < 1e-9);
+        assert!((pnl.unrealized + 4.99).abs() < 1e-9);
+        assert!((pnl.net - (25.0 - pnl.fees - 4.99)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_base_asset_fee() {
+        // Without BNB the buy's fee is taken from the BTC bought, so 0.999 is all there is to sell.
+        let trades = vec![
+            trade("BUY", 100.0, 1.0, 0.001, "BTC"),
+            trade("SELL", 110.0, 0.999, 0.10989, "USDT"),
+        ];
+        let pnl = compute("BTCUSDT", &trades, Some(110.0), |_| None);
+        assert!(pnl.positions.is_empty());
+        assert!((pnl.realized - 9.99).abs() < 1e-9);
+        assert!((pnl.fees - (0.1 + 0.10989)).abs() < 1e-9);
+        assert_eq!(pnl.unrealized, 0.0);
+        assert!((pnl.net - (9.99 - 0.20989)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_short_and_unconverted_fees() {
+        let trades = vec![
+            trade("SELL", 100.0, 2.0, 0.2, "BNB"),
+            trade("BUY", 90.0, 3.0, 0.0, "USDT"),
+        ];
+        let pnl = compute("BTCUSDT", &trades, None, |_| None);
+        assert!((pnl.realized - 20.0).abs() < 1e-9);
+        assert_eq!(pnl.unconverted_fees.get("BNB"), Some(&0.2));
+        assert!((pnl.positions[0].quantity - 1.0).abs() < 1e-9);
+        assert_eq!(pnl.positions[0].price, None);
+        assert_eq!(pnl.unrealized, 0.0);
+    }
+}
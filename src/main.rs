@@ -1,13 +1,17 @@
+mod backtest;
 mod client;
+mod config;
 mod market;
 mod notify;
 mod robot;
 mod strategy;
 mod crypto;
+mod ledger;
 mod util;
 mod router;
 
-use robot::{append_robot, get_robots, remove_robot, robot::MANAGER};
+use config::app_config::AppConfig;
+use robot::robot::{Robot, MANAGER};
 use salvo::prelude::*;
 use std::{thread, time::Duration};
 
@@ -22,9 +26,27 @@ fn start_job_scheduler() {
 
 #[tokio::main]
 async fn main() {
-    thread::spawn(|| {
+    let config = match AppConfig::try_get() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Refusing to start: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(err) = config.validate(&Robot::persisted_accounts()) {
+        eprintln!("Refusing to start: {}", err);
+        std::process::exit(1);
+    }
+    Robot::restore();
+
+    // Jobs spawn each robot run on the runtime, so a slow request only holds up its own robot.
+    let runtime = tokio::runtime::Handle::current();
+    thread::spawn(move || {
+        let _guard = runtime.enter();
         start_job_scheduler();
     });
+    tokio::spawn(ledger::ledger::start_sync());
+    tokio::spawn(client::server_clock::start_sync());
 
     let router = router::create_router(); 
     let acceptor = TcpListener::new("0.0.0.0:8080").bind().await;
@@ -1,6 +1,8 @@
 use lettre::transport::smtp::authentication::Credentials;
 use lettre::{Message, SmtpTransport, Transport};
 
+use crate::config::app_config::AppConfig;
+
 pub struct EmailClient {
     transport: SmtpTransport,
     origin: String,
@@ -8,17 +10,18 @@ pub struct EmailClient {
 
 impl EmailClient {
     pub fn new() -> EmailClient {
-        let creds = Credentials::new("771768137@qq.com".to_owned(), "pzpxdaqnuggobegg".to_owned());
+        let config = &AppConfig::get().email;
+        let creds = Credentials::new(config.username.clone(), config.password.clone());
 
-        let transport = SmtpTransport::relay("smtp.qq.com")
+        let transport = SmtpTransport::relay(config.smtp_host.as_str())
             .unwrap()
             .credentials(creds)
-            .port(465)
+            .port(config.smtp_port)
             .build();
 
         EmailClient {
             transport: transport,
-            origin: "771768137@qq.com".to_owned(),
+            origin: config.username.clone(),
         }
     }
 
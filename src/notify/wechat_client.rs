@@ -3,6 +3,8 @@ use std::time::Duration;
 use futures::TryFutureExt;
 use reqwest::Client;
 
+use crate::config::app_config::AppConfig;
+
 pub struct WechatClient {
     url: String,
 }
@@ -10,7 +12,7 @@ pub struct WechatClient {
 impl WechatClient {
     pub fn new() -> WechatClient {
         WechatClient {
-            url: "https://xizhi.qqoq.net/XZ121f7102a882c19c840f754d6b728a2b.send".to_owned(),
+            url: AppConfig::get().wechat.url.clone(),
         }
     }
 
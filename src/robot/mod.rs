@@ -1,65 +1,282 @@
-use std::{collections::HashMap, thread};
+use std::collections::HashMap;
+use std::str::FromStr;
 
-use salvo::{handler, Request, Response};
+use job_scheduler::Uuid;
+use salvo::{handler, http::StatusCode, prelude::Json, Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
-use crate::util::string_util::generate_random_id;
+use crate::config::app_config::AppConfig;
+use crate::crypto::trading_mode::TradingMode;
+use crate::util::time_util::now_timestamp;
+use crate::strategy::strategy::{account_of, StrategyFactory};
 
-use self::robot::Robot;
+use self::robot::{Robot, RobotError};
 
 pub mod robot;
 
+const DEFAULT_SCHEDULE: &str = "0 1/2 * * * *";
+const DEFAULT_RUNS_LIMIT: u32 = 20;
+const MAX_RUNS_LIMIT: u32 = 100;
+const DEFAULT_PNL_WINDOW: i64 = 24 * 3600 * 1000;
+const MAX_PNL_POINTS: u32 = 10_000;
 
 #[handler]
-pub fn get_robots(res: &mut Response) {
-    res.render(salvo::prelude::Json(Robot::list()));
+pub async fn get_robots(res: &mut Response) {
+    respond(res, StatusCode::OK, || Ok(Robot::list())).await;
 }
 
+/// Body of `POST /robot`.
+#[derive(Debug, Deserialize)]
+pub struct CreateRobot {
+    label: String,
+    #[serde(default = "default_name")]
+    name: String,
+    #[serde(default = "default_mode")]
+    mode: String,
+    #[serde(default = "default_schedule")]
+    schedule: String,
+    #[serde(default)]
+    params: HashMap<String, Value>,
+}
+
+/// Body of `PATCH /robot/<id>`; absent fields are left unchanged.
+#[derive(Debug, Deserialize)]
+pub struct UpdateRobot {
+    schedule: Option<String>,
+    params: Option<HashMap<String, Value>>,
+}
+
+fn default_name() -> String {
+    "Default Name".to_string()
+}
+
+fn default_mode() -> String {
+    "live".to_string()
+}
+
+fn default_schedule() -> String {
+    DEFAULT_SCHEDULE.to_string()
+}
+
+/// Strategies keep their params as strings; JSON numbers and booleans are accepted too.
+pub(crate) fn param_strings(params: HashMap<String, Value>) -> Result<HashMap<String, String>, String> {
+    params
+        .into_iter()
+        .map(|(key, value)| match value {
+            Value::String(value) => Ok((key, value)),
+            Value::Number(value) => Ok((key, value.to_string())),
+            Value::Bool(value) => Ok((key, value.to_string())),
+            other => Err(format!("invalid param {}: {}", key, other)),
+        })
+        .collect()
+}
+
+fn bad_request(res: &mut Response, message: String) {
+    res.status_code(StatusCode::BAD_REQUEST);
+    res.render(message);
+}
+
+fn unprocessable(res: &mut Response, message: String) {
+    res.status_code(StatusCode::UNPROCESSABLE_ENTITY);
+    res.render(message);
+}
+
+/// Runs a robot operation on the blocking pool, since it waits on the scheduler and database
+/// locks, computes PnL from the ledger, and blocks on the exchange to prepare the account or
+/// cancel the old strategy's orders. Renders the result with `status`, or the error with its
+/// matching status code.
+async fn respond<F, T>(res: &mut Response, status: StatusCode, operation: F)
+where
+    F: FnOnce() -> Result<T, RobotError> + Send + 'static,
+    T: Serialize + Send + 'static,
+{
+    match tokio::task::spawn_blocking(operation).await {
+        Ok(Ok(body)) => {
+            res.status_code(status);
+            res.render(Json(body));
+        }
+        Ok(Err(err)) => {
+            res.status_code(match err {
+                RobotError::NotFound(_) => StatusCode::NOT_FOUND,
+                RobotError::Conflict(_) => StatusCode::CONFLICT,
+                RobotError::Invalid(_) => StatusCode::UNPROCESSABLE_ENTITY,
+                RobotError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            });
+            res.render(err.to_string());
+        }
+        Err(err) => {
+            res.status_code(StatusCode::INTERNAL_SERVER_ERROR);
+            res.render(err.to_string());
+        }
+    }
+}
+
+fn query_id(req: &mut Request) -> String {
+    req.queries().get("id").cloned().unwrap_or_default()
+}
+
+/// Reads an optional param from the query string.
+fn query_param<T: FromStr>(req: &mut Request, key: &str, default: T) -> Result<T, String> {
+    match req.queries().get(key) {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("invalid {}: {}", key, value)),
+        None => Ok(default),
+    }
+}
+
+/// Creates and schedules a robot, answering 201 with the robot, including the id it is
+/// addressed by and its scheduler job.
 #[handler]
-pub async fn append_robot(req: &mut Request, res: &mut Response)  -> Result<(), salvo::Error> {
-    let params: HashMap<String, String> = match req.parse_form().await {
-        Ok(data) => data,
-        Err(_) => HashMap::new(),
+pub async fn append_robot(req: &mut Request, res: &mut Response) {
+    let body: CreateRobot = match req.parse_json().await {
+        Ok(body) => body,
+        Err(err) => return bad_request(res, format!("invalid body: {}", err)),
     };
-    let label = req.queries().get("label").cloned().unwrap_or_default();
-    let name = req.queries().get("name").cloned().unwrap_or("Default Name".to_string());
-    thread::spawn(|| {
-        Robot::create(
-            Robot::new(generate_random_id(), name, label, "0 1/2 * * * *".to_string(), params),
-        );
-    });
-    res.render("add success");
-    Ok(())
+    let params = match param_strings(body.params) {
+        Ok(params) => params,
+        Err(err) => return unprocessable(res, err),
+    };
+    if let Err(err) = Robot::validate_schedule(&body.schedule) {
+        return unprocessable(res, err.to_string());
+    }
+    let params = match StrategyFactory::new().validate(&body.label, &params) {
+        Ok(params) => params,
+        Err(err) => return unprocessable(res, err.to_string()),
+    };
+    let mode = match TradingMode::parse(&body.mode) {
+        Ok(mode) => mode,
+        Err(err) => return unprocessable(res, err.to_string()),
+    };
+    if mode != TradingMode::Paper {
+        if let Err(err) = AppConfig::get().validate(&[account_of(&params)]) {
+            return unprocessable(res, err.to_string());
+        }
+    }
+    let CreateRobot {
+        label,
+        name,
+        schedule,
+        ..
+    } = body;
+    respond(res, StatusCode::CREATED, move || {
+        let robot = Robot::new(Uuid::new_v4().to_string(), name, label, schedule, params, mode);
+        Robot::create(robot).map(|robot| robot.to_map())
+    })
+    .await;
+}
+
+#[handler]
+pub async fn get_robot(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    respond(res, StatusCode::OK, move || {
+        Robot::get(&id)
+            .map(|robot| robot.to_map())
+            .ok_or(RobotError::NotFound(id))
+    })
+    .await;
+}
+
+/// Changes a robot's schedule and/or strategy params (merged over the current ones). Nothing is
+/// applied unless every change is valid.
+#[handler]
+pub async fn update_robot(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    let body: UpdateRobot = match req.parse_json().await {
+        Ok(body) => body,
+        Err(err) => return bad_request(res, format!("invalid body: {}", err)),
+    };
+    let params = match body.params.map(param_strings).transpose() {
+        Ok(params) => params,
+        Err(err) => return unprocessable(res, err),
+    };
+    let schedule = body.schedule;
+    respond(res, StatusCode::OK, move || {
+        Robot::update(&id, schedule, params).map(|robot| robot.to_map())
+    })
+    .await;
 }
 
 #[handler]
 pub async fn remove_robot(req: &mut Request, res: &mut Response) {
-    let id = req.queries().get("id").cloned().unwrap_or_default();
-    thread::spawn(|| {
-        Robot::remove(id);
-    });
-    res.render("remove success");
+    let id = query_id(req);
+    respond(res, StatusCode::OK, move || {
+        Robot::remove(&id).map(|robot| robot.to_map())
+    })
+    .await;
 }
 
 #[handler]
 pub async fn suspend_robot(req: &mut Request, res: &mut Response) {
-    let id = req.queries().get("id").cloned().unwrap_or_default();
-    thread::spawn(|| {
-        Robot::suspend(id);
-    });
-    res.render("suspend success");
+    let id = query_id(req);
+    respond(res, StatusCode::OK, move || {
+        Robot::suspend(&id).map(|robot| robot.to_map())
+    })
+    .await;
 }
 
-
 #[handler]
 pub async fn resume_robot(req: &mut Request, res: &mut Response) {
-    let id = req.queries().get("id").cloned().unwrap_or_default();
-    thread::spawn(|| {
-        Robot::resume(id);
-    });
-    res.render("resume success");
+    let id = query_id(req);
+    respond(res, StatusCode::OK, move || {
+        Robot::resume(&id).map(|robot| robot.to_map())
+    })
+    .await;
 }
 
+/// Run history of a robot, newest first: `?limit=` (default 20, at most 100) and `?offset=`.
+#[handler]
+pub async fn get_robot_runs(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    let limit = match query_param(req, "limit", DEFAULT_RUNS_LIMIT) {
+        Ok(limit) => limit.min(MAX_RUNS_LIMIT),
+        Err(err) => return bad_request(res, err),
+    };
+    let offset = match query_param(req, "offset", 0) {
+        Ok(offset) => offset,
+        Err(err) => return bad_request(res, err),
+    };
+    respond(res, StatusCode::OK, move || {
+        let (runs, total) = Robot::runs(&id, limit, offset)?;
+        let mut page = Map::new();
+        page.insert("total".to_string(), Value::from(total));
+        page.insert("limit".to_string(), Value::from(limit));
+        page.insert("offset".to_string(), Value::from(offset));
+        page.insert(
+            "runs".to_string(),
+            serde_json::to_value(runs).map_err(|err| RobotError::Storage(err.to_string()))?,
+        );
+        Ok(page)
+    })
+    .await;
+}
 
-
-
-
+/// PnL snapshots of a robot, oldest first: `?from=`/`?to=` in milliseconds (default the last
+/// 24 hours) and `?limit=` (default and maximum 10000, keeping the newest).
+#[handler]
+pub async fn get_robot_pnl(req: &mut Request, res: &mut Response) {
+    let id = req.param::<String>("id").unwrap_or_default();
+    let now = now_timestamp();
+    let window = query_param(req, "from", now - DEFAULT_PNL_WINDOW).and_then(|from| {
+        let to = query_param(req, "to", now)?;
+        let limit = query_param(req, "limit", MAX_PNL_POINTS)?.min(MAX_PNL_POINTS);
+        Ok((from, to, limit))
+    });
+    let (from, to, limit) = match window {
+        Ok(window) => window,
+        Err(err) => return bad_request(res, err),
+    };
+    respond(res, StatusCode::OK, move || {
+        let series = Robot::pnl_series(&id, from, to, limit)?;
+        let mut page = Map::new();
+        page.insert("from".to_string(), Value::from(from));
+        page.insert("to".to_string(), Value::from(to));
+        page.insert(
+            "series".to_string(),
+            serde_json::to_value(series).map_err(|err| RobotError::Storage(err.to_string()))?,
+        );
+        Ok(page)
+    })
+    .await;
+}
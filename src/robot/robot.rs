@@ -1,11 +1,20 @@
+use crate::client::db_client::{DBClient, PnlRecord, RobotRecord, RunRecord};
+use crate::config::app_config::AppConfig;
+use crate::crypto::paper_book;
+use crate::crypto::trading_mode::TradingMode;
+use crate::ledger::pnl;
 use crate::strategy::{
     self,
-    strategy::{Strategy, StrategyFactory},
+    strategy::{account_of, order_tag, Strategy, StrategyFactory, Tick},
 };
-use job_scheduler::{Job, JobScheduler, Uuid};
+use anyhow::anyhow;
+use job_scheduler::{Job, JobScheduler, Schedule, Uuid};
 use once_cell::sync::Lazy;
 use serde_json::{Map, Value};
-use std::{borrow::Borrow, collections::HashMap, sync::Mutex};
+use crate::util::time_util::now_timestamp;
+use futures::FutureExt;
+use std::panic::AssertUnwindSafe;
+use std::{collections::HashMap, fmt, sync::Mutex};
 
 pub static mut MANAGER: Lazy<Mutex<JobScheduler>> = Lazy::new(|| Mutex::new(JobScheduler::new()));
 pub static mut ACTIVE_ROBOTS: Lazy<Mutex<HashMap<Uuid, Robot>>> =
@@ -24,6 +33,45 @@ impl Status {
             Status::Suspended => "suspended".to_string(),
         }
     }
+
+    pub fn parse(status: &str) -> Status {
+        match status {
+            "suspended" => Status::Suspended,
+            _ => Status::Running,
+        }
+    }
+}
+
+/// Why a robot operation was refused.
+#[derive(Debug)]
+pub enum RobotError {
+    /// No robot has this id, or the id is not a UUID.
+    NotFound(String),
+    /// The robot is not in a state the operation applies to, e.g. suspending a suspended robot.
+    Conflict(String),
+    /// The requested change does not pass validation.
+    Invalid(anyhow::Error),
+    /// The database failed.
+    Storage(String),
+}
+
+impl fmt::Display for RobotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RobotError::NotFound(id) => write!(f, "robot {} not found", id),
+            RobotError::Conflict(msg) => write!(f, "{}", msg),
+            RobotError::Invalid(err) => write!(f, "{}", err),
+            RobotError::Storage(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RobotError {}
+
+impl From<anyhow::Error> for RobotError {
+    fn from(err: anyhow::Error) -> Self {
+        RobotError::Invalid(err)
+    }
 }
 
 pub struct Robot {
@@ -32,6 +80,9 @@ pub struct Robot {
     strategy: Box<dyn Strategy>,
     status: Status,
     schedule: String,
+    mode: TradingMode,
+    /// Scheduler job running the robot, `None` while suspended.
+    job: Option<Uuid>,
 }
 
 impl Clone for Robot {
@@ -42,6 +93,8 @@ impl Clone for Robot {
             strategy: self.strategy.clone_box(),
             status: self.status.clone(),
             schedule: self.schedule.clone(),
+            mode: self.mode,
+            job: self.job,
         }
     }
 }
@@ -53,16 +106,44 @@ impl Robot {
         strategy: String,
         schedule: String,
         params: HashMap<String, String>,
+        mode: TradingMode,
     ) -> Robot {
+        let strategy = StrategyFactory::new().create_strategy(&id, &strategy, params, mode);
         Robot {
             id: id,
             name: name,
-            strategy: StrategyFactory::new().create_strategy(&strategy, params),
+            strategy,
             schedule: schedule,
             status: Status::Running,
+            mode,
+            job: None,
         }
     }
 
+    /// Checks a cron expression the way the scheduler will parse it.
+    pub fn validate_schedule(schedule: &str) -> Result<(), anyhow::Error> {
+        schedule
+            .parse::<Schedule>()
+            .map(|_| ())
+            .map_err(|err| anyhow!("invalid schedule {}: {}", schedule, err))
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn mode(&self) -> TradingMode {
+        self.mode
+    }
+
+    pub fn strategy_name(&self) -> String {
+        self.strategy.name()
+    }
+
+    pub fn params(&self) -> HashMap<String, String> {
+        self.strategy.params()
+    }
+
     pub fn to_map(&self) -> Map<String, Value> {
         let mut map = Map::new();
         map.insert("id".to_string(), Value::String(self.id.clone()));
@@ -72,12 +153,180 @@ impl Robot {
             Value::Object(self.strategy.to_json()),
         );
         map.insert("status".to_string(), Value::String(self.status.to_string()));
+        map.insert("schedule".to_string(), Value::String(self.schedule.clone()));
+        map.insert("mode".to_string(), Value::String(self.mode.to_string()));
+        map.insert("pnl".to_string(), self.pnl());
+        map.insert(
+            "job".to_string(),
+            self.job
+                .map(|job| Value::String(job.to_string()))
+                .unwrap_or(Value::Null),
+        );
         return map;
     }
 
-    pub fn create(robot: Robot) {
-        let uuid = Robot::add_task(robot.clone());
-        Robot::append(robot, uuid);
+    /// PnL of the robot's fills in the ledger at the last synced prices, `null` in paper mode
+    /// since paper fills are not synced.
+    fn pnl(&self) -> Value {
+        if self.mode == TradingMode::Paper {
+            return Value::Null;
+        }
+        let db = DBClient::get_instance().lock().unwrap();
+        match pnl::robot_pnl(&db, &self.id) {
+            Ok(pnl) => serde_json::to_value(pnl).unwrap_or(Value::Null),
+            Err(err) => {
+                println!("Failed to compute PnL of robot {}: {}", self.id, err);
+                Value::Null
+            }
+        }
+    }
+
+    pub fn to_record(&self) -> RobotRecord {
+        RobotRecord {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            strategy: self.strategy.name(),
+            params: self.strategy.params(),
+            schedule: self.schedule.clone(),
+            status: self.status.to_string(),
+            mode: self.mode.to_string(),
+        }
+    }
+
+    /// Rebuilds a stored robot. A mode or strategy this version does not know is an error rather
+    /// than a guess, so a live robot is never resumed as something else.
+    pub fn from_record(record: RobotRecord) -> Result<Robot, anyhow::Error> {
+        let mode = TradingMode::parse(&record.mode)?;
+        if !StrategyFactory::new().labels().contains(&record.strategy.as_str()) {
+            return Err(anyhow!("unknown strategy {}", record.strategy));
+        }
+        let mut robot = Robot::new(
+            record.id,
+            record.name,
+            record.strategy,
+            record.schedule,
+            record.params,
+            mode,
+        );
+        robot.status = Status::parse(&record.status);
+        Ok(robot)
+    }
+
+    /// Sets the account up for the robot, schedules, stores and registers it, returning it with
+    /// its job id. Runs on a blocking thread of the runtime.
+    pub fn create(mut robot: Robot) -> Result<Robot, RobotError> {
+        robot.check_position_mode()?;
+        robot.prepare()?;
+        robot.job = Some(Robot::add_task(robot.clone()));
+        Robot::persist(&robot);
+        Robot::append(robot.clone());
+        Ok(robot)
+    }
+
+    /// Refuses a futures robot whose position mode differs from that of another robot on the
+    /// same account, as the mode applies to the whole account.
+    fn check_position_mode(&self) -> Result<(), RobotError> {
+        let factory = StrategyFactory::new();
+        let params = self.strategy.params();
+        let Some(position_mode) = factory.position_mode(&self.strategy.name(), &params) else {
+            return Ok(());
+        };
+        if self.mode == TradingMode::Paper {
+            return Ok(());
+        }
+        let account = account_of(&params);
+        for other in Robot::all() {
+            if other.id == self.id || other.mode != self.mode || account_of(&other.params()) != account {
+                continue;
+            }
+            match factory.position_mode(&other.strategy_name(), &other.params()) {
+                Some(other_mode) if other_mode != position_mode => {
+                    return Err(RobotError::Conflict(format!(
+                        "robot {} trades account {} in {} position mode",
+                        other.id, account, other_mode
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the strategy's account settings, see `Strategy::prepare`.
+    fn prepare(&self) -> Result<(), RobotError> {
+        tokio::runtime::Handle::current()
+            .block_on(self.strategy.prepare())
+            .map_err(|err| RobotError::Conflict(format!("robot {} could not set up its account: {}", self.id, err)))
+    }
+
+    pub fn restore() {
+        let records = match DBClient::get_instance().lock().unwrap().list_robots() {
+            Ok(records) => records,
+            Err(err) => {
+                println!("Failed to load robots: {}", err);
+                return;
+            }
+        };
+        for record in records {
+            let id = record.id.clone();
+            let mut robot = match Robot::from_record(record) {
+                Ok(robot) => robot,
+                Err(err) => {
+                    println!("Skip robot {}: {}", id, err);
+                    continue;
+                }
+            };
+            if Uuid::parse_str(robot.id.as_str()).is_err() {
+                println!("Skip robot {} with invalid id", robot.id);
+                continue;
+            }
+            if robot.status == Status::Running {
+                robot.job = Some(Robot::add_task(robot.clone()));
+                let (id, strategy) = (robot.id.clone(), robot.strategy.clone_box());
+                tokio::spawn(async move {
+                    if let Err(err) = strategy.prepare().await {
+                        println!("Robot {} could not set up its account: {}", id, err);
+                    }
+                });
+            }
+            Robot::append(robot);
+        }
+    }
+
+    /// Account profiles referenced by the robots stored in the database.
+    pub fn persisted_accounts() -> Vec<String> {
+        match DBClient::get_instance().lock().unwrap().list_robots() {
+            Ok(records) => records
+                .iter()
+                .filter(|record| record.mode != TradingMode::Paper.to_string())
+                .map(|record| account_of(&record.params))
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn persist(robot: &Robot) {
+        if let Err(err) = DBClient::get_instance()
+            .lock()
+            .unwrap()
+            .save_robot(&robot.to_record())
+        {
+            println!("Failed to persist robot {}: {}", robot.id, err);
+        }
+    }
+
+    fn forget(id: &str) {
+        let db = DBClient::get_instance().lock().unwrap();
+        let deleted = db
+            .delete_robot(id)
+            .and_then(|_| db.delete_runs(id))
+            .and_then(|_| db.delete_pnl(id))
+            .and_then(|_| db.delete_grid_ladder(&order_tag(id)));
+        if let Err(err) = deleted {
+            println!("Failed to delete robot {}: {}", id, err);
+        }
+        drop(db);
+        paper_book::delete_robot_book(id);
     }
 
     pub fn add_task(robot: Robot) -> Uuid {
@@ -87,90 +336,205 @@ impl Robot {
                     .lock()
                     .unwrap()
                     .add(Job::new(robot.schedule.parse().unwrap(), move || {
-                        robot.excute();
+                        let robot = robot.clone();
+                        tokio::spawn(async move { robot.excute().await });
                     }));
             return uuid;
         }
     }
 
-    pub fn append(robot: Robot, uuid: Uuid) -> String {
-        unsafe {
-            ACTIVE_ROBOTS.lock().unwrap().insert(
-                uuid,
-                Robot::new(
-                    uuid.to_string(),
-                    robot.name,
-                    robot.strategy.name(),
-                    robot.schedule,
-                    robot.strategy.params(),
-                ),
-            );
-            return uuid.to_string();
+    fn remove_task(job: Option<Uuid>) {
+        if let Some(job) = job {
+            unsafe {
+                MANAGER.lock().unwrap().remove(job);
+            }
         }
     }
 
-    pub fn remove(uuid: String) -> bool {
+    pub fn append(robot: Robot) -> String {
+        let id = robot.id.clone();
         unsafe {
-            let is_removed = MANAGER
+            ACTIVE_ROBOTS
                 .lock()
                 .unwrap()
-                .remove(Uuid::parse_str(uuid.as_str()).unwrap());
-            if is_removed {
-                ACTIVE_ROBOTS
-                    .lock()
-                    .unwrap()
-                    .remove(&Uuid::parse_str(uuid.as_str()).unwrap());
-                return true;
-            }
-            return false;
+                .insert(Uuid::parse_str(id.as_str()).unwrap(), robot);
         }
+        id
     }
 
-    pub fn suspend(uuid: String) -> bool {
-        unsafe {
-            let is_suspended = MANAGER
-                .lock()
-                .unwrap()
-                .remove(Uuid::parse_str(uuid.as_str()).unwrap());
-            if is_suspended {
-                let mut robots = ACTIVE_ROBOTS.lock().unwrap();
-                let robot = robots
-                    .get_mut(&Uuid::parse_str(uuid.as_str()).unwrap())
-                    .unwrap();
-                robot.status = Status::Suspended;
-                return true;
-            }
-            return false;
+    pub fn get(id: &str) -> Option<Robot> {
+        let uuid = Uuid::parse_str(id).ok()?;
+        unsafe { ACTIVE_ROBOTS.lock().unwrap().get(&uuid).cloned() }
+    }
+
+    fn take(id: &str) -> Option<Robot> {
+        let uuid = Uuid::parse_str(id).ok()?;
+        unsafe { ACTIVE_ROBOTS.lock().unwrap().remove(&uuid) }
+    }
+
+    pub fn remove(id: &str) -> Result<Robot, RobotError> {
+        let robot = Robot::take(id).ok_or_else(|| RobotError::NotFound(id.to_string()))?;
+        Robot::remove_task(robot.job);
+        Robot::forget(id);
+        Ok(robot)
+    }
+
+    pub fn suspend(id: &str) -> Result<Robot, RobotError> {
+        let mut robot = Robot::get(id).ok_or_else(|| RobotError::NotFound(id.to_string()))?;
+        if robot.status == Status::Suspended {
+            return Err(RobotError::Conflict(format!("robot {} is already suspended", id)));
         }
+        Robot::take(id);
+        Robot::remove_task(robot.job.take());
+        robot.status = Status::Suspended;
+        if let Err(err) = DBClient::get_instance()
+            .lock()
+            .unwrap()
+            .update_robot_status(id, &robot.status.to_string())
+        {
+            println!("Failed to persist robot {}: {}", id, err);
+        }
+        Robot::append(robot.clone());
+        Ok(robot)
     }
 
-    pub fn resume(uuid: String) -> bool {
-        unsafe {
-            let removed = ACTIVE_ROBOTS
-                .lock()
-                .unwrap()
-                .remove(&Uuid::parse_str(uuid.as_str()).unwrap());
-            if removed.is_some() {
-                let robot = removed.unwrap();
-                Robot::create(robot);
-                return true;
+    pub fn resume(id: &str) -> Result<Robot, RobotError> {
+        let mut robot = Robot::get(id).ok_or_else(|| RobotError::NotFound(id.to_string()))?;
+        if robot.status == Status::Running {
+            return Err(RobotError::Conflict(format!("robot {} is already running", id)));
+        }
+        Robot::take(id);
+        robot.status = Status::Running;
+        robot.job = Some(Robot::add_task(robot.clone()));
+        Robot::persist(&robot);
+        Robot::append(robot.clone());
+        Ok(robot)
+    }
+
+    /// Applies a new schedule and/or params (merged over the current ones) to a robot,
+    /// re-registering its job when it is running. Changing params builds a fresh strategy, once
+    /// the open orders of the old one are cancelled; the robot keeps the old strategy if any of
+    /// them cannot be. Runs on a blocking thread of the runtime.
+    pub fn update(
+        id: &str,
+        schedule: Option<String>,
+        params: Option<HashMap<String, String>>,
+    ) -> Result<Robot, RobotError> {
+        let current = Robot::get(id).ok_or_else(|| RobotError::NotFound(id.to_string()))?;
+        if let Some(schedule) = &schedule {
+            Robot::validate_schedule(schedule)?;
+        }
+        let mut updated = current.clone();
+        let replaced = params.is_some();
+        if let Some(changes) = params {
+            let mut merged = current.strategy.params();
+            merged.extend(changes);
+            let factory = StrategyFactory::new();
+            let merged = factory.validate(&current.strategy.name(), &merged)?;
+            if current.mode != TradingMode::Paper {
+                AppConfig::get().validate(&[account_of(&merged)])?;
+            }
+            updated.strategy = factory.create_strategy(id, &current.strategy.name(), merged, current.mode);
+        }
+        if let Some(schedule) = schedule {
+            updated.schedule = schedule;
+        }
+        if replaced {
+            updated.check_position_mode()?;
+            updated.prepare()?;
+        }
+
+        Robot::take(id);
+        Robot::remove_task(current.job);
+        if replaced {
+            // The new strategy lays out its own orders; the old one's would be left unmanaged.
+            let tick = tokio::runtime::Handle::current().block_on(current.strategy.cancel_orders());
+            if !tick.errors.is_empty() {
+                let mut current = current;
+                current.job = match current.status {
+                    Status::Running => Some(Robot::add_task(current.clone())),
+                    Status::Suspended => None,
+                };
+                Robot::append(current);
+                return Err(RobotError::Conflict(format!(
+                    "robot {} kept its strategy, its orders could not all be cancelled: {}",
+                    id,
+                    tick.errors.join("; ")
+                )));
             }
-            return false;
         }
+        updated.job = match updated.status {
+            Status::Running => Some(Robot::add_task(updated.clone())),
+            Status::Suspended => None,
+        };
+        Robot::persist(&updated);
+        Robot::append(updated.clone());
+        Ok(updated)
     }
 
+    pub fn all() -> Vec<Robot> {
+        unsafe { ACTIVE_ROBOTS.lock().unwrap().values().cloned().collect() }
+    }
+
+    /// Every robot with its PnL, computed once the robots are out of the lock.
     pub fn list() -> Vec<Map<String, Value>> {
-        unsafe {
-            return ACTIVE_ROBOTS
-                .lock()
-                .unwrap()
-                .values()
-                .map(|robot| robot.to_map())
-                .collect();
+        Robot::all().iter().map(|robot| robot.to_map()).collect()
+    }
+
+    /// Runs the strategy once and records the run, including a panic, in the run history.
+    pub async fn excute(&self) {
+        let started_at = now_timestamp();
+        let (outcome, tick) = match AssertUnwindSafe(self.strategy.excute()).catch_unwind().await {
+            Ok(tick) if tick.errors.is_empty() => ("ok", tick),
+            Ok(tick) => ("error", tick),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_else(|| "unknown panic".to_string());
+                ("panic", Tick::skipped(format!("Robot {} panicked: {}", self.id, message)))
+            }
+        };
+        let run = RunRecord {
+            id: 0,
+            robot_id: self.id.clone(),
+            started_at,
+            finished_at: now_timestamp(),
+            outcome: outcome.to_string(),
+            orders: tick.orders,
+            errors: tick.errors,
+        };
+        if let Err(err) = DBClient::get_instance().lock().unwrap().save_run(&run) {
+            println!("Failed to record run of robot {}: {}", self.id, err);
+        }
+        if self.mode == TradingMode::Paper {
+            paper_book::save_robot_book(&self.id);
+        }
+    }
+
+    /// PnL snapshots of the robot taken by the ledger sync between `from` and `to`.
+    pub fn pnl_series(id: &str, from: i64, to: i64, limit: u32) -> Result<Vec<PnlRecord>, RobotError> {
+        if Robot::get(id).is_none() {
+            return Err(RobotError::NotFound(id.to_string()));
         }
+        DBClient::get_instance()
+            .lock()
+            .unwrap()
+            .list_pnl(id, from, to, limit)
+            .map_err(|err| RobotError::Storage(format!("failed to load PnL: {}", err)))
     }
 
-    pub fn excute(&self) {
-        self.strategy.excute();
+    /// A page of the robot's runs, newest first, and the total number of runs.
+    pub fn runs(id: &str, limit: u32, offset: u32) -> Result<(Vec<RunRecord>, u32), RobotError> {
+        if Robot::get(id).is_none() {
+            return Err(RobotError::NotFound(id.to_string()));
+        }
+        let db = DBClient::get_instance().lock().unwrap();
+        let runs = db
+            .list_runs(id, limit, offset)
+            .and_then(|runs| Ok((runs, db.count_runs(id)?)))
+            .map_err(|err| RobotError::Storage(format!("failed to load runs: {}", err)))?;
+        Ok(runs)
     }
 }
@@ -0,0 +1,6 @@
+use salvo::prelude::*;
+use crate::client::rate_limiter::get_rate_limits;
+
+pub fn create_router() -> Router {
+    Router::with_path("admin/rate-limits").get(get_rate_limits)
+}
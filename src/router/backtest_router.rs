@@ -0,0 +1,6 @@
+use salvo::prelude::*;
+use crate::backtest::run_backtest;
+
+pub fn create_router() -> Router {
+    Router::with_path("backtest").post(run_backtest)
+}
@@ -1,9 +1,22 @@
+pub mod admin_router;
+pub mod backtest_router;
+pub mod order_router;
 pub mod robot_router;
+pub mod strategy_router;
 
 use salvo::prelude::*;
 
 pub fn create_router() -> Router {
     let robot_router = robot_router::create_router();
+    let strategy_router = strategy_router::create_router();
+    let order_router = order_router::create_router();
+    let admin_router = admin_router::create_router();
+    let backtest_router = backtest_router::create_router();
 
-    Router::new().push(robot_router)
+    Router::new()
+        .push(robot_router)
+        .push(strategy_router)
+        .push(order_router)
+        .push(admin_router)
+        .push(backtest_router)
 }
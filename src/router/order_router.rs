@@ -0,0 +1,6 @@
+use salvo::prelude::*;
+use crate::ledger::get_orders;
+
+pub fn create_router() -> Router {
+    Router::with_path("orders").get(get_orders)
+}
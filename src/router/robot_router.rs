@@ -1,6 +1,9 @@
 
 use salvo::prelude::*;
-use crate::robot::{append_robot, get_robots, remove_robot, resume_robot, suspend_robot};
+use crate::robot::{
+    append_robot, get_robot, get_robot_pnl, get_robot_runs, get_robots, remove_robot,
+    resume_robot, suspend_robot, update_robot,
+};
 
 pub fn create_router() -> Router {
     Router::new().push(
@@ -10,6 +13,13 @@ pub fn create_router() -> Router {
             .delete(remove_robot)
             .push(Router::with_path("suspend").get(suspend_robot))
             .push(Router::with_path("resume").get(resume_robot))
+            .push(
+                Router::with_path("<id>")
+                    .get(get_robot)
+                    .patch(update_robot)
+                    .push(Router::with_path("runs").get(get_robot_runs))
+                    .push(Router::with_path("pnl").get(get_robot_pnl)),
+            )
     )
 
 }
\ No newline at end of file
@@ -0,0 +1,6 @@
+use salvo::prelude::*;
+use crate::strategy::get_strategies;
+
+pub fn create_router() -> Router {
+    Router::with_path("strategies").get(get_strategies)
+}
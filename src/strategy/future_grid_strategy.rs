@@ -1,37 +1,322 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
+use async_trait::async_trait;
 use serde_json::{Map, Value};
 
-use crate::crypto::futures_client::FutureClient;
-use crate::strategy::strategy::Strategy;
-use crate::util::number_util::{get_precision, round};
+use crate::crypto::exchange::{Exchange, ExchangeOrder, ExchangePosition, OrderRequest};
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::strategy::param_schema::{ParamSchema, ParamSpec};
+use crate::strategy::strategy::{cancel_tagged_orders, next_client_order_id, require, Strategy, Tick};
 use crate::util::string_util::generate_random_id;
 
+/// Quantities below this are treated as zero.
+const EPSILON: f64 = 1e-12;
+
+struct FutureGridParams {
+    symbol: String,
+    gap: f64,
+    quantity: f64,
+    leverage: u8,
+    max_position: f64,
+    liquidation_gap: f64,
+    hedge: bool,
+    time_in_force: String,
+    stop_loss_pct: Option<f64>,
+    take_profit_pct: Option<f64>,
+}
+
+/// Reads an optional param, falling back to `default` for robots stored before it existed.
+fn or_default<T: std::str::FromStr>(
+    params: &HashMap<String, String>,
+    key: &str,
+    default: T,
+) -> Result<T, anyhow::Error> {
+    match params.get(key) {
+        Some(_) => require(params, key),
+        None => Ok(default),
+    }
+}
+
+fn optional<T: std::str::FromStr>(params: &HashMap<String, String>, key: &str) -> Result<Option<T>, anyhow::Error> {
+    match params.get(key) {
+        Some(_) => Ok(Some(require(params, key)?)),
+        None => Ok(None),
+    }
+}
+
+impl FutureGridParams {
+    fn parse(params: &HashMap<String, String>) -> Result<FutureGridParams, anyhow::Error> {
+        let quantity: f64 = require(params, "quantity")?;
+        let position_mode: String = or_default(params, "position_mode", "one_way".to_string())?;
+        Ok(FutureGridParams {
+            symbol: require(params, "symbol")?,
+            gap: require(params, "gap")?,
+            quantity,
+            leverage: require(params, "leverage")?,
+            max_position: or_default(params, "max_position", quantity * 5.0)?,
+            liquidation_gap: or_default(params, "liquidation_gap", 0.1)?,
+            hedge: position_mode == "hedge",
+            time_in_force: or_default(params, "time_in_force", "GTC".to_string())?,
+            stop_loss_pct: optional(params, "stop_loss_pct")?,
+            take_profit_pct: optional(params, "take_profit_pct")?,
+        })
+    }
+}
+
+fn signed(side: &str, quantity: f64) -> f64 {
+    match side {
+        "SELL" => -quantity,
+        _ => quantity,
+    }
+}
+
+/// Whether an order on `side` grows the position rather than closing part of it.
+fn opens(position: f64, side: &str) -> bool {
+    signed(side, 1.0) * position >= 0.0
+}
+
+/// An order the grid wants this tick. In hedge mode it names the position it trades.
+struct Leg {
+    side: &'static str,
+    position_side: Option<&'static str>,
+    price: f64,
+    closes: bool,
+}
+
+/// A buy one gap below the price and a sell one gap above. In one-way mode the one against the
+/// net position closes part of it; in hedge mode each position gets an opening and a closing
+/// order.
+fn legs(hedge: bool, position: f64, price: f64, gap: f64) -> Vec<Leg> {
+    let below = price * (1.0 - gap);
+    let above = price * (1.0 + gap);
+    if !hedge {
+        return vec![
+            Leg { side: "BUY", position_side: None, price: below, closes: !opens(position, "BUY") },
+            Leg { side: "SELL", position_side: None, price: above, closes: !opens(position, "SELL") },
+        ];
+    }
+    vec![
+        Leg { side: "BUY", position_side: Some("LONG"), price: below, closes: false },
+        Leg { side: "SELL", position_side: Some("LONG"), price: above, closes: true },
+        Leg { side: "SELL", position_side: Some("SHORT"), price: above, closes: false },
+        Leg { side: "BUY", position_side: Some("SHORT"), price: below, closes: true },
+    ]
+}
+
+/// Net quantity of the positions on `position_side`, or of all of them in one-way mode.
+fn position_of(positions: &[ExchangePosition], position_side: Option<&str>) -> f64 {
+    positions
+        .iter()
+        .filter(|position| position_side.is_none_or(|side| position.position_side == side))
+        .map(|position| position.quantity)
+        .sum()
+}
+
+/// Signed quantity the open orders of a leg still have to fill.
+fn pending(open_orders: &[ExchangeOrder], leg: &Leg) -> f64 {
+    open_orders
+        .iter()
+        .filter(|order| order.side == leg.side)
+        .filter(|order| leg.position_side.is_none_or(|side| order.position_side == side))
+        .map(|order| signed(leg.side, order.orig_qty - order.executed_qty))
+        .sum()
+}
+
+fn is_protective(order: &ExchangeOrder) -> bool {
+    order.order_type == "STOP_MARKET" || order.order_type == "TAKE_PROFIT_MARKET"
+}
+
+/// Stop-loss and take-profit trigger prices of a position, as order type and stop price, the
+/// given percentages away from its entry price.
+fn protective_stops(
+    position: &ExchangePosition,
+    stop_loss_pct: Option<f64>,
+    take_profit_pct: Option<f64>,
+) -> Vec<(&'static str, f64)> {
+    let direction = position.quantity.signum();
+    let mut stops = Vec::new();
+    if let Some(pct) = stop_loss_pct {
+        stops.push(("STOP_MARKET", position.entry_price * (1.0 - direction * pct / 100.0)));
+    }
+    if let Some(pct) = take_profit_pct {
+        stops.push(("TAKE_PROFIT_MARKET", position.entry_price * (1.0 + direction * pct / 100.0)));
+    }
+    stops
+}
+
+/// Whether a resting protective order is the one `request` asks for.
+fn same_protection(order: &ExchangeOrder, request: &OrderRequest) -> bool {
+    let close = |a: f64, b: f64| (a - b).abs() <= b.abs() * 1e-9;
+    order.order_type == request.order_type
+        && order.side == request.side
+        && order.position_side == request.position_side.clone().unwrap_or("BOTH".to_string())
+        && close(order.stop_price, request.stop_price.unwrap_or(0.0))
+        && close(order.orig_qty - order.executed_qty, request.quantity)
+}
+
+/// Whether `price` is within `gap`, as a fraction of it, of a position's liquidation price.
+fn near_liquidation(positions: &[ExchangePosition], price: f64, gap: f64) -> bool {
+    positions
+        .iter()
+        .filter_map(|position| position.liquidation_price)
+        .any(|liquidation_price| (price - liquidation_price).abs() / price < gap)
+}
 
 #[derive(Clone)]
 pub struct FutureGridStrategy {
     id: String,
     name: String,
-    crypto_client: FutureClient,
+    exchange: Arc<dyn Exchange>,
     params: HashMap<String, String>,
 }
 
 impl FutureGridStrategy {
-    pub fn new(params: HashMap<String, String>) -> Self {
+    pub fn new(params: HashMap<String, String>, exchange: Arc<dyn Exchange>) -> Self {
         Self {
             id: generate_random_id(),
             name: String::from("FutureGrid"),
-            crypto_client: FutureClient::new(),
+            exchange,
             params,
         }
     }
 }
 
+impl FutureGridStrategy {
+    fn order_prefix(&self) -> &str {
+        self.id.get(..16).unwrap_or(&self.id)
+    }
+
+    /// One stop-loss and one take-profit order for each position, sized to it and priced from
+    /// its entry.
+    fn protective_requests(
+        &self,
+        tick: &mut Tick,
+        rules: &SymbolRules,
+        positions: &[ExchangePosition],
+        hedge: bool,
+        stop_loss_pct: Option<f64>,
+        take_profit_pct: Option<f64>,
+    ) -> Vec<OrderRequest> {
+        let mut wanted: Vec<OrderRequest> = Vec::new();
+        for position in positions.iter().filter(|position| position.quantity.abs() > EPSILON) {
+            let side = if position.quantity > 0.0 { "SELL" } else { "BUY" };
+            for (order_type, stop_price) in protective_stops(position, stop_loss_pct, take_profit_pct) {
+                let request = OrderRequest::triggered(
+                    &position.symbol,
+                    side,
+                    order_type,
+                    stop_price,
+                    position.quantity.abs(),
+                    "",
+                );
+                let request = match hedge {
+                    true => request.position_side(&position.position_side),
+                    false => request.reduce_only(),
+                };
+                match rules.normalize(&request) {
+                    Ok(request) => wanted.push(request),
+                    Err(e) => tick.error(format!("Skip {} order: {}", order_type, e)),
+                }
+            }
+        }
+        wanted
+    }
+
+    /// Leaves the resting protective orders that are still `wanted`, cancels the others and
+    /// places the missing ones.
+    async fn sync_protection(&self, tick: &mut Tick, resting: &[ExchangeOrder], mut wanted: Vec<OrderRequest>) {
+        for order in resting {
+            match wanted.iter().position(|request| same_protection(order, request)) {
+                Some(index) => {
+                    wanted.remove(index);
+                }
+                None => {
+                    if let Err(e) = self.exchange.cancel_order(&order.symbol, order.order_id).await {
+                        tick.error(format!("Failed to cancel {} order {}: {}", order.order_type, order.order_id, e));
+                    }
+                }
+            }
+        }
+        for request in wanted {
+            let request = match next_client_order_id(self.order_prefix()) {
+                Ok(client_order_id) => request.client_order_id(&client_order_id),
+                Err(e) => {
+                    tick.error(format!("Skip {} order, no client order id: {}", request.order_type, e));
+                    continue;
+                }
+            };
+            match self.exchange.place_order(&request).await {
+                Ok(order) => tick.orders.push(order),
+                Err(e) => tick.error(format!("Failed to place {} order: {}", request.order_type, e)),
+            }
+        }
+    }
+
+    pub fn schema() -> ParamSchema {
+        ParamSchema::new(vec![
+            ParamSpec::string("symbol", "USDⓈ-M futures contract, e.g. BTCUSDT"),
+            ParamSpec::float("gap", "Distance between the grid orders and the price, as a fraction")
+                .range(Some(0.0001), Some(0.5)),
+            ParamSpec::float("quantity", "Contract quantity of each order").range(Some(0.00000001), None),
+            ParamSpec::integer("leverage", "Leverage set on the symbol when the robot is created or updated")
+                .range(Some(1.0), Some(125.0))
+                .default("1"),
+            ParamSpec::float("max_position", "Largest net position, long or short, counting open orders; defaults to 5 orders")
+                .range(Some(0.00000001), None)
+                .optional(),
+            ParamSpec::float("liquidation_gap", "No order grows the position while the price is this fraction from liquidation")
+                .range(Some(0.0), Some(1.0))
+                .default("0.1"),
+            ParamSpec::one_of("position_mode", &["one_way", "hedge"], "hedge keeps a long and a short position side by side; the account is switched to it, so all its futures robots must agree")
+                .default("one_way"),
+            ParamSpec::one_of("time_in_force", &["GTC", "GTX"], "GTX makes the orders post-only")
+                .default("GTC"),
+            ParamSpec::float("stop_loss_pct", "Stop-market order on the position this many percent past its entry price against it")
+                .range(Some(0.01), Some(100.0))
+                .optional(),
+            ParamSpec::float("take_profit_pct", "Take-profit-market order on the position this many percent past its entry price in its favour")
+                .range(Some(0.01), Some(1000.0))
+                .optional(),
+        ])
+    }
+
+    pub fn validate(params: &HashMap<String, String>) -> Result<HashMap<String, String>, anyhow::Error> {
+        FutureGridStrategy::schema().validate(params)
+    }
+}
+
+#[async_trait]
 impl Strategy for FutureGridStrategy {
     fn clone_box(&self) -> Box<dyn Strategy> {
         Box::new((*self).clone())
     }
 
+    fn set_order_tag(&mut self, tag: &str) {
+        self.id = tag.to_string();
+    }
+
+    async fn prepare(&self) -> Result<(), anyhow::Error> {
+        let FutureGridParams {
+            symbol, leverage, hedge, ..
+        } = FutureGridParams::parse(&self.params)?;
+        self.exchange
+            .change_leverage(&symbol, leverage)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to set leverage: {}", e))?;
+        self.exchange
+            .set_hedge_mode(hedge)
+            .await
+            .map_err(|e| anyhow::anyhow!("failed to set position mode: {}", e))
+    }
+
+    async fn cancel_orders(&self) -> Tick {
+        match require::<String>(&self.params, "symbol") {
+            Ok(symbol) => cancel_tagged_orders(self.exchange.as_ref(), &symbol, self.order_prefix()).await,
+            Err(err) => Tick::skipped(format!("Failed to cancel orders: {}", err)),
+        }
+    }
+
     fn to_json(&self) -> Map<String, Value> {
         let mut map = Map::new();
         map.insert("name".to_string(), Value::String(self.name.clone()));
@@ -46,74 +331,103 @@ impl Strategy for FutureGridStrategy {
 
     
 
-    fn excute(&self) {
-        let gap: f64 = self
-            .params
-            .get("gap")
-            .unwrap()
-            .parse()
-            .expect("Not a valid f64");
-        let quantity: f64 = self
-            .params
-            .get("quantity")
-            .unwrap()
-            .parse()
-            .expect("Not a valid f64");
-        let symbol = self.params.get("symbol").unwrap();
-        let leverage: u8 = self
-            .params
-            .get("leverage")
-            .unwrap()
-            .parse()
-            .expect("Not a valid i32");
+    async fn excute(&self) -> Tick {
+        let FutureGridParams {
+            symbol,
+            gap,
+            quantity,
+            max_position,
+            liquidation_gap,
+            hedge,
+            time_in_force,
+            stop_loss_pct,
+            take_profit_pct,
+            ..
+        } = match FutureGridParams::parse(&self.params) {
+            Ok(params) => params,
+            Err(e) => return Tick::skipped(format!("Skip future grid tick: {}", e)),
+        };
+        let symbol = &symbol;
 
-        // Set leverage first
-        if let Err(e) = self.crypto_client.change_leverage(symbol, leverage) {
-            println!("Failed to set leverage: {}", e);
-            return;
-        }
+        let current_price = match self.exchange.current_price(symbol).await {
+            Ok(price) => price,
+            Err(e) => return Tick::skipped(format!("Failed to get current price: {}", e)),
+        };
 
-        let current_price = match self.crypto_client.current_price(symbol) {
-            Ok(price) => price.price,
-            Err(e) => {
-                println!("Failed to get current price: {}", e);
-                return;
-            }
+        let rules = match self.exchange.symbol_rules(symbol).await {
+            Ok(rules) => rules,
+            Err(e) => return Tick::skipped(format!("Failed to get symbol rules: {}", e)),
         };
 
-        // Place long position order
-        let buy_price = round(
-            (current_price * (1.0 - gap) * 100000000.0).round() / 100000000.0,
-            get_precision(current_price),
-        );
-        
-        if let Err(e) = self.crypto_client.place_order(
-            symbol.to_string(),
-            "BUY".to_string(),
-            "LIMIT".to_string(),
-            buy_price,
-            quantity,
-            None,
-        ) {
-            println!("Failed to place buy order: {}", e);
-        }
+        let positions = match self.exchange.positions(symbol).await {
+            Ok(positions) => positions,
+            Err(e) => return Tick::skipped(format!("Failed to get positions: {}", e)),
+        };
+        let (protective, open_orders): (Vec<ExchangeOrder>, Vec<ExchangeOrder>) = match self.exchange.open_orders(symbol).await {
+            Ok(orders) => orders
+                .into_iter()
+                .filter(|order| order.client_order_id.starts_with(self.order_prefix()))
+                .partition(is_protective),
+            Err(e) => return Tick::skipped(format!("Failed to get open orders: {}", e)),
+        };
+        let liquidation_near = near_liquidation(&positions, current_price, liquidation_gap);
 
-        // Place short position order
-        let sell_price = round(
-            (current_price * (1.0 + gap) * 100000000.0).round() / 100000000.0,
-            get_precision(current_price),
-        );
+        let mut tick = Tick::new();
+        for leg in legs(hedge, position_of(&positions, None), current_price, gap) {
+            let position = position_of(&positions, leg.position_side);
+            let pending = pending(&open_orders, &leg);
+            let quantity = if leg.closes {
+                // Closing orders never add up to more than the position.
+                let uncovered = position.abs() - pending.abs();
+                if uncovered <= EPSILON {
+                    continue;
+                }
+                quantity.min(uncovered)
+            } else {
+                if liquidation_near {
+                    tick.error(format!(
+                        "Skip {} order, {} is within {} of liquidation",
+                        leg.side, symbol, liquidation_gap
+                    ));
+                    continue;
+                }
+                let exposure = position + pending + signed(leg.side, quantity);
+                if exposure.abs() > max_position + EPSILON {
+                    tick.error(format!("Skip {} order, position on {} could reach {}", leg.side, symbol, exposure));
+                    continue;
+                }
+                quantity
+            };
 
-        if let Err(e) = self.crypto_client.place_order(
-            symbol.to_string(),
-            "SELL".to_string(),
-            "LIMIT".to_string(),
-            sell_price,
-            quantity,
-            None,
-        ) {
-            println!("Failed to place sell order: {}", e);
+            let mut request = OrderRequest::limit(symbol, leg.side, leg.price, quantity, "")
+                .time_in_force(&time_in_force);
+            request = match leg.position_side {
+                Some(position_side) => request.position_side(position_side),
+                None if leg.closes => request.reduce_only(),
+                None => request,
+            };
+            let request = match rules.normalize(&request) {
+                Ok(request) => request,
+                Err(e) => {
+                    tick.error(format!("Skip {} order: {}", leg.side, e));
+                    continue;
+                }
+            };
+            let request = match next_client_order_id(self.order_prefix()) {
+                Ok(client_order_id) => request.client_order_id(&client_order_id),
+                Err(e) => {
+                    tick.error(format!("Skip {} order, no client order id: {}", leg.side, e));
+                    continue;
+                }
+            };
+            match self.exchange.place_order(&request).await {
+                Ok(order) => tick.orders.push(order),
+                Err(e) => tick.error(format!("Failed to place {} order: {}", leg.side, e)),
+            }
         }
+        let wanted = self.protective_requests(&mut tick, &rules, &positions, hedge, stop_loss_pct, take_profit_pct);
+        self.sync_protection(&mut tick, &protective, wanted).await;
+        tick
     }
     
     fn name(&self) -> String {
@@ -128,17 +442,213 @@ impl Strategy for FutureGridStrategy {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crypto::mock_exchange::MockExchange;
     use std::collections::HashMap;
 
-    #[test]
-    fn test_future_grid_strategy() {
+    #[tokio::test]
+    async fn test_future_grid_strategy() {
         let mut parameters: HashMap<String, String> = HashMap::new();
         parameters.insert("gap".into(), "0.003".to_string());
         parameters.insert("quantity".into(), "400".to_string());
         parameters.insert("symbol".into(), "XAIUSDT".to_string());
         parameters.insert("leverage".into(), "10".to_string());
 
-        let strategy = FutureGridStrategy::new(parameters);
-        strategy.excute();
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("XAIUSDT", 0.5);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+        assert_eq!(exchange.open_orders("XAIUSDT").await.unwrap().len(), 2);
+    }
+
+    async fn open_buys(exchange: &MockExchange) -> usize {
+        exchange
+            .open_orders("BTCUSDT")
+            .await
+            .unwrap()
+            .iter()
+            .filter(|order| order.side == "BUY")
+            .count()
+    }
+
+    #[tokio::test]
+    async fn test_position_cap() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.01".to_string());
+        parameters.insert("quantity".into(), "1".to_string());
+        parameters.insert("symbol".into(), "BTCUSDT".to_string());
+        parameters.insert("max_position".into(), "2".to_string());
+        let parameters = FutureGridStrategy::validate(&parameters).unwrap();
+
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+        exchange.set_price("BTCUSDT", 98.0);
+        assert_eq!(exchange.positions("BTCUSDT").await.unwrap()[0].quantity, 1.0);
+
+        // Long 1 with no open buy: one more buy fits under the cap.
+        assert!(strategy.excute().await.errors.is_empty());
+        assert_eq!(open_buys(&exchange).await, 1);
+
+        // Long 1 with a buy resting: another one could take the position to 3.
+        exchange.set_price("BTCUSDT", 98.5);
+        let tick = strategy.excute().await;
+        assert_eq!(open_buys(&exchange).await, 1);
+        assert_eq!(tick.errors, vec!["Skip BUY order, position on BTCUSDT could reach 3".to_string()]);
+
+        // The sell from the first tick already covers the position.
+        let sells = exchange
+            .open_orders("BTCUSDT")
+            .await
+            .unwrap()
+            .iter()
+            .filter(|order| order.side == "SELL")
+            .count();
+        assert_eq!(sells, 1);
+    }
+
+    #[tokio::test]
+    async fn test_hedge_mode() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.01".to_string());
+        parameters.insert("quantity".into(), "1".to_string());
+        parameters.insert("symbol".into(), "BTCUSDT".to_string());
+        parameters.insert("position_mode".into(), "hedge".to_string());
+        let parameters = FutureGridStrategy::validate(&parameters).unwrap();
+
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        let tick = strategy.excute().await;
+        let sides: Vec<(String, String)> = tick
+            .orders
+            .iter()
+            .map(|order| (order.side.clone(), order.position_side.clone()))
+            .collect();
+        assert_eq!(
+            sides,
+            vec![("BUY".to_string(), "LONG".to_string()), ("SELL".to_string(), "SHORT".to_string())]
+        );
+
+        // The long opened at 99 is taken profit on by a sell on the long side, next to the short.
+        exchange.set_price("BTCUSDT", 98.0);
+        let tick = strategy.excute().await;
+        let take_profit = tick
+            .orders
+            .iter()
+            .find(|order| order.side == "SELL" && order.position_side == "LONG")
+            .unwrap();
+        assert_eq!(take_profit.orig_qty, 1.0);
+
+        exchange.set_price("BTCUSDT", 99.5);
+        let positions = exchange.positions("BTCUSDT").await.unwrap();
+        assert_eq!(position_of(&positions, Some("LONG")), 0.0);
+        assert_eq!(position_of(&positions, Some("SHORT")), -1.0);
+    }
+
+    #[tokio::test]
+    async fn test_prepare() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.01".to_string());
+        parameters.insert("quantity".into(), "1".to_string());
+        parameters.insert("symbol".into(), "BTCUSDT".to_string());
+        parameters.insert("leverage".into(), "10".to_string());
+        parameters.insert("position_mode".into(), "hedge".to_string());
+        let parameters = FutureGridStrategy::validate(&parameters).unwrap();
+
+        // Runs leave the account alone.
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+        assert_eq!(exchange.leverage("BTCUSDT"), None);
+        assert_eq!(exchange.hedge_mode(), None);
+
+        strategy.prepare().await.unwrap();
+        assert_eq!(exchange.leverage("BTCUSDT"), Some(10));
+        assert_eq!(exchange.hedge_mode(), Some(true));
+    }
+
+    #[tokio::test]
+    async fn test_liquidation_gap() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.01".to_string());
+        parameters.insert("quantity".into(), "1".to_string());
+        parameters.insert("symbol".into(), "BTCUSDT".to_string());
+        let parameters = FutureGridStrategy::validate(&parameters).unwrap();
+
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+        exchange.set_price("BTCUSDT", 98.0);
+        exchange.set_liquidation_price("BTCUSDT", 90.0);
+
+        let tick = strategy.excute().await;
+        assert_eq!(open_buys(&exchange).await, 0);
+        assert_eq!(exchange.open_orders("BTCUSDT").await.unwrap().len(), 1);
+        assert_eq!(tick.errors, vec!["Skip BUY order, BTCUSDT is within 0.1 of liquidation".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_protective_orders() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.01".to_string());
+        parameters.insert("quantity".into(), "1".to_string());
+        parameters.insert("symbol".into(), "BTCUSDT".to_string());
+        parameters.insert("stop_loss_pct".into(), "5".to_string());
+        parameters.insert("take_profit_pct".into(), "10".to_string());
+        let parameters = FutureGridStrategy::validate(&parameters).unwrap();
+
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCUSDT", 100.0);
+        let strategy = FutureGridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+        let protective = async |exchange: &MockExchange| -> Vec<(String, f64, f64)> {
+            let mut orders: Vec<(String, f64, f64)> = exchange
+                .open_orders("BTCUSDT")
+                .await
+                .unwrap()
+                .iter()
+                .filter(|order| is_protective(order))
+                .map(|order| (order.order_type.clone(), order.stop_price, order.orig_qty))
+                .collect();
+            orders.sort_by(|a, b| a.0.cmp(&b.0));
+            orders
+        };
+        assert!(protective(&exchange).await.is_empty());
+
+        // Long 1 from 99: stop 5% below, take profit 10% above.
+        exchange.set_price("BTCUSDT", 98.0);
+        strategy.excute().await;
+        let orders = protective(&exchange).await;
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].0, "STOP_MARKET");
+        assert!((orders[0].1 - 94.05).abs() < 1e-9);
+        assert_eq!(orders[0].2, 1.0);
+        assert!((orders[1].1 - 108.9).abs() < 1e-9);
+
+        // A second buy fills: the orders are resized and repriced, not duplicated.
+        exchange.set_price("BTCUSDT", 97.0);
+        strategy.excute().await;
+        let orders = protective(&exchange).await;
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders[0].2, 2.0);
+        assert!((orders[0].1 - (99.0 + 97.02) / 2.0 * 0.95).abs() < 1e-9);
+
+        // Unchanged position: the resting orders stay.
+        let protective_ids = async |exchange: &MockExchange| -> Vec<u64> {
+            exchange
+                .open_orders("BTCUSDT")
+                .await
+                .unwrap()
+                .iter()
+                .filter(|order| is_protective(order))
+                .map(|order| order.order_id)
+                .collect()
+        };
+        let ids = protective_ids(&exchange).await;
+        strategy.excute().await;
+        assert_eq!(protective_ids(&exchange).await, ids);
     }
 }
@@ -1,27 +1,173 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+use async_trait::async_trait;
 use serde_json::{Map, Value};
+use tokio::sync::Mutex;
 
-use crate::strategy::strategy::Strategy;
-use crate::crypto::crypto_client::CryptoClient;
-use crate::util::number_util::{get_precision, round};
+use crate::crypto::exchange::{is_closed_unfilled, Exchange, ExchangeOrder, OrderRequest};
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::strategy::param_schema::{ParamSchema, ParamSpec};
+use crate::strategy::strategy::{cancel_tagged_orders, next_client_order_id, require, Strategy, Tick};
 use crate::util::string_util::generate_random_id;
 
+#[derive(Debug, Clone, PartialEq)]
+struct GridOrder {
+    client_order_id: String,
+    side: String,
+    price: f64,
+    /// Ordered while it rests, executed once it left the book.
+    quantity: f64,
+}
+
+/// The orders a grid follows, and the fills whose counter order is not placed yet.
+#[derive(Default)]
+struct GridState {
+    orders: HashMap<String, GridOrder>,
+    fills: Vec<GridOrder>,
+}
+
+/// Params of a tick, parsed from the validated string map.
+struct GridParams {
+    symbol: String,
+    gap: f64,
+    quantity: f64,
+    stale_gap: f64,
+}
+
+impl GridParams {
+    fn parse(params: &HashMap<String, String>) -> Result<GridParams, anyhow::Error> {
+        let gap: f64 = require(params, "gap")?;
+        let stale_gap = match params.get("stale_gap") {
+            Some(_) => require(params, "stale_gap")?,
+            None => gap * 5.0,
+        };
+        Ok(GridParams {
+            symbol: require(params, "symbol")?,
+            gap,
+            quantity: require(params, "quantity")?,
+            stale_gap,
+        })
+    }
+}
+
 pub struct GridStrategy {
     id: String,
     name: String,
-    crypto_client: CryptoClient,
+    exchange: Arc<dyn Exchange>,
     params: HashMap<String, String>,
+    state: Arc<Mutex<GridState>>,
 }
 
 impl GridStrategy {
-    pub fn new(params: HashMap<String, String>) -> GridStrategy {
+    pub fn new(params: HashMap<String, String>, exchange: Arc<dyn Exchange>) -> GridStrategy {
         GridStrategy {
             id: generate_random_id(),
             name: String::from("Grid"),
-            crypto_client: CryptoClient::new(),
-            params: params,
+            exchange,
+            params,
+            state: Arc::new(Mutex::new(GridState::default())),
+        }
+    }
+
+    pub fn schema() -> ParamSchema {
+        ParamSchema::new(vec![
+            ParamSpec::string("symbol", "Spot trading pair, e.g. BTCFDUSD"),
+            ParamSpec::float("gap", "Distance between the grid orders and the price, as a fraction")
+                .range(Some(0.0001), Some(0.5)),
+            ParamSpec::float("quantity", "Base asset quantity of each order").range(Some(0.00000001), None),
+            ParamSpec::float("stale_gap", "Orders further than this fraction from the price are cancelled, defaults to 5 gaps")
+                .range(Some(0.0001), Some(1.0))
+                .optional(),
+        ])
+    }
+
+    pub fn validate(params: &HashMap<String, String>) -> Result<HashMap<String, String>, anyhow::Error> {
+        GridStrategy::schema().validate(params)
+    }
+
+    fn order_prefix(&self) -> &str {
+        self.id.get(..16).unwrap_or(&self.id)
+    }
+
+    /// Removes the tracked orders that left the book and returns the ones that executed anything,
+    /// with the executed quantity, lowest price first. A cancelled or expired order counts for
+    /// what it filled before; one that filled nothing is dropped, and the ones Binance cannot be
+    /// asked about stay tracked until the next tick.
+    async fn take_filled(
+        &self,
+        tick: &mut Tick,
+        symbol: &str,
+        orders: &mut HashMap<String, GridOrder>,
+        open_ids: &HashSet<&str>,
+    ) -> Vec<GridOrder> {
+        let gone: Vec<String> = orders
+            .keys()
+            .filter(|id| !open_ids.contains(id.as_str()))
+            .cloned()
+            .collect();
+        let mut filled = Vec::new();
+        for id in gone {
+            match self.exchange.query_order(symbol, &id).await {
+                Ok(order) if order.status == "FILLED" || is_closed_unfilled(&order.status) => {
+                    let Some(mut tracked) = orders.remove(&id) else {
+                        continue;
+                    };
+                    if order.executed_qty > 0.0 {
+                        tracked.quantity = order.executed_qty;
+                        filled.push(tracked);
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => tick.error(format!("Failed to look up order {}: {}", id, err)),
+            }
         }
+        filled.sort_by(|a, b| a.price.total_cmp(&b.price));
+        filled
+    }
+
+    /// Places `request` and follows it. Returns whether it was placed.
+    async fn place(
+        &self,
+        tick: &mut Tick,
+        orders: &mut HashMap<String, GridOrder>,
+        rules: &SymbolRules,
+        request: OrderRequest,
+    ) -> bool {
+        let request = match rules.normalize(&request) {
+            Ok(request) => request,
+            Err(err) => {
+                tick.error(format!("Skip {} order at {}: {}", request.side, request.price, err));
+                return false;
+            }
+        };
+        let request = match next_client_order_id(self.order_prefix()) {
+            Ok(client_order_id) => request.client_order_id(&client_order_id),
+            Err(err) => {
+                tick.error(format!("Skip {} order at {}, no client order id: {}", request.side, request.price, err));
+                return false;
+            }
+        };
+        match self.exchange.place_order(&request).await {
+            Ok(order) => tick.orders.push(order),
+            Err(err) => {
+                tick.error(format!(
+                    "Failed to place {} order at {}: {}",
+                    request.side, request.price, err
+                ));
+                return false;
+            }
+        }
+        orders.insert(
+            request.client_order_id.clone(),
+            GridOrder {
+                client_order_id: request.client_order_id,
+                side: request.side,
+                price: request.price,
+                quantity: request.quantity,
+            },
+        );
+        true
     }
 }
 
@@ -30,17 +176,57 @@ impl Clone for GridStrategy {
         GridStrategy {
             id: self.id.clone(),
             name: self.name.clone(),
-            crypto_client: self.crypto_client.clone(),
+            exchange: self.exchange.clone(),
             params: self.params.clone(),
+            state: self.state.clone(),
         }
     }
 }
 
+/// Follows an open order placed with the strategy's tag that it does not track yet, as after a
+/// restart or an update of the robot.
+fn adopt(orders: &mut HashMap<String, GridOrder>, open_orders: &[ExchangeOrder]) {
+    for order in open_orders {
+        orders
+            .entry(order.client_order_id.clone())
+            .or_insert_with(|| GridOrder {
+                client_order_id: order.client_order_id.clone(),
+                side: order.side.clone(),
+                price: order.price,
+                quantity: order.orig_qty,
+            });
+    }
+}
+
+/// A filled buy is closed by a sell one gap above it, and a filled sell by a buy one gap below.
+fn counter_order(filled: &GridOrder, gap: f64) -> (String, f64) {
+    match filled.side.as_str() {
+        "BUY" => ("SELL".to_string(), filled.price * (1.0 + gap)),
+        _ => ("BUY".to_string(), filled.price * (1.0 - gap)),
+    }
+}
+
+fn is_stale(price: f64, current_price: f64, stale_gap: f64) -> bool {
+    (price - current_price).abs() / current_price > stale_gap
+}
+
+#[async_trait]
 impl Strategy for GridStrategy {
     fn clone_box(&self) -> Box<dyn Strategy> {
         Box::new((*self).clone())
     }
 
+    fn set_order_tag(&mut self, tag: &str) {
+        self.id = tag.to_string();
+    }
+
+    async fn cancel_orders(&self) -> Tick {
+        match require::<String>(&self.params, "symbol") {
+            Ok(symbol) => cancel_tagged_orders(self.exchange.as_ref(), &symbol, self.order_prefix()).await,
+            Err(err) => Tick::skipped(format!("Failed to cancel orders: {}", err)),
+        }
+    }
+
     fn params(&self) -> HashMap<String, String> {
         self.params.clone()
     }
@@ -52,7 +238,7 @@ impl Strategy for GridStrategy {
     fn to_json(&self) -> Map<String, Value> {
         let mut map = Map::new();
         map.insert("name".to_string(), Value::String(self.name.clone()));
-        
+
         let mut params_map = Map::new();
         for (key, value) in &self.params {
             params_map.insert(key.clone(), Value::String(value.clone()));
@@ -61,61 +247,260 @@ impl Strategy for GridStrategy {
         map
     }
 
-    fn excute(&self) {
-        let gap: f64 = self
-            .params
-            .get("gap")
-            .unwrap()
-            .parse()
-            .expect("Not a valid f64");
-        let quantity: f32 = self
-            .params
-            .get("quantity")
-            .unwrap()
-            .parse()
-            .expect("Not a valid f32");
-        let symbol = self.params.get("symbol").unwrap();
-        let current_price = self.crypto_client.current_price(symbol.as_str()).price;
-
-        self.crypto_client.limit_buy(
-            symbol.as_str(),
+    async fn excute(&self) -> Tick {
+        let GridParams {
+            symbol,
+            gap,
             quantity,
-            round(
-                (current_price * (1.0 - gap) * 100000000.0).round() / 100000000.0,
-                get_precision(current_price),
-            ),
-            self.id.as_str()
-        );
-        self.crypto_client.limit_sell(
-            symbol.as_str(),
-            quantity,
-            round(
-                (current_price * (1.0 + gap) * 100000000.0).round() / 100000000.0,
-                get_precision(current_price),
-            ),
-            self.id.as_str()
-        );
+            stale_gap,
+        } = match GridParams::parse(&self.params) {
+            Ok(params) => params,
+            Err(err) => return Tick::skipped(format!("Skip grid tick: {}", err)),
+        };
+        let symbol = &symbol;
+        let current_price = match self.exchange.current_price(symbol).await {
+            Ok(price) => price,
+            Err(err) => return Tick::skipped(format!("Skip grid tick, no price for {}: {}", symbol, err)),
+        };
+        let rules = match self.exchange.symbol_rules(symbol).await {
+            Ok(rules) => rules,
+            Err(err) => return Tick::skipped(format!("Skip grid tick, no symbol rules for {}: {}", symbol, err)),
+        };
+
+        let open_orders: Vec<ExchangeOrder> = match self.exchange.open_orders(symbol).await {
+            Ok(orders) => orders
+                .into_iter()
+                .filter(|order| order.client_order_id.starts_with(self.order_prefix()))
+                .collect(),
+            Err(err) => return Tick::skipped(format!("Skip grid tick, failed to get open orders: {}", err)),
+        };
+        let open_ids: HashSet<&str> = open_orders
+            .iter()
+            .map(|order| order.client_order_id.as_str())
+            .collect();
+
+        let mut tick = Tick::new();
+        let mut state = self.state.lock().await;
+        let GridState { orders, fills } = &mut *state;
+        fills.extend(self.take_filled(&mut tick, symbol, orders, &open_ids).await);
+        adopt(orders, &open_orders);
+
+        for order in open_orders
+            .iter()
+            .filter(|order| is_stale(order.price, current_price, stale_gap))
+        {
+            match self.exchange.cancel_order(symbol, order.order_id).await {
+                Ok(_) => {
+                    orders.remove(&order.client_order_id);
+                }
+                Err(err) => tick.error(format!("Failed to cancel order {}: {}", order.order_id, err)),
+            }
+        }
+
+        // A fill stays pending until its counter order is placed, however many ticks it takes.
+        if orders.is_empty() && fills.is_empty() {
+            for (side, price) in [("BUY", current_price * (1.0 - gap)), ("SELL", current_price * (1.0 + gap))] {
+                let request = OrderRequest::limit(symbol, side, price, quantity, "");
+                self.place(&mut tick, orders, &rules, request).await;
+            }
+        }
+        fills.sort_by(|a, b| a.price.total_cmp(&b.price));
+        for fill in std::mem::take(fills) {
+            let (side, price) = counter_order(&fill, gap);
+            let request = OrderRequest::limit(symbol, &side, price, fill.quantity, "");
+            if !self.place(&mut tick, orders, &rules, request).await {
+                fills.push(fill);
+            }
+        }
+        tick
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashMap;
+    use std::collections::{HashMap, HashSet};
+    use std::sync::Arc;
 
-    use crate::strategy::strategy::Strategy;
+    use crate::crypto::exchange::{Exchange, OrderRequest};
+    use crate::crypto::mock_exchange::MockExchange;
+    use crate::crypto::symbol_rules::SymbolRules;
+    use crate::strategy::strategy::{Strategy, Tick};
 
-    use super::GridStrategy;
+    use super::{counter_order, is_stale, GridOrder, GridStrategy};
 
-    #[test]
-    fn test_excute() {
+    fn grid_order(id: &str, side: &str, price: f64) -> GridOrder {
+        GridOrder {
+            client_order_id: id.to_string(),
+            side: side.to_string(),
+            price,
+            quantity: 1.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_excute() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+
+        parameters.insert("gap".into(), "0.005".to_string());
+        parameters.insert("quantity".into(), "10.0".to_string());
+        parameters.insert("symbol".into(), "ATOMFDUSD".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("ATOMFDUSD", 10.0);
+        let strategy = GridStrategy::new(parameters, exchange.clone());
+        let tick = strategy.excute().await;
+        assert_eq!(tick.orders.len(), 2);
+        assert!(tick.errors.is_empty());
+        assert_eq!(exchange.open_orders("ATOMFDUSD").await.unwrap().len(), 2);
+
+        exchange.set_price("ATOMFDUSD", 9.94);
+        strategy.excute().await;
+        let open_orders = exchange.open_orders("ATOMFDUSD").await.unwrap();
+        assert_eq!(open_orders.len(), 2);
+        assert!(open_orders.iter().all(|order| order.side == "SELL"));
+    }
+
+    #[tokio::test]
+    async fn test_take_filled() {
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("ATOMFDUSD", 10.0);
+        for (id, side, price) in [("a", "BUY", 9.9), ("b", "SELL", 10.1), ("c", "BUY", 9.8), ("e", "BUY", 9.6)] {
+            exchange
+                .place_order(&OrderRequest::limit("ATOMFDUSD", side, price, 1.0, id))
+                .await
+                .unwrap();
+        }
+        exchange.set_price("ATOMFDUSD", 9.85);
+        let c = exchange.query_order("ATOMFDUSD", "c").await.unwrap();
+        exchange.cancel_order("ATOMFDUSD", c.order_id).await.unwrap();
+        let e = exchange.query_order("ATOMFDUSD", "e").await.unwrap();
+        exchange.cancel_order("ATOMFDUSD", e.order_id).await.unwrap();
+        exchange.set_executed("e", 0.4);
+
+        let strategy = GridStrategy::new(HashMap::new(), exchange.clone());
+        let mut orders = HashMap::new();
+        for order in [
+            grid_order("a", "BUY", 9.9),
+            grid_order("b", "SELL", 10.1),
+            grid_order("c", "BUY", 9.8),
+            grid_order("d", "BUY", 9.7),
+            grid_order("e", "BUY", 9.6),
+        ] {
+            orders.insert(order.client_order_id.clone(), order);
+        }
+        let open_ids: HashSet<&str> = ["b"].into_iter().collect();
+
+        // "a" filled, "e" was cancelled after filling 0.4, "c" before filling anything, and "d"
+        // cannot be found, so it is kept for later.
+        let mut tick = Tick::new();
+        let filled = strategy.take_filled(&mut tick, "ATOMFDUSD", &mut orders, &open_ids).await;
+        let partial = GridOrder {
+            quantity: 0.4,
+            ..grid_order("e", "BUY", 9.6)
+        };
+        assert_eq!(filled, vec![partial, grid_order("a", "BUY", 9.9)]);
+        let mut tracked: Vec<&String> = orders.keys().collect();
+        tracked.sort();
+        assert_eq!(tracked, vec!["b", "d"]);
+        assert_eq!(tick.errors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_counter_order() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.005".to_string());
+        parameters.insert("quantity".into(), "10.0".to_string());
+        parameters.insert("symbol".into(), "ATOMFDUSD".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("ATOMFDUSD", 10.0);
+        let strategy = GridStrategy::new(parameters, exchange.clone());
+        strategy.excute().await;
+
+        // The buy fills while a minimum notional refuses its counter sell.
+        let mut rules = SymbolRules::unrestricted("ATOMFDUSD");
+        rules.min_notional = 1000.0;
+        exchange.set_rules(rules);
+        exchange.set_price("ATOMFDUSD", 9.94);
+        let tick = strategy.excute().await;
+        assert!(tick.orders.is_empty());
+        assert_eq!(tick.errors.len(), 1);
+
+        // Once it fits, the sell is placed on the next tick, and only once.
+        exchange.set_rules(SymbolRules::unrestricted("ATOMFDUSD"));
+        let tick = strategy.excute().await;
+        assert_eq!(tick.orders.len(), 1);
+        assert_eq!((tick.orders[0].side.as_str(), tick.orders[0].orig_qty), ("SELL", 10.0));
+        assert!(strategy.excute().await.orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_adopt_open_orders() {
         let mut parameters: HashMap<String, String> = HashMap::new();
+        parameters.insert("gap".into(), "0.005".to_string());
+        parameters.insert("quantity".into(), "10.0".to_string());
+        parameters.insert("symbol".into(), "ATOMFDUSD".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("ATOMFDUSD", 10.0);
+        let mut strategy = GridStrategy::new(parameters.clone(), exchange.clone());
+        strategy.set_order_tag("0123456789abcdef");
+        strategy.excute().await;
+
+        // A fresh instance with the same tag, as after a restart, follows the resting orders.
+        let mut restarted = GridStrategy::new(parameters, exchange.clone());
+        restarted.set_order_tag("0123456789abcdef");
+        assert!(restarted.excute().await.orders.is_empty());
+        exchange.set_price("ATOMFDUSD", 9.94);
+        let tick = restarted.excute().await;
+        assert_eq!(tick.orders.len(), 1);
+        assert_eq!(tick.orders[0].side, "SELL");
+        assert_eq!(exchange.open_orders("ATOMFDUSD").await.unwrap().len(), 2);
+    }
 
+    #[tokio::test]
+    async fn test_cancel_orders() {
+        let mut parameters: HashMap<String, String> = HashMap::new();
         parameters.insert("gap".into(), "0.005".to_string());
         parameters.insert("quantity".into(), "10.0".to_string());
         parameters.insert("symbol".into(), "ATOMFDUSD".to_string());
-        let strategy = GridStrategy::new(parameters);
-        strategy.excute();
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("ATOMFDUSD", 10.0);
+        exchange
+            .place_order(&OrderRequest::limit("ATOMFDUSD", "BUY", 9.0, 1.0, "manual"))
+            .await
+            .unwrap();
+        let mut strategy = GridStrategy::new(parameters, exchange.clone());
+        strategy.set_order_tag("0123456789abcdef");
+        strategy.excute().await;
+
+        let tick = strategy.cancel_orders().await;
+        assert_eq!(tick.orders.len(), 2);
+        assert!(tick.errors.is_empty());
+        let open_orders = exchange.open_orders("ATOMFDUSD").await.unwrap();
+        assert_eq!(open_orders.len(), 1);
+        assert_eq!(open_orders[0].client_order_id, "manual");
+    }
+
+    #[test]
+    fn test_short_order_tag() {
+        let mut strategy = GridStrategy::new(HashMap::new(), Arc::new(MockExchange::new()));
+        strategy.set_order_tag("abc");
+        assert_eq!(strategy.order_prefix(), "abc");
     }
 
-    
+    #[test]
+    fn test_counter_order() {
+        let (side, price) = counter_order(&grid_order("a", "BUY", 10.0), 0.01);
+        assert_eq!(side, "SELL");
+        assert!((price - 10.1).abs() < 1e-9);
+
+        let (side, price) = counter_order(&grid_order("b", "SELL", 10.0), 0.01);
+        assert_eq!(side, "BUY");
+        assert!((price - 9.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_is_stale() {
+        assert!(!is_stale(10.4, 10.0, 0.05));
+        assert!(is_stale(10.6, 10.0, 0.05));
+        assert!(is_stale(9.4, 10.0, 0.05));
+    }
 }
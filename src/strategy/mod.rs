@@ -1,3 +1,22 @@
+use salvo::{handler, prelude::Json, Response};
+use serde_json::{json, Value};
+
+use self::strategy::StrategyFactory;
+
 pub mod strategy;
+pub mod param_schema;
 pub mod grid_strategy;
-pub mod future_grid_strategy;
\ No newline at end of file
+pub mod future_grid_strategy;
+pub mod range_grid_strategy;
+
+/// Every strategy label with the params it accepts.
+#[handler]
+pub fn get_strategies(res: &mut Response) {
+    let factory = StrategyFactory::new();
+    let strategies: Vec<Value> = factory
+        .labels()
+        .into_iter()
+        .map(|label| json!({ "label": label, "params": factory.schema(label).map(|schema| schema.params) }))
+        .collect();
+    res.render(Json(strategies));
+}
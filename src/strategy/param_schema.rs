@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use anyhow::bail;
+use serde::Serialize;
+
+/// Params every strategy accepts on top of its own schema.
+const COMMON_PARAMS: [&str; 1] = ["account"];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "type", content = "values")]
+pub enum ParamType {
+    Float,
+    Integer,
+    String,
+    Enum(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub param_type: ParamType,
+    pub required: bool,
+    pub default: Option<String>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub description: String,
+}
+
+impl ParamSpec {
+    pub fn float(name: &str, description: &str) -> ParamSpec {
+        ParamSpec::new(name, ParamType::Float, description)
+    }
+
+    pub fn integer(name: &str, description: &str) -> ParamSpec {
+        ParamSpec::new(name, ParamType::Integer, description)
+    }
+
+    pub fn string(name: &str, description: &str) -> ParamSpec {
+        ParamSpec::new(name, ParamType::String, description)
+    }
+
+    pub fn one_of(name: &str, values: &[&str], description: &str) -> ParamSpec {
+        let values = values.iter().map(|value| value.to_string()).collect();
+        ParamSpec::new(name, ParamType::Enum(values), description)
+    }
+
+    fn new(name: &str, param_type: ParamType, description: &str) -> ParamSpec {
+        ParamSpec {
+            name: name.to_string(),
+            param_type,
+            required: true,
+            default: None,
+            min: None,
+            max: None,
+            description: description.to_string(),
+        }
+    }
+
+    /// Makes the param optional; `default` is filled in when it is missing.
+    pub fn default(mut self, default: &str) -> ParamSpec {
+        self.required = false;
+        self.default = Some(default.to_string());
+        self
+    }
+
+    /// Makes the param optional with no default; the strategy derives a value itself.
+    pub fn optional(mut self) -> ParamSpec {
+        self.required = false;
+        self
+    }
+
+    pub fn range(mut self, min: Option<f64>, max: Option<f64>) -> ParamSpec {
+        self.min = min;
+        self.max = max;
+        self
+    }
+
+    fn check(&self, value: &str) -> Result<(), anyhow::Error> {
+        let number = match &self.param_type {
+            ParamType::Float => match value.parse::<f64>() {
+                Ok(number) if number.is_finite() => Some(number),
+                _ => bail!("invalid param {}: {} is not a number", self.name, value),
+            },
+            ParamType::Integer => match value.parse::<i64>() {
+                Ok(number) => Some(number as f64),
+                Err(_) => bail!("invalid param {}: {} is not an integer", self.name, value),
+            },
+            ParamType::String => {
+                if value.trim().is_empty() {
+                    bail!("invalid param {}: must not be empty", self.name);
+                }
+                None
+            }
+            ParamType::Enum(values) => {
+                if !values.iter().any(|allowed| allowed == value) {
+                    bail!("invalid param {}: {}, expected {}", self.name, value, values.join("|"));
+                }
+                None
+            }
+        };
+        if let Some(number) = number {
+            if let Some(min) = self.min {
+                if number < min {
+                    bail!("invalid param {}: {} is below {}", self.name, value, min);
+                }
+            }
+            if let Some(max) = self.max {
+                if number > max {
+                    bail!("invalid param {}: {} is above {}", self.name, value, max);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The params a strategy takes, checked before a robot is scheduled.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamSchema {
+    pub params: Vec<ParamSpec>,
+}
+
+impl ParamSchema {
+    pub fn new(params: Vec<ParamSpec>) -> ParamSchema {
+        ParamSchema { params }
+    }
+
+    /// Checks every param against its spec, rejects unknown ones, and returns `params` with
+    /// defaults filled in.
+    pub fn validate(
+        &self,
+        params: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        for key in params.keys() {
+            if !COMMON_PARAMS.contains(&key.as_str()) && !self.params.iter().any(|spec| spec.name == *key) {
+                bail!("unknown param {}", key);
+            }
+        }
+        let mut validated = params.clone();
+        for spec in self.params.iter() {
+            match params.get(&spec.name) {
+                Some(value) => spec.check(value)?,
+                None => match &spec.default {
+                    Some(default) => {
+                        validated.insert(spec.name.clone(), default.clone());
+                    }
+                    None if spec.required => bail!("missing param {}", spec.name),
+                    None => {}
+                },
+            }
+        }
+        Ok(validated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{ParamSchema, ParamSpec};
+
+    fn schema() -> ParamSchema {
+        ParamSchema::new(vec![
+            ParamSpec::string("symbol", "Trading pair"),
+            ParamSpec::float("gap", "Grid gap").range(Some(0.0001), Some(0.5)),
+            ParamSpec::integer("leverage", "Leverage").range(Some(1.0), Some(125.0)).default("1"),
+            ParamSpec::one_of("mode", &["arithmetic", "geometric"], "Spacing").default("arithmetic"),
+        ])
+    }
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_fills_defaults() {
+        let validated = schema()
+            .validate(&params(&[("symbol", "BTCUSDT"), ("gap", "0.01"), ("account", "main")]))
+            .unwrap();
+        assert_eq!(validated.get("leverage").unwrap(), "1");
+        assert_eq!(validated.get("mode").unwrap(), "arithmetic");
+        assert_eq!(validated.get("account").unwrap(), "main");
+    }
+
+    #[test]
+    fn test_validate_rejects() {
+        let schema = schema();
+        let err = schema.validate(&params(&[("symbol", "BTCUSDT")])).unwrap_err();
+        assert_eq!(err.to_string(), "missing param gap");
+
+        let err = schema
+            .validate(&params(&[("symbol", "BTCUSDT"), ("gap", "0.01"), ("gapp", "1")]))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "unknown param gapp");
+
+        assert!(schema.validate(&params(&[("symbol", "BTCUSDT"), ("gap", "1")])).is_err());
+        assert!(schema.validate(&params(&[("symbol", "BTCUSDT"), ("gap", "abc")])).is_err());
+        assert!(schema
+            .validate(&params(&[("symbol", "BTCUSDT"), ("gap", "0.01"), ("leverage", "2.5")]))
+            .is_err());
+        assert!(schema
+            .validate(&params(&[("symbol", "BTCUSDT"), ("gap", "0.01"), ("mode", "linear")]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let spec = ParamSpec::one_of("mode", &["arithmetic", "geometric"], "Spacing").default("arithmetic");
+        let json = serde_json::to_value(&spec).unwrap();
+        assert_eq!(json["type"], "enum");
+        assert_eq!(json["values"][1], "geometric");
+        assert_eq!(json["required"], false);
+
+        let json = serde_json::to_value(ParamSpec::float("gap", "Grid gap")).unwrap();
+        assert_eq!(json["type"], "float");
+        assert_eq!(json["required"], true);
+    }
+}
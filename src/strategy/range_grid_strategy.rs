@@ -0,0 +1,595 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tokio::sync::Mutex;
+
+use crate::client::db_client::DBClient;
+use crate::crypto::exchange::{is_closed_unfilled, Exchange, ExchangeOrder, OrderRequest};
+use crate::crypto::symbol_rules::SymbolRules;
+use crate::strategy::param_schema::{ParamSchema, ParamSpec};
+use crate::strategy::strategy::{cancel_tagged_orders, next_client_order_id, require, Strategy, Tick};
+use crate::util::string_util::generate_random_id;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum GridMode {
+    Arithmetic,
+    Geometric,
+}
+
+impl GridMode {
+    fn parse(mode: &str) -> GridMode {
+        match mode {
+            "geometric" => GridMode::Geometric,
+            _ => GridMode::Arithmetic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct LevelOrder {
+    side: String,
+    level: usize,
+}
+
+/// The side wanted at each rung of the ladder, and the live orders placed for them. Stored
+/// after every tick with the prices it was laid out on, see `RangeGridStrategy::restore`.
+#[derive(Default, Serialize, Deserialize)]
+struct Ladder {
+    levels: Vec<f64>,
+    rungs: BTreeMap<usize, String>,
+    orders: HashMap<String, LevelOrder>,
+}
+
+impl Ladder {
+    /// Rungs without a live order.
+    fn missing(&self) -> Vec<(usize, String)> {
+        self.rungs
+            .iter()
+            .filter(|(level, _)| !self.orders.values().any(|order| order.level == **level))
+            .map(|(level, side)| (*level, side.clone()))
+            .collect()
+    }
+}
+
+struct RangeGridParams {
+    symbol: String,
+    lower_price: f64,
+    upper_price: f64,
+    grid_count: usize,
+    quantity: f64,
+    mode: GridMode,
+}
+
+impl RangeGridParams {
+    fn parse(params: &HashMap<String, String>) -> Result<RangeGridParams, anyhow::Error> {
+        Ok(RangeGridParams {
+            symbol: require(params, "symbol")?,
+            lower_price: require(params, "lower_price")?,
+            upper_price: require(params, "upper_price")?,
+            grid_count: require(params, "grid_count")?,
+            quantity: require(params, "quantity")?,
+            mode: GridMode::parse(params.get("mode").map(|mode| mode.as_str()).unwrap_or("arithmetic")),
+        })
+    }
+}
+
+/// Fixed-range grid: lays out a ladder of limit orders between `lower_price` and `upper_price`
+/// once, then replaces every filled rung with the opposite order one level away. The ladder is
+/// stored under the strategy's tag, so a restart or an update picks it up where it was, settles
+/// the orders filled meanwhile, and places again a rung left without an order.
+pub struct RangeGridStrategy {
+    id: String,
+    name: String,
+    exchange: Arc<dyn Exchange>,
+    params: HashMap<String, String>,
+    ladder: Arc<Mutex<Ladder>>,
+}
+
+impl RangeGridStrategy {
+    pub fn new(params: HashMap<String, String>, exchange: Arc<dyn Exchange>) -> RangeGridStrategy {
+        RangeGridStrategy {
+            id: generate_random_id(),
+            name: String::from("RangeGrid"),
+            exchange,
+            params,
+            ladder: Arc::new(Mutex::new(Ladder::default())),
+        }
+    }
+
+    pub fn schema() -> ParamSchema {
+        ParamSchema::new(vec![
+            ParamSpec::string("symbol", "Spot trading pair, e.g. BTCFDUSD"),
+            ParamSpec::float("lower_price", "Lowest rung of the ladder").range(Some(0.00000001), None),
+            ParamSpec::float("upper_price", "Highest rung of the ladder").range(Some(0.00000001), None),
+            ParamSpec::integer("grid_count", "Number of intervals between the rungs").range(Some(1.0), Some(500.0)),
+            ParamSpec::float("quantity", "Base asset quantity of each order").range(Some(0.00000001), None),
+            ParamSpec::one_of("mode", &["arithmetic", "geometric"], "Equal price steps or equal ratios")
+                .default("arithmetic"),
+        ])
+    }
+
+    pub fn validate(params: &HashMap<String, String>) -> Result<HashMap<String, String>, anyhow::Error> {
+        let params = RangeGridStrategy::schema().validate(params)?;
+        let range = RangeGridParams::parse(&params)?;
+        if range.lower_price <= 0.0 || range.lower_price >= range.upper_price {
+            anyhow::bail!("lower_price must be positive and below upper_price");
+        }
+        Ok(params)
+    }
+
+    fn order_prefix(&self) -> &str {
+        self.id.get(..16).unwrap_or(&self.id)
+    }
+
+    /// Ladder stored for the strategy's tag, if it was laid out on `levels`. Without one, the
+    /// tagged open orders are adopted as they are, and only a grid with neither starts from
+    /// `initial_ladder`: rebuilding rungs from the price would put back the one a fill vacated.
+    fn restore(&self, levels: &[f64], open_orders: &[ExchangeOrder], current_price: f64) -> Ladder {
+        let stored = DBClient::get_instance()
+            .lock()
+            .unwrap()
+            .load_grid_ladder(self.order_prefix());
+        let stored: Option<Ladder> = match stored {
+            Ok(stored) => stored.and_then(|ladder| serde_json::from_str(&ladder).ok()),
+            Err(err) => {
+                println!("Failed to load the ladder of {}: {}", self.order_prefix(), err);
+                None
+            }
+        };
+        if let Some(ladder) = stored.filter(|ladder| ladder.levels == levels) {
+            return ladder;
+        }
+        let mut ladder = Ladder {
+            levels: levels.to_vec(),
+            ..Ladder::default()
+        };
+        if open_orders.is_empty() {
+            ladder.rungs = initial_ladder(levels, current_price)
+                .into_iter()
+                .map(|(side, level)| (level, side))
+                .collect();
+        }
+        ladder
+    }
+
+    fn save(&self, ladder: &Ladder) {
+        let saved = serde_json::to_string(ladder).map_err(anyhow::Error::from).and_then(|json| {
+            DBClient::get_instance()
+                .lock()
+                .unwrap()
+                .save_grid_ladder(self.order_prefix(), &json)
+                .map_err(anyhow::Error::from)
+        });
+        if let Err(err) = saved {
+            println!("Failed to save the ladder of {}: {}", self.order_prefix(), err);
+        }
+    }
+
+    /// Looks up the ladder's orders that are no longer open. A filled order hands its rung over
+    /// to the counter order; one cancelled or expired elsewhere leaves its rung to be placed
+    /// again. Orders that cannot be looked up stay on the ladder until the next tick.
+    async fn settle(
+        &self,
+        tick: &mut Tick,
+        symbol: &str,
+        ladder: &mut Ladder,
+        open_ids: &HashSet<&str>,
+        grid_count: usize,
+    ) {
+        let gone: Vec<String> = ladder
+            .orders
+            .keys()
+            .filter(|id| !open_ids.contains(id.as_str()))
+            .cloned()
+            .collect();
+        for id in gone {
+            match self.exchange.query_order(symbol, &id).await {
+                Ok(order) if order.status == "FILLED" => {
+                    let Some(filled) = ladder.orders.remove(&id) else {
+                        continue;
+                    };
+                    ladder.rungs.remove(&filled.level);
+                    if let Some((side, level)) = counter_level(&filled, grid_count) {
+                        ladder.rungs.insert(level, side);
+                    }
+                }
+                Ok(order) if is_closed_unfilled(&order.status) => {
+                    ladder.orders.remove(&id);
+                }
+                Ok(_) => {}
+                Err(err) => tick.error(format!("Failed to look up order {}: {}", id, err)),
+            }
+        }
+    }
+
+    /// Places the rung `level` of the ladder, snapped to the symbol's filters.
+    async fn place(
+        &self,
+        tick: &mut Tick,
+        ladder: &mut Ladder,
+        rules: &SymbolRules,
+        level: usize,
+        request: OrderRequest,
+    ) {
+        let request = match rules.normalize(&request) {
+            Ok(request) => request,
+            Err(err) => {
+                tick.error(format!(
+                    "Skip {} order at level {} ({}): {}",
+                    request.side, level, request.price, err
+                ));
+                return;
+            }
+        };
+        let request = match next_client_order_id(self.order_prefix()) {
+            Ok(client_order_id) => request.client_order_id(&client_order_id),
+            Err(err) => {
+                tick.error(format!(
+                    "Skip {} order at level {} ({}), no client order id: {}",
+                    request.side, level, request.price, err
+                ));
+                return;
+            }
+        };
+        match self.exchange.place_order(&request).await {
+            Ok(order) => tick.orders.push(order),
+            Err(err) => {
+                tick.error(format!(
+                    "Failed to place {} order at level {} ({}): {}",
+                    request.side, level, request.price, err
+                ));
+                return;
+            }
+        }
+        ladder.orders.insert(
+            request.client_order_id,
+            LevelOrder {
+                side: request.side,
+                level,
+            },
+        );
+    }
+}
+
+impl Clone for RangeGridStrategy {
+    fn clone(&self) -> Self {
+        RangeGridStrategy {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            exchange: self.exchange.clone(),
+            params: self.params.clone(),
+            ladder: self.ladder.clone(),
+        }
+    }
+}
+
+/// Returns the `grid_count + 1` prices of the ladder, from `lower` to `upper` inclusive.
+fn grid_levels(lower: f64, upper: f64, grid_count: usize, mode: GridMode) -> Vec<f64> {
+    (0..=grid_count)
+        .map(|i| {
+            let step = i as f64 / grid_count as f64;
+            match mode {
+                GridMode::Arithmetic => lower + (upper - lower) * step,
+                GridMode::Geometric => lower * (upper / lower).powf(step),
+            }
+        })
+        .collect()
+}
+
+/// Buys below and sells above the current price, leaving the level closest to it empty.
+fn initial_ladder(levels: &[f64], current_price: f64) -> Vec<(String, usize)> {
+    let nearest = nearest_level(levels, current_price);
+    levels
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| Some(*i) != nearest)
+        .map(|(i, price)| {
+            let side = if *price < current_price { "BUY" } else { "SELL" };
+            (side.to_string(), i)
+        })
+        .collect()
+}
+
+/// Puts the open orders the ladder does not track yet on the rung nearest their price, as after a
+/// restart or an update of the robot.
+fn adopt(ladder: &mut Ladder, open_orders: &[ExchangeOrder], levels: &[f64]) {
+    for order in open_orders {
+        if ladder.orders.contains_key(&order.client_order_id) {
+            continue;
+        }
+        let Some(level) = nearest_level(levels, order.price) else {
+            continue;
+        };
+        ladder.rungs.insert(level, order.side.clone());
+        ladder.orders.insert(
+            order.client_order_id.clone(),
+            LevelOrder {
+                side: order.side.clone(),
+                level,
+            },
+        );
+    }
+}
+
+fn nearest_level(levels: &[f64], price: f64) -> Option<usize> {
+    levels
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| (*a - price).abs().total_cmp(&(*b - price).abs()))
+        .map(|(i, _)| i)
+}
+
+/// A filled buy is closed by a sell one level up, a filled sell by a buy one level down.
+fn counter_level(filled: &LevelOrder, grid_count: usize) -> Option<(String, usize)> {
+    match filled.side.as_str() {
+        "BUY" if filled.level < grid_count => Some(("SELL".to_string(), filled.level + 1)),
+        "SELL" if filled.level > 0 => Some(("BUY".to_string(), filled.level - 1)),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl Strategy for RangeGridStrategy {
+    fn clone_box(&self) -> Box<dyn Strategy> {
+        Box::new((*self).clone())
+    }
+
+    fn set_order_tag(&mut self, tag: &str) {
+        self.id = tag.to_string();
+    }
+
+    async fn cancel_orders(&self) -> Tick {
+        match require::<String>(&self.params, "symbol") {
+            Ok(symbol) => cancel_tagged_orders(self.exchange.as_ref(), &symbol, self.order_prefix()).await,
+            Err(err) => Tick::skipped(format!("Failed to cancel orders: {}", err)),
+        }
+    }
+
+    fn params(&self) -> HashMap<String, String> {
+        self.params.clone()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn to_json(&self) -> Map<String, Value> {
+        let mut map = Map::new();
+        map.insert("name".to_string(), Value::String(self.name.clone()));
+
+        let mut params_map = Map::new();
+        for (key, value) in &self.params {
+            params_map.insert(key.clone(), Value::String(value.clone()));
+        }
+        map.insert("params".to_string(), Value::Object(params_map));
+        map
+    }
+
+    async fn excute(&self) -> Tick {
+        let RangeGridParams {
+            symbol,
+            lower_price,
+            upper_price,
+            grid_count,
+            quantity,
+            mode,
+        } = match RangeGridParams::parse(&self.params) {
+            Ok(params) => params,
+            Err(err) => return Tick::skipped(format!("Skip range grid tick: {}", err)),
+        };
+        let symbol = &symbol;
+
+        let current_price = match self.exchange.current_price(symbol).await {
+            Ok(price) => price,
+            Err(err) => return Tick::skipped(format!("Skip range grid tick, no price for {}: {}", symbol, err)),
+        };
+        let rules = match self.exchange.symbol_rules(symbol).await {
+            Ok(rules) => rules,
+            Err(err) => return Tick::skipped(format!("Skip range grid tick, no symbol rules for {}: {}", symbol, err)),
+        };
+        let levels = grid_levels(lower_price, upper_price, grid_count, mode);
+
+        let open_orders: Vec<ExchangeOrder> = match self.exchange.open_orders(symbol).await {
+            Ok(orders) => orders
+                .into_iter()
+                .filter(|order| order.client_order_id.starts_with(self.order_prefix()))
+                .collect(),
+            Err(err) => return Tick::skipped(format!("Skip range grid tick, failed to get open orders: {}", err)),
+        };
+        let open_ids: HashSet<&str> = open_orders
+            .iter()
+            .map(|order| order.client_order_id.as_str())
+            .collect();
+
+        let mut tick = Tick::new();
+        let mut ladder = self.ladder.lock().await;
+        if ladder.levels != levels {
+            *ladder = self.restore(&levels, &open_orders, current_price);
+        }
+        adopt(&mut ladder, &open_orders, &levels);
+        self.settle(&mut tick, symbol, &mut ladder, &open_ids, grid_count).await;
+
+        for (level, side) in ladder.missing() {
+            let request = OrderRequest::limit(
+                symbol,
+                &side,
+                levels[level],
+                quantity,
+                "",
+            );
+            self.place(&mut tick, &mut ladder, &rules, level, request).await;
+        }
+        self.save(&ladder);
+        tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use crate::crypto::exchange::Exchange;
+    use crate::crypto::mock_exchange::MockExchange;
+    use crate::strategy::strategy::Strategy;
+
+    use super::{counter_level, grid_levels, initial_ladder, GridMode, LevelOrder, RangeGridStrategy};
+
+    #[test]
+    fn test_arithmetic_levels() {
+        let levels = grid_levels(10.0, 20.0, 4, GridMode::Arithmetic);
+        assert_eq!(levels, vec![10.0, 12.5, 15.0, 17.5, 20.0]);
+    }
+
+    #[test]
+    fn test_geometric_levels() {
+        let levels = grid_levels(10.0, 40.0, 2, GridMode::Geometric);
+        assert_eq!(levels.len(), 3);
+        assert!((levels[1] - 20.0).abs() < 1e-9);
+        assert!((levels[2] - 40.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_initial_ladder() {
+        let levels = grid_levels(10.0, 20.0, 4, GridMode::Arithmetic);
+        let ladder = initial_ladder(&levels, 14.0);
+        assert_eq!(
+            ladder,
+            vec![
+                ("BUY".to_string(), 0),
+                ("BUY".to_string(), 1),
+                ("SELL".to_string(), 3),
+                ("SELL".to_string(), 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_counter_level() {
+        let buy = LevelOrder {
+            side: "BUY".to_string(),
+            level: 1,
+        };
+        assert_eq!(counter_level(&buy, 4), Some(("SELL".to_string(), 2)));
+
+        let sell = LevelOrder {
+            side: "SELL".to_string(),
+            level: 0,
+        };
+        assert_eq!(counter_level(&sell, 4), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("symbol".into(), "BTCFDUSD".to_string());
+        params.insert("quantity".into(), "0.001".to_string());
+        params.insert("lower_price".into(), "60000".to_string());
+        params.insert("upper_price".into(), "70000".to_string());
+        params.insert("grid_count".into(), "10".to_string());
+        assert!(RangeGridStrategy::validate(&params).is_ok());
+
+        params.insert("upper_price".into(), "50000".to_string());
+        assert!(RangeGridStrategy::validate(&params).is_err());
+
+        params.insert("upper_price".into(), "abc".to_string());
+        let err = RangeGridStrategy::validate(&params).unwrap_err();
+        assert_eq!(err.to_string(), "invalid param upper_price: abc is not a number");
+    }
+
+    #[tokio::test]
+    async fn test_same_rung_after_fill() {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("symbol".into(), "BTCFDUSD".to_string());
+        params.insert("quantity".into(), "1".to_string());
+        params.insert("lower_price".into(), "10".to_string());
+        params.insert("upper_price".into(), "20".to_string());
+        params.insert("grid_count".into(), "4".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCFDUSD", 14.0);
+        let strategy = RangeGridStrategy::new(params, exchange.clone());
+        let first = strategy.excute().await;
+        let first_buy = first.orders.iter().find(|order| order.price == 12.5).unwrap().clone();
+
+        // The buy at 12.5 fills, its sell at 15 fills, and the buy at 12.5 is placed again.
+        exchange.set_price("BTCFDUSD", 12.0);
+        strategy.excute().await;
+        exchange.set_price("BTCFDUSD", 15.5);
+        let again = strategy.excute().await;
+        assert_eq!(again.orders.len(), 1);
+        let second_buy = &again.orders[0];
+        assert_eq!((second_buy.side.as_str(), second_buy.price), ("BUY", 12.5));
+        assert_ne!(second_buy.client_order_id, first_buy.client_order_id);
+        assert_eq!(second_buy.status, "NEW");
+        assert!(exchange
+            .open_orders("BTCFDUSD")
+            .await
+            .unwrap()
+            .iter()
+            .any(|order| order.client_order_id == second_buy.client_order_id));
+        let first_buy = exchange.query_order("BTCFDUSD", &first_buy.client_order_id).await.unwrap();
+        assert_eq!(first_buy.status, "FILLED");
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_ladder() {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("symbol".into(), "BTCFDUSD".to_string());
+        params.insert("quantity".into(), "1".to_string());
+        params.insert("lower_price".into(), "10".to_string());
+        params.insert("upper_price".into(), "20".to_string());
+        params.insert("grid_count".into(), "4".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCFDUSD", 14.0);
+        let mut strategy = RangeGridStrategy::new(params.clone(), exchange.clone());
+        strategy.set_order_tag("0123456789abcdef");
+        assert_eq!(strategy.excute().await.orders.len(), 4);
+
+        // While the robot is down, the sell at 17.5 is cancelled by hand.
+        let open_orders = exchange.open_orders("BTCFDUSD").await.unwrap();
+        let sell = open_orders.iter().find(|order| order.price == 17.5).unwrap();
+        exchange.cancel_order("BTCFDUSD", sell.order_id).await.unwrap();
+
+        // A fresh instance with the same tag keeps the three live rungs and only places the missing one.
+        let mut restarted = RangeGridStrategy::new(params, exchange.clone());
+        restarted.set_order_tag("0123456789abcdef");
+        let tick = restarted.excute().await;
+        assert_eq!(tick.orders.len(), 1);
+        assert_eq!((tick.orders[0].side.as_str(), tick.orders[0].price), ("SELL", 17.5));
+        assert_eq!(exchange.open_orders("BTCFDUSD").await.unwrap().len(), 4);
+        assert!(restarted.excute().await.orders.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restart_after_fill() {
+        let mut params: HashMap<String, String> = HashMap::new();
+        params.insert("symbol".into(), "BTCFDUSD".to_string());
+        params.insert("quantity".into(), "1".to_string());
+        params.insert("lower_price".into(), "10".to_string());
+        params.insert("upper_price".into(), "20".to_string());
+        params.insert("grid_count".into(), "4".to_string());
+        let exchange = Arc::new(MockExchange::new());
+        exchange.set_price("BTCFDUSD", 14.0);
+        let mut strategy = RangeGridStrategy::new(params.clone(), exchange.clone());
+        strategy.set_order_tag("fedcba9876543210");
+        strategy.excute().await;
+
+        // The buy at 12.5 fills and is closed by a sell at 15, vacating the rung at 12.5.
+        exchange.set_price("BTCFDUSD", 12.0);
+        let tick = strategy.excute().await;
+        assert_eq!(tick.orders.len(), 1);
+        assert_eq!((tick.orders[0].side.as_str(), tick.orders[0].price), ("SELL", 15.0));
+
+        // Back at 14, a restarted instance must not buy at 12.5 a second time.
+        exchange.set_price("BTCFDUSD", 14.0);
+        let mut restarted = RangeGridStrategy::new(params, exchange.clone());
+        restarted.set_order_tag("fedcba9876543210");
+        assert!(restarted.excute().await.orders.is_empty());
+        let open_orders = exchange.open_orders("BTCFDUSD").await.unwrap();
+        assert_eq!(open_orders.len(), 4);
+        assert!(!open_orders.iter().any(|order| order.price == 12.5));
+    }
+}
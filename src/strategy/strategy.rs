@@ -1,19 +1,129 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
 use serde_json::{Map, Value};
 
+use crate::client::db_client::DBClient;
+use crate::config::app_config::DEFAULT_ACCOUNT;
+use crate::crypto::crypto_client::CryptoClient;
+use crate::crypto::exchange::{Exchange, ExchangeOrder};
+use crate::crypto::futures_client::FutureClient;
+use crate::crypto::paper_book::robot_book;
+use crate::crypto::risk_guard::RiskGuard;
+use crate::crypto::trading_mode::TradingMode;
+use crate::util::time_util::now_timestamp;
 use crate::strategy::grid_strategy::GridStrategy;
 use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
 
 use super::future_grid_strategy::FutureGridStrategy;
+use super::param_schema::ParamSchema;
+use super::range_grid_strategy::RangeGridStrategy;
 
-pub trait Strategy {
+/// What one run of a strategy did: the orders it placed and what went wrong.
+#[derive(Debug, Clone, Default)]
+pub struct Tick {
+    pub orders: Vec<ExchangeOrder>,
+    pub errors: Vec<String>,
+}
+
+impl Tick {
+    pub fn new() -> Tick {
+        Tick::default()
+    }
+
+    /// Logs `message` and records it against this run.
+    pub fn error(&mut self, message: String) {
+        println!("{}", message);
+        self.errors.push(message);
+    }
+
+    /// A run that stopped before placing anything.
+    pub fn skipped(message: String) -> Tick {
+        let mut tick = Tick::new();
+        tick.error(message);
+        tick
+    }
+}
+
+#[async_trait]
+pub trait Strategy: Send + Sync {
     fn name(&self) -> String;
     fn params(&self) -> HashMap<String, String>;
-    fn excute(&self);
+    async fn excute(&self) -> Tick;
+    /// Prefix of the client order ids of the strategy's orders, see `order_tag`.
+    fn set_order_tag(&mut self, tag: &str);
+    /// Cancels the strategy's open orders, before another strategy takes over the robot.
+    async fn cancel_orders(&self) -> Tick;
+    /// Sets the account up for the strategy, such as its leverage, once when the robot is
+    /// created, restored or updated rather than on every run.
+    async fn prepare(&self) -> Result<(), anyhow::Error> {
+        Ok(())
+    }
     fn clone_box(&self) -> Box<dyn Strategy>;
     fn to_json(&self) -> Map<String, Value>;
     
 }
 
+/// Name of the Binance account profile a strategy trades on, taken from its `account` param.
+pub fn account_of(params: &HashMap<String, String>) -> String {
+    params
+        .get("account")
+        .cloned()
+        .unwrap_or(DEFAULT_ACCOUNT.to_string())
+}
+
+/// Client order id prefix of a robot's orders, which lets the ledger attribute exchange orders
+/// to the robot: the first 16 hex digits of its id.
+pub fn order_tag(robot_id: &str) -> String {
+    robot_id.chars().filter(|c| *c != '-').take(16).collect()
+}
+
+/// Client order id of a new order: `tag` and the next number of the tag's sequence, stored before
+/// the id is returned. The sequence starts from the clock, so ids stay unique across restarts and
+/// even without the database they were counted in. Only a retry of the same attempt, inside
+/// `place_once`, sends an id twice.
+pub fn next_client_order_id(tag: &str) -> Result<String, anyhow::Error> {
+    let sequence = DBClient::get_instance()
+        .lock()
+        .unwrap()
+        .next_order_sequence(tag, now_timestamp())?;
+    Ok(format!("{}-{}", tag, sequence))
+}
+
+/// Cancels the open orders of `symbol` whose client order id starts with `tag`, and returns them
+/// as the tick's orders.
+pub async fn cancel_tagged_orders(exchange: &dyn Exchange, symbol: &str, tag: &str) -> Tick {
+    let open_orders = match exchange.open_orders(symbol).await {
+        Ok(orders) => orders,
+        Err(err) => return Tick::skipped(format!("Failed to get open orders of {}: {}", symbol, err)),
+    };
+    let mut tick = Tick::new();
+    for order in open_orders
+        .iter()
+        .filter(|order| order.client_order_id.starts_with(tag))
+    {
+        match exchange.cancel_order(symbol, order.order_id).await {
+            Ok(order) => tick.orders.push(order),
+            Err(err) => tick.error(format!("Failed to cancel order {}: {}", order.order_id, err)),
+        }
+    }
+    tick
+}
+
+/// Parses the required param `key`, naming it in the error when it is missing or malformed.
+pub fn require<T: FromStr>(params: &HashMap<String, String>, key: &str) -> Result<T, anyhow::Error> {
+    let value = params
+        .get(key)
+        .ok_or_else(|| anyhow!("missing param {}", key))?;
+    match value.parse() {
+        Ok(value) => Ok(value),
+        Err(_) => bail!("invalid param {}: {}", key, value),
+    }
+}
+
+const STRATEGY_LABELS: [&str; 3] = ["Grid", "FutureGrid", "RangeGrid"];
+
 pub struct StrategyFactory;
 
 impl StrategyFactory {
@@ -23,15 +133,81 @@ impl StrategyFactory {
 
     
 
+    /// Builds a robot's strategy trading on the Binance venue its label needs, in the given mode.
+    /// Its orders carry the robot's order tag and go through the risk guard.
     pub fn create_strategy(
+        &self,
+        robot_id: &str,
+        label: &str,
+        params: HashMap<String, String>,
+        mode: TradingMode,
+    ) -> Box<dyn Strategy> {
+        let account = account_of(&params);
+        // A paper robot keeps its book across updates and restarts.
+        let exchange: Arc<dyn Exchange> = match (self.is_futures(label), mode) {
+            (true, TradingMode::Paper) => Arc::new(FutureClient::new_paper(&account, robot_book(robot_id))),
+            (true, _) => Arc::new(FutureClient::new_with_mode(&account, mode)),
+            (false, TradingMode::Paper) => Arc::new(CryptoClient::new_paper(&account, robot_book(robot_id))),
+            (false, _) => Arc::new(CryptoClient::new_with_mode(&account, mode)),
+        };
+        let exchange = Arc::new(RiskGuard::new(exchange, robot_id, mode));
+        let mut strategy = self.create_strategy_with_exchange(label, params, exchange);
+        strategy.set_order_tag(&order_tag(robot_id));
+        strategy
+    }
+
+    /// Whether the strategy trades USDⓈ-M futures rather than spot.
+    pub fn is_futures(&self, label: &str) -> bool {
+        label == "FutureGrid"
+    }
+
+    /// `one_way` or `hedge` for a futures strategy, which puts its whole account in that mode.
+    pub fn position_mode(&self, label: &str, params: &HashMap<String, String>) -> Option<String> {
+        if !self.is_futures(label) {
+            return None;
+        }
+        Some(params.get("position_mode").cloned().unwrap_or("one_way".to_string()))
+    }
+
+    pub fn labels(&self) -> Vec<&'static str> {
+        STRATEGY_LABELS.to_vec()
+    }
+
+    pub fn schema(&self, label: &str) -> Option<ParamSchema> {
+        match label {
+            "Grid" => Some(GridStrategy::schema()),
+            "FutureGrid" => Some(FutureGridStrategy::schema()),
+            "RangeGrid" => Some(RangeGridStrategy::schema()),
+            _ => None,
+        }
+    }
+
+    /// Checks `params` against the schema of the strategy behind `label`, before any robot uses
+    /// them, and returns them with defaults filled in.
+    pub fn validate(
+        &self,
+        label: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        match label {
+            "Grid" => GridStrategy::validate(params),
+            "FutureGrid" => FutureGridStrategy::validate(params),
+            "RangeGrid" => RangeGridStrategy::validate(params),
+            _ => bail!("unknown strategy {}, expected {}", label, STRATEGY_LABELS.join("|")),
+        }
+    }
+
+    pub fn create_strategy_with_exchange(
         &self,
         label: &str,
         params: HashMap<String, String>,
+        exchange: Arc<dyn Exchange>,
     ) -> Box<dyn Strategy> {
         match label {
-            "Grid" => Box::new(GridStrategy::new(params)),
-            "FutureGrid" => Box::new(FutureGridStrategy::new(params)),
-            _ => Box::new(GridStrategy::new(params)),
+            "Grid" => Box::new(GridStrategy::new(params, exchange)),
+            "FutureGrid" => Box::new(FutureGridStrategy::new(params, exchange)),
+            "RangeGrid" => Box::new(RangeGridStrategy::new(params, exchange)),
+            _ => Box::new(GridStrategy::new(params, exchange)),
         }
     }
 }
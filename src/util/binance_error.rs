@@ -1,5 +1,6 @@
+use std::fmt;
+
 use serde::Deserialize;
-use error_chain::error_chain;
 
 #[derive(Debug, Deserialize)]
 pub struct BinanceContentError {
@@ -7,3 +8,216 @@ pub struct BinanceContentError {
     pub msg: String,
 }
 
+pub type Result<T> = std::result::Result<T, BinanceError>;
+
+/// Error returned by the exchange clients. Binance error payloads are classified by their
+/// `code` so callers can tell a throttled or rejected request from a broken connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BinanceError {
+    /// Request weight or order count limit hit (HTTP 429/418, codes -1003 and -1015).
+    RateLimited {
+        code: i16,
+        msg: String,
+        retry_after: Option<u64>,
+    },
+    InsufficientBalance { code: i16, msg: String },
+    /// Price, quantity or notional rejected by a symbol filter.
+    FilterViolation { code: i16, msg: String },
+    /// Missing, invalid or under-privileged API key, or a bad signature.
+    Auth { code: i16, msg: String },
+    /// The request never got a response.
+    Network(String),
+    /// No response within the request timeout; the request may still have been executed.
+    Timeout(String),
+    /// Binance answered 5xx; the outcome of the request is unknown.
+    Server { status: u16, msg: String },
+    /// Any other error payload from Binance.
+    Api { code: i16, msg: String },
+    /// The response body could not be decoded.
+    Decode(String),
+}
+
+impl BinanceError {
+    pub fn from_content(content: BinanceContentError) -> BinanceError {
+        let BinanceContentError { code, msg } = content;
+        match code {
+            -1003 | -1015 => BinanceError::RateLimited {
+                code,
+                msg,
+                retry_after: None,
+            },
+            -2018 | -2019 => BinanceError::InsufficientBalance { code, msg },
+            -2010 if msg.to_lowercase().contains("insufficient balance") => {
+                BinanceError::InsufficientBalance { code, msg }
+            }
+            -1013 | -1111 | -4003 | -4014 | -4023 | -4164 => {
+                BinanceError::FilterViolation { code, msg }
+            }
+            -1002 | -1022 | -2014 | -2015 => BinanceError::Auth { code, msg },
+            _ => BinanceError::Api { code, msg },
+        }
+    }
+
+    /// Classifies a non-200 response from its status, `Retry-After` header and body.
+    pub fn from_response(status: u16, retry_after: Option<u64>, body: &str) -> BinanceError {
+        let content = serde_json::from_str::<BinanceContentError>(body).ok();
+        match (status, content) {
+            (429 | 418, content) => {
+                let (code, msg) = content
+                    .map(|content| (content.code, content.msg))
+                    .unwrap_or((0, body.to_string()));
+                BinanceError::RateLimited {
+                    code,
+                    msg,
+                    retry_after,
+                }
+            }
+            (_, Some(content)) => BinanceError::from_content(content),
+            (401 | 403, None) => BinanceError::Auth {
+                code: 0,
+                msg: body.to_string(),
+            },
+            (500..=599, None) => BinanceError::Server {
+                status,
+                msg: body.to_string(),
+            },
+            (_, None) => BinanceError::Api {
+                code: 0,
+                msg: format!("HTTP {}: {}", status, body),
+            },
+        }
+    }
+
+    /// Whether sending the request again may succeed. For an order, it also means the order may
+    /// have been executed even though no answer came back.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            BinanceError::Network(_) | BinanceError::Timeout(_) | BinanceError::Server { .. }
+        )
+    }
+
+    /// Binance rejected a new order because an open order already has its client order id.
+    pub fn is_duplicate_order(&self) -> bool {
+        match self {
+            BinanceError::Api { code: -4116, .. } => true,
+            BinanceError::Api { code: -2010, msg } => msg.to_lowercase().contains("duplicate"),
+            _ => false,
+        }
+    }
+
+    /// The request's timestamp was ahead of the server or older than its `recvWindow`.
+    pub fn is_timestamp_rejected(&self) -> bool {
+        matches!(self, BinanceError::Api { code: -1021, .. })
+    }
+
+    pub fn is_unknown_order(&self) -> bool {
+        matches!(self, BinanceError::Api { code: -2013, .. })
+    }
+
+    /// What Binance answers when an order id or client order id is not known.
+    pub fn no_such_order(id: &str) -> BinanceError {
+        BinanceError::Api {
+            code: -2013,
+            msg: format!("Order does not exist: {}", id),
+        }
+    }
+}
+
+impl fmt::Display for BinanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BinanceError::RateLimited { code, msg, .. } => write!(f, "rate limited ({}): {}", code, msg),
+            BinanceError::InsufficientBalance { code, msg } => {
+                write!(f, "insufficient balance ({}): {}", code, msg)
+            }
+            BinanceError::FilterViolation { code, msg } => write!(f, "filter violation ({}): {}", code, msg),
+            BinanceError::Auth { code, msg } => write!(f, "authentication failed ({}): {}", code, msg),
+            BinanceError::Network(msg) => write!(f, "network error: {}", msg),
+            BinanceError::Timeout(msg) => write!(f, "request timed out: {}", msg),
+            BinanceError::Server { status, msg } => write!(f, "server error ({}): {}", status, msg),
+            BinanceError::Api { code, msg } => write!(f, "binance error ({}): {}", code, msg),
+            BinanceError::Decode(msg) => write!(f, "failed to decode response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BinanceError {}
+
+impl From<reqwest::Error> for BinanceError {
+    fn from(err: reqwest::Error) -> Self {
+        BinanceError::from(&err)
+    }
+}
+
+impl From<&reqwest::Error> for BinanceError {
+    fn from(err: &reqwest::Error) -> Self {
+        if err.is_decode() {
+            BinanceError::Decode(err.to_string())
+        } else if err.is_timeout() {
+            BinanceError::Timeout(err.to_string())
+        } else {
+            BinanceError::Network(err.to_string())
+        }
+    }
+}
+
+impl From<serde_json::Error> for BinanceError {
+    fn from(err: serde_json::Error) -> Self {
+        BinanceError::Decode(err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BinanceError;
+
+    #[test]
+    fn test_from_response() {
+        let error = BinanceError::from_response(
+            400,
+            None,
+            r#"{"code":-2010,"msg":"Account has insufficient balance for requested action."}"#,
+        );
+        assert!(matches!(error, BinanceError::InsufficientBalance { code: -2010, .. }));
+
+        let error = BinanceError::from_response(
+            400,
+            None,
+            r#"{"code":-1013,"msg":"Filter failure: PRICE_FILTER"}"#,
+        );
+        assert!(matches!(error, BinanceError::FilterViolation { code: -1013, .. }));
+
+        let error = BinanceError::from_response(401, None, r#"{"code":-2015,"msg":"Invalid API-key"}"#);
+        assert!(matches!(error, BinanceError::Auth { code: -2015, .. }));
+
+        let error = BinanceError::from_response(429, Some(30), r#"{"code":-1003,"msg":"Too many requests"}"#);
+        assert_eq!(
+            error,
+            BinanceError::RateLimited {
+                code: -1003,
+                msg: "Too many requests".to_string(),
+                retry_after: Some(30),
+            }
+        );
+
+        let error = BinanceError::from_response(503, None, "Service Unavailable");
+        assert!(matches!(error, BinanceError::Server { status: 503, .. }));
+
+        let error = BinanceError::from_response(400, None, r#"{"code":-2013,"msg":"Order does not exist."}"#);
+        assert!(matches!(error, BinanceError::Api { code: -2013, .. }));
+        assert!(error.is_unknown_order());
+
+        let error = BinanceError::from_response(400, None, r#"{"code":-2010,"msg":"Duplicate order sent."}"#);
+        assert!(error.is_duplicate_order());
+        assert!(!error.is_transient());
+        assert!(BinanceError::Timeout("operation timed out".to_string()).is_transient());
+
+        let error = BinanceError::from_response(
+            400,
+            None,
+            r#"{"code":-1021,"msg":"Timestamp for this request is outside of the recvWindow."}"#,
+        );
+        assert!(error.is_timestamp_rejected());
+    }
+}
@@ -19,20 +19,6 @@ pub fn get_random_number() -> u32 {
     thread_rng().gen_range(1..=1_000_000)
 }
 
-pub fn smart_quantity(order_size: f64, price: f64) -> f64 {
-    let mut precision = 0;
-    if price >= 800.0 {
-        precision = 3;
-    } else if price >= 100.0 {
-        precision = 2;
-    } else if price >= 20.0 {
-        precision = 1;
-    } else {
-        precision = 0;
-    }
-    round(order_size / price, precision)
-}
-
 #[cfg(test)]
 mod tests {
     use crate::util::number_util::{get_precision, get_random_number, round};
@@ -4,6 +4,10 @@ pub fn hours_ago_timestamp(hours: i64) -> i64 {
     (Utc::now().timestamp() - hours * 3600) * 1000
 }
 
+pub fn now_timestamp() -> i64 {
+    Utc::now().timestamp_millis()
+}
+
 pub fn minutes_ago_timestamp(minutes: i64) -> i64 {
     (Utc::now().timestamp() - minutes * 60) * 1000
 }